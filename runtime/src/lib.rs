@@ -0,0 +1,1087 @@
+//! Runtime support for Pulse programs compiled to Rust with
+//! `pulse build --target rust`. The generated code only builds values, binds
+//! variables and calls into here; every operator follows the interpreter's
+//! `operators.rs` so a program prints the same whichever way it is run.
+
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Lists and objects are shared by reference. `frozen` is set by the
+// `freeze` builtin and checked before any mutation.
+#[derive(Debug, Default)]
+pub struct Shared<T> {
+    pub data: RefCell<T>,
+    pub frozen: Cell<bool>,
+}
+
+impl<T> Shared<T> {
+    pub fn new(data: T) -> Rc<Self> {
+        Rc::new(Shared {
+            data: RefCell::new(data),
+            frozen: Cell::new(false),
+        })
+    }
+}
+
+impl<T: PartialEq> PartialEq for Shared<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
+
+pub type List = Rc<Shared<Vec<Value>>>;
+pub type Object = Rc<Shared<Map>>;
+
+// Whole numbers within 64 bits are `Int` keys, whether they were `Int`s or
+// `Num`s, as in the interpreter. Other numeric keys are stored by bit
+// pattern so they hash the way its `OrderedFloat` keys do.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Str(String),
+    Int(i64),
+    Num(u64),
+}
+
+impl Key {
+    pub fn num(n: f64) -> Key {
+        if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(&n) {
+            Key::Int(n as i64)
+        } else {
+            Key::Num(n.to_bits())
+        }
+    }
+}
+
+// 2^63, the first float past the `i64`s
+const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+
+// An object's entries in insertion order, with an index to look keys up.
+// The interpreter uses `IndexMap`; this crate has no dependencies.
+#[derive(Clone, Debug, Default)]
+pub struct Map {
+    entries: Vec<(Key, Value)>,
+    index: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.index.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    // A new key goes last; an existing one keeps its place
+    pub fn insert(&mut self, key: Key, value: Value) {
+        match self.index.get(&key) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    // Keeps the order of the other entries
+    pub fn remove(&mut self, key: &Key) -> Option<Value> {
+        let i = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self.index.get_mut(key).unwrap() -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.entries.iter().map(|(_, v)| v)
+    }
+}
+
+impl Extend<(Key, Value)> for Map {
+    fn extend<I: IntoIterator<Item = (Key, Value)>>(&mut self, entries: I) {
+        entries.into_iter().for_each(|(k, v)| self.insert(k, v));
+    }
+}
+
+impl FromIterator<(Key, Value)> for Map {
+    fn from_iter<I: IntoIterator<Item = (Key, Value)>>(entries: I) -> Self {
+        let mut map = Map::default();
+        map.extend(entries);
+        map
+    }
+}
+
+// Equal objects have the same entries, in any order
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries.len() == other.entries.len()
+            && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Debug)]
+pub struct Error(pub String);
+
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Error(msg)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(msg: &str) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+pub type Native = Box<dyn Fn(&[Value]) -> Result<Value>>;
+
+/// A compiled Pulse function: its declared type and the closure itself.
+pub struct Func {
+    pub ty: Type,
+    pub call: Native,
+}
+
+#[derive(Clone)]
+pub enum Value {
+    Undefined,
+    Int(i64),
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    Vec(List),
+    Obj(Object),
+    Fn(Rc<Func>),
+}
+
+// `Int`s and `Num`s are equal when they are the same number. Functions are
+// only equal to themselves.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Undefined, Value::Undefined) => true,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Num(a), Value::Num(b)) => a == b,
+            (Value::Int(a), Value::Num(b)) | (Value::Num(b), Value::Int(a)) => {
+                compare(*a, *b) == Some(Ordering::Equal)
+            }
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Vec(a), Value::Vec(b)) => a == b,
+            (Value::Obj(a), Value::Obj(b)) => a == b,
+            (Value::Fn(a), Value::Fn(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(x) => write!(f, "{}", x),
+            Value::Num(x) => write!(f, "{}", x),
+            Value::Str(x) => write!(f, "{}", x),
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Vec(_) | Value::Obj(_) => show(self, f, &mut Vec::new()),
+            Value::Undefined => write!(f, "undefined"),
+            Value::Fn(_) => write!(f, "(Function () => {{}})"),
+        }
+    }
+}
+
+// Lists and objects print like their literals, strings inside them quoted;
+// `seen` holds the ones `value` is inside of, which print as `[...]` or
+// `{...}` where they come round again.
+fn show(value: &Value, f: &mut fmt::Formatter, seen: &mut Vec<usize>) -> fmt::Result {
+    match value {
+        Value::Str(s) if !seen.is_empty() => write!(f, "\"{}\"", s),
+        Value::Vec(list) => {
+            let at = Rc::as_ptr(list) as usize;
+            if seen.contains(&at) {
+                return write!(f, "[...]");
+            }
+            seen.push(at);
+            write!(f, "[")?;
+            for (i, item) in list.data.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                show(item, f, seen)?;
+            }
+            seen.pop();
+            write!(f, "]")
+        }
+        Value::Obj(obj) => {
+            let at = Rc::as_ptr(obj) as usize;
+            if seen.contains(&at) {
+                return write!(f, "{{...}}");
+            }
+            seen.push(at);
+            write!(f, "{{")?;
+            for (i, (key, value)) in obj.data.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                match key {
+                    Key::Str(s) => write!(f, "\"{}\"", s)?,
+                    Key::Int(n) => write!(f, "{}", n)?,
+                    Key::Num(bits) => write!(f, "{}", f64::from_bits(*bits))?,
+                }
+                write!(f, ": ")?;
+                show(value, f, seen)?;
+            }
+            seen.pop();
+            write!(f, "}}")
+        }
+        value => write!(f, "{}", value),
+    }
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Undefined => "Undefined",
+            Value::Int(_) => "Int",
+            Value::Num(_) => "Num",
+            Value::Str(_) => "Str",
+            Value::Bool(_) => "Bool",
+            Value::Vec(_) => "Vec",
+            Value::Obj(_) => "Obj",
+            Value::Fn(_) => "Function",
+        }
+    }
+
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Undefined => false,
+            Value::Fn(_) => true,
+            Value::Bool(b) => *b,
+            Value::Int(n) => *n != 0,
+            Value::Num(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+            Value::Vec(v) => !v.data.borrow().is_empty(),
+            Value::Obj(o) => !o.data.borrow().is_empty(),
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Num(a), Value::Num(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Num(b)) => compare(*a, *b),
+            (Value::Num(a), Value::Int(b)) => compare(*b, *a).map(Ordering::reverse),
+            (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+            (Value::Undefined, Value::Undefined) => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+
+// How an `Int` compares with a `Num`, without rounding either
+fn compare(a: i64, b: f64) -> Option<Ordering> {
+    if b.is_nan() {
+        None
+    } else if b >= LIMIT {
+        Some(Ordering::Less)
+    } else if b < -LIMIT {
+        Some(Ordering::Greater)
+    } else {
+        match a.cmp(&(b.trunc() as i64)) {
+            Ordering::Equal => 0.0.partial_cmp(&b.fract()),
+            order => Some(order),
+        }
+    }
+}
+
+// Type annotations, checked where the interpreter checks them: when a
+// binding is declared or assigned, on entry to a function and on return.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Any,
+    Never,
+    Undefined,
+    Num,
+    Str,
+    Bool,
+    Obj,
+    List(Box<Type>),
+    Fn(Vec<Type>, Box<Type>),
+    Union(Vec<Type>),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Never => write!(f, "never"),
+            Type::Undefined => write!(f, "undefined"),
+            Type::Num => write!(f, "num"),
+            Type::Str => write!(f, "str"),
+            Type::Bool => write!(f, "bool"),
+            Type::Obj => write!(f, "obj"),
+            Type::List(item) => write!(f, "list<{}>", item),
+            Type::Fn(params, ret) => {
+                let params: Vec<_> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), ret)
+            }
+            Type::Union(types) => {
+                let types: Vec<_> = types.iter().map(|t| t.to_string()).collect();
+                write!(f, "{}", types.join(" | "))
+            }
+        }
+    }
+}
+
+impl Type {
+    pub fn of(value: &Value) -> Type {
+        match value {
+            Value::Undefined => Type::Undefined,
+            Value::Int(_) | Value::Num(_) => Type::Num,
+            Value::Str(_) => Type::Str,
+            Value::Bool(_) => Type::Bool,
+            Value::Obj(_) => Type::Obj,
+            Value::Vec(list) => {
+                let items = list.data.borrow().iter().map(Type::of).collect();
+                Type::List(Box::new(Type::union(items)))
+            }
+            Value::Fn(func) => func.ty.clone(),
+        }
+    }
+
+    fn union(types: Vec<Type>) -> Type {
+        let mut members: Vec<Type> = Vec::new();
+        for ty in types {
+            let flat = match ty {
+                Type::Union(inner) => inner,
+                ty => vec![ty],
+            };
+            for ty in flat {
+                match ty {
+                    Type::Any => return Type::Any,
+                    Type::Never => {}
+                    ty if !members.contains(&ty) => members.push(ty),
+                    _ => {}
+                }
+            }
+        }
+        match members.len() {
+            0 => Type::Any,
+            1 => members.pop().unwrap(),
+            _ => Type::Union(members),
+        }
+    }
+
+    fn admits(&self, value: &Value) -> bool {
+        match (self, value) {
+            (Type::Any, _) => true,
+            (Type::Union(types), value) => types.iter().any(|t| t.admits(value)),
+            (Type::Undefined, Value::Undefined)
+            | (Type::Num, Value::Int(_) | Value::Num(_))
+            | (Type::Str, Value::Str(_))
+            | (Type::Bool, Value::Bool(_))
+            | (Type::Obj, Value::Obj(_))
+            | (Type::Fn(..), Value::Fn(_)) => true,
+            (Type::List(item), Value::Vec(list)) => {
+                list.data.borrow().iter().all(|v| item.admits(v))
+            }
+            _ => false,
+        }
+    }
+}
+
+enum Boundary<'a> {
+    Binding(&'a str),
+    Param(&'a str),
+    Return,
+}
+
+fn check(ty: Option<&Type>, value: &Value, boundary: Boundary) -> Result<()> {
+    let Some(expected) = ty else { return Ok(()) };
+    if expected.admits(value) {
+        return Ok(());
+    }
+    let found = Type::of(value);
+    Err(match boundary {
+        Boundary::Binding(name) => format!(
+            "Type mismatch: '{}' is declared as {}, found {}.",
+            name, expected, found
+        ),
+        Boundary::Param(name) => format!(
+            "Type mismatch: parameter '{}' expects {}, found {}.",
+            name, expected, found
+        ),
+        Boundary::Return => format!(
+            "Type mismatch: expected return type {}, found {}.",
+            expected, found
+        ),
+    }
+    .into())
+}
+
+// A variable. Generated code clones the `Rc` into every closure that
+// captures it, so closures see later assignments like the interpreter's do.
+struct Binding {
+    name: &'static str,
+    value: RefCell<Option<Value>>,
+    ty: RefCell<Option<Type>>,
+}
+
+#[derive(Clone)]
+pub struct Var(Rc<Binding>);
+
+impl Var {
+    /// A global, which exists from the start but is only readable once its
+    /// declaration has run.
+    pub fn new(name: &'static str) -> Var {
+        Var(Rc::new(Binding {
+            name,
+            value: RefCell::new(None),
+            ty: RefCell::new(None),
+        }))
+    }
+
+    /// A local, declared with its initial value.
+    pub fn declare(name: &'static str, value: Value, ty: Option<Type>) -> Result<Var> {
+        let var = Var::new(name);
+        var.define(value, ty)?;
+        Ok(var)
+    }
+
+    /// Binds parameter `index` of a call, `undefined` if it was not passed.
+    pub fn param(
+        name: &'static str,
+        args: &[Value],
+        index: usize,
+        ty: Option<Type>,
+    ) -> Result<Var> {
+        let value = args.get(index).cloned().unwrap_or(Value::Undefined);
+        check(ty.as_ref(), &value, Boundary::Param(name))?;
+        Ok(Var::new(name).with(value))
+    }
+
+    fn with(self, value: Value) -> Var {
+        *self.0.value.borrow_mut() = Some(value);
+        self
+    }
+
+    pub fn define(&self, value: Value, ty: Option<Type>) -> Result<Value> {
+        check(ty.as_ref(), &value, Boundary::Binding(self.0.name))?;
+        *self.0.value.borrow_mut() = Some(value);
+        *self.0.ty.borrow_mut() = ty;
+        Ok(Value::Undefined)
+    }
+
+    pub fn get(&self) -> Result<Value> {
+        match &*self.0.value.borrow() {
+            Some(value) => Ok(value.clone()),
+            None => Err(format!("Undefined variable '{}'.", self.0.name).into()),
+        }
+    }
+
+    pub fn set(&self, value: Value) -> Result<Value> {
+        if self.0.value.borrow().is_none() {
+            return Err(format!("Undefined variable '{}'.", self.0.name).into());
+        }
+        check(
+            self.0.ty.borrow().as_ref(),
+            &value,
+            Boundary::Binding(self.0.name),
+        )?;
+        *self.0.value.borrow_mut() = Some(value);
+        Ok(Value::Undefined)
+    }
+}
+
+pub fn func(ty: Type, call: impl Fn(&[Value]) -> Result<Value> + 'static) -> Value {
+    Value::Fn(Rc::new(Func {
+        ty,
+        call: Box::new(call),
+    }))
+}
+
+// A call in tail position, left for `call` to make once the function it
+// ends has returned. `ret` is that function's return type, which the call's
+// result is checked against as well.
+struct Tail {
+    callee: Value,
+    args: Vec<Value>,
+    ret: Option<Type>,
+}
+
+thread_local! {
+    static TAIL: RefCell<Option<Tail>> = const { RefCell::new(None) };
+}
+
+/// Calls `callee`, then any tail calls it ends with, so tail recursion runs
+/// in constant stack.
+pub fn call(callee: &Value, args: &[Value]) -> Result<Value> {
+    let mut value = invoke(callee, args)?;
+    let mut returns: Vec<Type> = Vec::new();
+    while let Some(tail) = TAIL.take() {
+        if let Some(ty) = tail.ret
+            && !returns.contains(&ty)
+        {
+            returns.push(ty);
+        }
+        value = invoke(&tail.callee, &tail.args)?;
+    }
+    for ty in returns.iter().rev() {
+        check(Some(ty), &value, Boundary::Return)?;
+    }
+    Ok(value)
+}
+
+fn invoke(callee: &Value, args: &[Value]) -> Result<Value> {
+    match callee {
+        Value::Fn(func) => (func.call)(args),
+        _ => Err("Not a function.".into()),
+    }
+}
+
+/// A call in tail position. It is made by the `call` the running function
+/// returns to, which the undefined returned in its place never reaches.
+pub fn tail(callee: Value, args: Vec<Value>) -> Result<Value> {
+    TAIL.set(Some(Tail {
+        callee,
+        args,
+        ret: None,
+    }));
+    Ok(Value::Undefined)
+}
+
+/// Checks a function's result against its declared return type, or leaves
+/// that to `call` when the function ends in a tail call.
+pub fn ret(value: Value, ty: Option<Type>) -> Result<Value> {
+    let pending = TAIL.with_borrow_mut(|tail| match tail {
+        Some(tail) => {
+            tail.ret = ty.clone();
+            true
+        }
+        None => false,
+    });
+    if !pending {
+        check(ty.as_ref(), &value, Boundary::Return)?;
+    }
+    Ok(value)
+}
+
+pub fn list(items: Vec<Value>) -> Value {
+    Value::Vec(Shared::new(items))
+}
+
+pub fn obj(entries: Vec<(Key, Value)>) -> Value {
+    Value::Obj(Shared::new(entries.into_iter().collect()))
+}
+
+/// The key a computed `[expr]` in an object literal stands for.
+pub fn key(value: Value) -> Result<Key> {
+    match value {
+        Value::Str(s) => Ok(Key::Str(s)),
+        Value::Int(n) => Ok(Key::Int(n)),
+        Value::Num(n) => Ok(Key::num(n)),
+        other => Err(format!(
+            "Object keys must be Str or Num, found {}.",
+            other.type_name()
+        )
+        .into()),
+    }
+}
+
+/// Reads a field of an object; a missing field is undefined.
+pub fn field(value: Value, name: &str) -> Result<Value> {
+    match value {
+        Value::Obj(obj) => {
+            let key = Key::Str(name.to_string());
+            Ok(obj
+                .data
+                .borrow()
+                .get(&key)
+                .cloned()
+                .unwrap_or(Value::Undefined))
+        }
+        _ => Err(format!("Cannot read field '{}' of {}.", name, value.type_name()).into()),
+    }
+}
+
+/// Reads `target[index]`: an element of a list, a character of a string,
+/// counting from the end if negative, or a field of an object. Anything
+/// missing is undefined.
+pub fn index(target: Value, index: Value) -> Result<Value> {
+    match target {
+        Value::Vec(list) => {
+            let list = list.data.borrow();
+            let at = position(&index, list.len())?;
+            Ok(at.map(|i| list[i].clone()).unwrap_or(Value::Undefined))
+        }
+        Value::Str(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            let at = position(&index, chars.len())?;
+            Ok(at
+                .map(|i| Value::Str(chars[i].to_string()))
+                .unwrap_or(Value::Undefined))
+        }
+        Value::Obj(obj) => Ok(obj
+            .data
+            .borrow()
+            .get(&key(index)?)
+            .cloned()
+            .unwrap_or(Value::Undefined)),
+        other => Err(format!("Cannot index {}.", other.type_name()).into()),
+    }
+}
+
+/// Assigns `target[index] = value`.
+pub fn set_index(target: Value, index: Value, value: Value) -> Result<()> {
+    match target {
+        Value::Vec(list) => {
+            if list.frozen.get() {
+                return Err("Cannot change a frozen list.".into());
+            }
+            let mut items = list.data.borrow_mut();
+            let len = items.len();
+            match position(&index, len)? {
+                Some(i) => {
+                    items[i] = value;
+                    Ok(())
+                }
+                None => {
+                    Err(format!("Index {} is out of range for a list of {}.", index, len).into())
+                }
+            }
+        }
+        Value::Obj(obj) => store(&obj, key(index)?, value),
+        other => Err(format!("Cannot assign to an element of {}.", other.type_name()).into()),
+    }
+}
+
+/// Assigns `target.name = value`.
+pub fn set_field(target: Value, name: &str, value: Value) -> Result<()> {
+    match target {
+        Value::Obj(obj) => store(&obj, Key::Str(name.to_string()), value),
+        other => Err(format!("Cannot set field '{}' of {}.", name, other.type_name()).into()),
+    }
+}
+
+fn store(obj: &Object, key: Key, value: Value) -> Result<()> {
+    if obj.frozen.get() {
+        return Err("Cannot change a frozen object.".into());
+    }
+    obj.data.borrow_mut().insert(key, value);
+    Ok(())
+}
+
+// Where `index` falls in a sequence of `len`, if anywhere
+fn position(index: &Value, len: usize) -> Result<Option<usize>> {
+    match index {
+        Value::Int(n) => {
+            let i = if *n < 0 { *n + len as i64 } else { *n };
+            Ok((0..len as i64).contains(&i).then_some(i as usize))
+        }
+        Value::Num(n) if n.fract() == 0.0 => {
+            let i = if *n < 0.0 { *n + len as f64 } else { *n };
+            Ok((i >= 0.0 && i < len as f64).then_some(i as usize))
+        }
+        Value::Num(n) => Err(format!("List indexes must be integers, found {}.", n).into()),
+        other => Err(format!(
+            "List indexes must be integers, found {}.",
+            other.type_name()
+        )
+        .into()),
+    }
+}
+
+/// Prints one argument of `print`, followed by a space unless it is the last.
+pub fn write(value: &Value, last: bool) {
+    match last {
+        true => print!("{}", value),
+        false => print!("{} ", value),
+    }
+}
+
+pub fn newline() {
+    println!();
+}
+
+/// Runs a builtin other than `print`.
+pub fn builtin(name: &str, args: Vec<Value>) -> Result<Value> {
+    match name {
+        "freeze" => {
+            let value = args.into_iter().next().unwrap_or(Value::Undefined);
+            freeze(&value);
+            Ok(value)
+        }
+        _ => Err(format!("Unknown builtin '{}'.", name).into()),
+    }
+}
+
+fn freeze(value: &Value) {
+    match value {
+        Value::Vec(list) if !list.frozen.get() => {
+            list.frozen.set(true);
+            list.data.borrow().iter().for_each(freeze);
+        }
+        Value::Obj(obj) if !obj.frozen.get() => {
+            obj.frozen.set(true);
+            obj.data.borrow().values().for_each(freeze);
+        }
+        _ => {}
+    }
+}
+
+/// Reports the error a top-level statement stopped with; the program then
+/// carries on with the next statement, as the interpreter does.
+pub fn report(result: Result<Value>) {
+    if let Err(Error(msg)) = result {
+        eprintln!("Error: {}", msg);
+    }
+}
+
+fn unsupported(what: &str, sym: &str, lhs: Value, rhs: Value) -> Result<Value> {
+    Err(format!(
+        "{} not supported: {} {} {}",
+        what,
+        lhs.type_name(),
+        sym,
+        rhs.type_name()
+    )
+    .into())
+}
+
+// Mixing an `Int` with a `Num` makes both floats
+fn promote(l: Value, r: Value) -> (Value, Value) {
+    match (l, r) {
+        (Value::Int(a), Value::Num(b)) => (Value::Num(a as f64), Value::Num(b)),
+        (Value::Num(a), Value::Int(b)) => (Value::Num(a), Value::Num(b as f64)),
+        pair => pair,
+    }
+}
+
+// The result of checked `Int` arithmetic, which is `None` if it overflowed
+fn checked(n: Option<i64>, a: i64, sym: &str, b: i64) -> Result<Value> {
+    match n {
+        Some(n) => Ok(Value::Int(n)),
+        None => Err(format!("Integer overflow: {} {} {}", a, sym, b).into()),
+    }
+}
+
+pub fn add(l: Value, r: Value) -> Result<Value> {
+    Ok(match promote(l, r) {
+        (Value::Int(a), Value::Int(b)) => return checked(a.checked_add(b), a, "+", b),
+        (Value::Num(a), Value::Num(b)) => Value::Num(a + b),
+
+        (Value::Int(a), Value::Str(b)) => Value::Str(format!("{}{}", a, b)),
+        (Value::Str(a), Value::Int(b)) => Value::Str(format!("{}{}", a, b)),
+        (Value::Num(a), Value::Str(b)) => Value::Str(format!("{}{}", a, b)),
+        (Value::Str(a), Value::Num(b)) => Value::Str(format!("{}{}", a, b)),
+        (Value::Bool(a), Value::Str(b)) => Value::Str(format!("{}{}", a, b)),
+        (Value::Str(a), Value::Bool(b)) => Value::Str(format!("{}{}", a, b)),
+        (Value::Str(a), Value::Str(b)) => Value::Str(a + &b),
+
+        (Value::Int(a), Value::Bool(b)) | (Value::Bool(b), Value::Int(a)) => {
+            return checked(a.checked_add(b as i64), a, "+", b as i64);
+        }
+        (Value::Num(a), Value::Bool(b)) | (Value::Bool(b), Value::Num(a)) => {
+            Value::Num(if b { a + 1.0 } else { a })
+        }
+
+        (Value::Undefined, Value::Undefined) => Value::Undefined,
+        (
+            Value::Undefined,
+            v @ (Value::Int(_) | Value::Num(_) | Value::Str(_) | Value::Vec(_) | Value::Obj(_)),
+        )
+        | (
+            v @ (Value::Int(_) | Value::Num(_) | Value::Str(_) | Value::Vec(_) | Value::Obj(_)),
+            Value::Undefined,
+        ) => v,
+
+        (Value::Vec(a), Value::Vec(b)) => {
+            let mut list = a.data.borrow().clone();
+            list.extend(b.data.borrow().iter().cloned());
+            Value::Vec(Shared::new(list))
+        }
+
+        // RHS entries override LHS on key conflict, keeping the LHS order;
+        // new keys go last
+        (Value::Obj(a), Value::Obj(b)) => {
+            let mut obj = a.data.borrow().clone();
+            obj.extend(b.data.borrow().iter().map(|(k, v)| (k.clone(), v.clone())));
+            Value::Obj(Shared::new(obj))
+        }
+
+        (lhs, rhs) => return unsupported("Addition", "+", lhs, rhs),
+    })
+}
+
+pub fn sub(l: Value, r: Value) -> Result<Value> {
+    Ok(match promote(l, r) {
+        (Value::Int(a), Value::Int(b)) => return checked(a.checked_sub(b), a, "-", b),
+        (Value::Num(a), Value::Num(b)) => Value::Num(a - b),
+
+        (Value::Int(a), Value::Bool(b)) | (Value::Bool(b), Value::Int(a)) => {
+            return checked(a.checked_sub(b as i64), a, "-", b as i64);
+        }
+        (Value::Num(a), Value::Bool(b)) | (Value::Bool(b), Value::Num(a)) => {
+            Value::Num(if b { a - 1.0 } else { a })
+        }
+
+        (Value::Undefined, Value::Undefined) => Value::Undefined,
+        (
+            Value::Undefined,
+            v @ (Value::Int(_) | Value::Num(_) | Value::Str(_) | Value::Vec(_) | Value::Obj(_)),
+        )
+        | (
+            v @ (Value::Int(_) | Value::Num(_) | Value::Str(_) | Value::Vec(_) | Value::Obj(_)),
+            Value::Undefined,
+        ) => v,
+
+        // Drops every key of the RHS from the LHS
+        (Value::Obj(a), Value::Obj(b)) => {
+            let mut obj = a.data.borrow().clone();
+            for key in b.data.borrow().keys() {
+                obj.remove(key);
+            }
+            Value::Obj(Shared::new(obj))
+        }
+
+        (lhs, rhs) => return unsupported("Subtraction", "-", lhs, rhs),
+    })
+}
+
+pub fn mul(l: Value, r: Value) -> Result<Value> {
+    Ok(match promote(l, r) {
+        (Value::Int(a), Value::Int(b)) => return checked(a.checked_mul(b), a, "*", b),
+        (Value::Num(a), Value::Num(b)) => Value::Num(a * b),
+
+        (Value::Str(s), Value::Int(n)) | (Value::Int(n), Value::Str(s)) => {
+            Value::Str(s.repeat(n.max(0) as usize))
+        }
+        (Value::Str(s), Value::Num(n)) | (Value::Num(n), Value::Str(s)) => {
+            let count = if n >= 0.0 { n as usize } else { 0 };
+            Value::Str(s.repeat(count))
+        }
+
+        (Value::Undefined, Value::Undefined) => Value::Undefined,
+        (Value::Undefined, rhs) => rhs,
+        (lhs, Value::Undefined) => lhs,
+
+        (lhs, rhs) => return unsupported("Multiplication", "×", lhs, rhs),
+    })
+}
+
+// Dividing `Int`s gives a `Num`; `~/` is the division that stays whole
+pub fn div(l: Value, r: Value) -> Result<Value> {
+    Ok(match promote(l, r) {
+        (Value::Int(a), Value::Int(b)) => {
+            if b == 0 {
+                return Err("Division by zero".into());
+            }
+            Value::Num(a as f64 / b as f64)
+        }
+        (Value::Num(a), Value::Num(b)) => {
+            if b == 0.0 {
+                return Err("Division by zero".into());
+            }
+            Value::Num(a / b)
+        }
+
+        (Value::Undefined, Value::Undefined) => Value::Undefined,
+        (lhs, Value::Undefined) => lhs,
+        (Value::Undefined, _) => Value::Undefined,
+
+        (lhs, rhs) => return unsupported("Division", "÷", lhs, rhs),
+    })
+}
+
+pub fn rem(l: Value, r: Value) -> Result<Value> {
+    Ok(match promote(l, r) {
+        (Value::Int(a), Value::Int(b)) => {
+            if b == 0 {
+                return Err("Modulo by zero".into());
+            }
+            Value::Int(a.wrapping_rem(b))
+        }
+        (Value::Num(a), Value::Num(b)) => {
+            if b == 0.0 {
+                return Err("Modulo by zero".into());
+            }
+            Value::Num(a % b)
+        }
+
+        (Value::Undefined, Value::Undefined) => Value::Undefined,
+        (lhs, Value::Undefined) => lhs,
+        (Value::Undefined, _) => Value::Undefined,
+
+        (lhs, rhs) => return unsupported("Modulo", "%", lhs, rhs),
+    })
+}
+
+// An `Int` to a negative power is a fraction, so a `Num`
+pub fn pow(l: Value, r: Value) -> Result<Value> {
+    match promote(l, r) {
+        (Value::Int(a), Value::Int(b)) if b >= 0 => {
+            let n = u32::try_from(b)
+                .ok()
+                .and_then(|e| a.checked_pow(e))
+                // Only 0, 1 and -1 survive an exponent that large
+                .or_else(|| {
+                    (-1..=1)
+                        .contains(&a)
+                        .then(|| if b % 2 == 0 { a.abs() } else { a })
+                });
+            checked(n, a, "**", b)
+        }
+        (Value::Int(a), Value::Int(b)) => Ok(Value::Num((a as f64).powf(b as f64))),
+        (Value::Num(a), Value::Num(b)) => Ok(Value::Num(a.powf(b))),
+        (lhs, rhs) => unsupported("Exponentiation", "**", lhs, rhs),
+    }
+}
+
+// Division rounded down
+pub fn int_div(l: Value, r: Value) -> Result<Value> {
+    Ok(match promote(l, r) {
+        (Value::Int(a), Value::Int(b)) => {
+            if b == 0 {
+                return Err("Division by zero".into());
+            }
+            return match a.checked_div(b) {
+                Some(q) if a % b != 0 && (a < 0) != (b < 0) => Ok(Value::Int(q - 1)),
+                q => checked(q, a, "~/", b),
+            };
+        }
+        (Value::Num(a), Value::Num(b)) => {
+            if b == 0.0 {
+                return Err("Division by zero".into());
+            }
+            Value::Num((a / b).floor())
+        }
+
+        (Value::Undefined, Value::Undefined) => Value::Undefined,
+        (lhs, Value::Undefined) => lhs,
+        (Value::Undefined, _) => Value::Undefined,
+
+        (lhs, rhs) => return unsupported("Integer division", "~/", lhs, rhs),
+    })
+}
+
+pub fn bit_and(l: Value, r: Value) -> Result<Value> {
+    bitwise("Bitwise and", "&", l, r, |a, b| a & b)
+}
+
+pub fn bit_or(l: Value, r: Value) -> Result<Value> {
+    bitwise("Bitwise or", "|", l, r, |a, b| a | b)
+}
+
+pub fn bit_xor(l: Value, r: Value) -> Result<Value> {
+    bitwise("Bitwise xor", "^", l, r, |a, b| a ^ b)
+}
+
+pub fn shl(l: Value, r: Value) -> Result<Value> {
+    bitwise("Left shift", "<<", l, r, |a, b| a << b)
+}
+
+pub fn shr(l: Value, r: Value) -> Result<Value> {
+    bitwise("Right shift", ">>", l, r, |a, b| a >> b)
+}
+
+pub fn bit_not(v: Value) -> Result<Value> {
+    match integer(&v) {
+        Some(n) => Ok(Value::Int(!n?)),
+        None => Err(format!("Bitwise not not supported: {}", v.type_name()).into()),
+    }
+}
+
+fn bitwise(what: &str, sym: &str, l: Value, r: Value, op: fn(i64, i64) -> i64) -> Result<Value> {
+    let (a, b) = match (integer(&l), integer(&r)) {
+        (Some(a), Some(b)) => (a?, b?),
+        _ => return unsupported(what, sym, l, r),
+    };
+    if matches!(sym, "<<" | ">>") && !(0..64).contains(&b) {
+        return Err(format!("Shift amount must be between 0 and 63, found {}.", b).into());
+    }
+    Ok(Value::Int(op(a, b)))
+}
+
+// The 64-bit integer a number stands for, if it is a whole one in range
+fn integer(v: &Value) -> Option<Result<i64>> {
+    match v {
+        Value::Int(n) => Some(Ok(*n)),
+        Value::Num(n) if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(n) => Some(Ok(*n as i64)),
+        Value::Num(n) => Some(Err(format!(
+            "Bitwise operators need whole numbers, found {}.",
+            n
+        )
+        .into())),
+        _ => None,
+    }
+}
+
+pub fn neg(v: Value) -> Result<Value> {
+    match v {
+        Value::Int(n) => match n.checked_neg() {
+            Some(n) => Ok(Value::Int(n)),
+            None => Err(format!("Integer overflow: -({})", n).into()),
+        },
+        Value::Num(n) => Ok(Value::Num(-n)),
+        Value::Undefined => Ok(Value::Undefined),
+        other => Err(format!("Negation not supported: {}", other.type_name()).into()),
+    }
+}
+
+// Logical operators evaluate both sides, like the interpreter's
+pub fn and(l: Value, r: Value) -> Value {
+    Value::Bool(l.truthy() && r.truthy())
+}
+
+pub fn or(l: Value, r: Value) -> Value {
+    Value::Bool(l.truthy() || r.truthy())
+}
+
+pub fn not(v: Value) -> Value {
+    Value::Bool(!v.truthy())
+}
+
+pub fn eq(l: Value, r: Value) -> Value {
+    Value::Bool(l == r)
+}
+
+pub fn ne(l: Value, r: Value) -> Value {
+    Value::Bool(l != r)
+}
+
+pub fn gt(l: Value, r: Value) -> Value {
+    Value::Bool(l > r)
+}
+
+pub fn ge(l: Value, r: Value) -> Value {
+    Value::Bool(l >= r)
+}
+
+pub fn lt(l: Value, r: Value) -> Value {
+    Value::Bool(l < r)
+}
+
+pub fn le(l: Value, r: Value) -> Value {
+    Value::Bool(l <= r)
+}
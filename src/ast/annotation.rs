@@ -0,0 +1,57 @@
+use super::super::types::{Span, Tkn, Type};
+use chumsky::{input::ValueInput, prelude::*};
+
+/// Type annotations: `num`, `str`, `bool`, `obj`, `any`, `undefined`,
+/// `list<T>`, `fn(A, B) -> R` and unions such as `num | str`.
+pub fn annotation<'a, I>() -> impl Parser<'a, I, Type, extra::Err<Rich<'a, Tkn<'a>, Span>>> + Clone
+where
+    I: ValueInput<'a, Token = Tkn<'a>, Span = Span>,
+{
+    let sym = |c: &'a str| just(Tkn::Symbol(c));
+    let del = |c: char| just(Tkn::Delimiter(c));
+
+    recursive(|ty| {
+        let named =
+            select! { Tkn::Identifier(name) => name }.try_map(|name, span| match name.as_str() {
+                "any" => Ok(Type::Any),
+                "undefined" => Ok(Type::Undefined),
+                "num" => Ok(Type::Num),
+                "str" => Ok(Type::Str),
+                "bool" => Ok(Type::Bool),
+                "obj" => Ok(Type::Obj),
+                "list" => Ok(Type::List(Box::new(Type::Any))),
+                _ => Err(Rich::custom(span, format!("Unknown type '{}'", name))),
+            });
+
+        let list = select! { Tkn::Identifier(name) if name.as_str() == "list" => () }
+            .ignore_then(ty.clone().delimited_by(sym("<"), sym(">")))
+            .map(|item| Type::List(Box::new(item)));
+
+        let r#fn = just(Tkn::Keyword("fn"))
+            .ignore_then(
+                ty.clone()
+                    .separated_by(sym(","))
+                    .allow_trailing()
+                    .collect::<Vec<_>>()
+                    .delimited_by(del('('), del(')')),
+            )
+            .then(sym("->").ignore_then(ty.clone()).or_not())
+            .map(|(params, ret)| Type::Fn(params, Box::new(ret.unwrap_or(Type::Any))));
+
+        let atom = choice((
+            list,
+            named,
+            r#fn,
+            ty.clone().delimited_by(del('('), del(')')),
+        ));
+
+        atom.separated_by(sym("|"))
+            .at_least(1)
+            .collect::<Vec<_>>()
+            .map(|mut types| match types.len() {
+                1 => types.pop().unwrap(),
+                _ => Type::Union(types),
+            })
+    })
+    .labelled("type")
+}
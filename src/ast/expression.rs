@@ -1,11 +1,16 @@
-use super::super::types::{Expr, Op, Span, Spanned, Tkn, Value};
+use super::super::types::{BSE, Expr, Key, Op, PropKey, Slot, Span, Spanned, Symbol, Tkn, Value};
 use chumsky::{input::ValueInput, prelude::*};
+use std::rc::Rc;
 
-pub fn expression<'a, I>(
-    ext_val: impl Parser<'a, I, Box<Spanned<Expr<'a>>>, extra::Err<Rich<'a, Tkn<'a>, Span>>>
-    + Clone
-    + 'a,
-) -> impl Parser<'a, I, Box<Spanned<Expr<'a>>>, extra::Err<Rich<'a, Tkn<'a>, Span>>> + Clone
+#[derive(Clone)]
+enum Postfix {
+    Call(Vec<BSE>),
+    Field(Symbol),
+    Index(BSE),
+}
+
+pub fn expression<'a, I>()
+-> impl Parser<'a, I, Box<Spanned<Expr>>, extra::Err<Rich<'a, Tkn<'a>, Span>>> + Clone
 where
     I: ValueInput<'a, Token = Tkn<'a>, Span = Span>,
 {
@@ -21,6 +26,9 @@ where
 
     recursive(|inline| {
         let primitive = select! {
+            Tkn::Int(n) => Value::Int(n),
+            Tkn::BigInt(n) => Value::BigInt(Rc::new(n)),
+            Tkn::Decimal(n) => Value::Decimal(Rc::new(n)),
             Tkn::Number(f) => Value::Num(f),
             Tkn::Bool(f) => Value::Bool(f),
             Tkn::Str(f) => Value::Str(f)
@@ -36,112 +44,246 @@ where
         let vec = items
             .clone()
             .delimited_by(del('['), del(']'))
-            .map(Value::Vec);
+            .map(Expr::List);
+
+        // `{` opens an object wherever an expression is expected; only the
+        // bodies of `if`, `else`, `while` and `fn` take it as a block. Keys
+        // are names, strings, numbers or `[expr]`, and `{name}` is short
+        // for `{name: name}`.
+        let key = choice((
+            ident.map(|name| Key::Str(name.to_string())),
+            select! { Tkn::Str(s) => Key::Str(s) },
+            // Literals have no sign of their own, so a negative key's is
+            // taken here
+            sym("-")
+                .or_not()
+                .then(select! {
+                    Tkn::Int(n) => Key::Int(n),
+                    Tkn::Number(n) => Key::num(n),
+                })
+                .map(|(minus, key)| match (minus, key) {
+                    (Some(_), Key::Int(n)) => Key::Int(-n),
+                    (Some(_), Key::Num(n)) => Key::num(-n.0),
+                    (_, key) => key,
+                }),
+        ))
+        .map(PropKey::Fixed)
+        .or(inline
+            .clone()
+            .padded_by(nl.clone())
+            .delimited_by(del('['), del(']'))
+            .map(PropKey::Computed));
 
-        let val = choice((vec, primitive)).map(Expr::Val);
+        let shorthand = ident.map_with(|name, e| {
+            let var = Box::new((Expr::Var(name, Slot::Global), e.span()));
+            (PropKey::Fixed(Key::Str(name.to_string())), var)
+        });
+
+        let object = key
+            .then_ignore(sym(":").padded_by(nl.clone()))
+            .then(inline.clone())
+            .or(shorthand)
+            .padded_by(nl.clone())
+            .separated_by(sym(",").padded_by(nl.clone()))
+            .allow_trailing()
+            .collect()
+            .delimited_by(del('{').padded_by(nl.clone()), del('}'))
+            .map(Expr::Obj);
+
+        let val = choice((vec, object, primitive.map(Expr::Val)));
+
+        // Standard library modules, like `print`, can't be shadowed
+        let namespace =
+            select! { Tkn::Identifier(name) if matches!(name.as_str(), "math" | "object" | "fs") => name }
+                .map(Expr::ReservedVar);
 
         let atom = val
-            .or(ident.map(Expr::Var))
+            .or(namespace)
+            .or(ident.map(|name| Expr::Var(name, Slot::Global)))
             .map_with(|expr, e| Box::new((expr, e.span())))
-            .or(ext_val.clone())
-            .or(inline.clone().delimited_by(del('('), del(')')));
+            .or(inline.clone().delimited_by(del('('), del(')')))
+            .boxed();
 
-        let call = atom
-            .clone()
-            .then(items.clone().delimited_by(del('('), del(')')))
-            .map(|(f, args)| Expr::Call(f, args))
-            .map_with(|expr, e| Box::new((expr, e.span())));
+        // Calls, field reads and indexing chain left to right:
+        // `math.pow(2)(3)`, `rows[0].len()`
+        let postfix = choice((
+            items
+                .clone()
+                .delimited_by(del('('), del(')'))
+                .map(Postfix::Call),
+            sym(".").ignore_then(ident).map(Postfix::Field),
+            inline
+                .clone()
+                .padded_by(nl.clone())
+                .delimited_by(del('['), del(']'))
+                .map(Postfix::Index),
+        ));
 
-        let reserved_fn = select! { Tkn::Identifier("print") => "print" }
+        let reserved_fn = select! { Tkn::Identifier(name) if matches!(name.as_str(), "print" | "freeze" | "range") => name }
             .then(items.clone().delimited_by(del('('), del(')')))
             .map(|(f, args)| Expr::ReservedCall(f, args))
             .map_with(|expr, e| Box::new((expr, e.span())));
 
-        let at = choice((reserved_fn, call, atom));
+        // A reserved call can head a postfix chain like any atom:
+        // `range(1, 5).map(square)`
+        let at = choice((reserved_fn, atom))
+            .foldl_with(postfix.repeated(), |f, postfix, e| {
+                let expr = match postfix {
+                    Postfix::Call(args) => Expr::Call(f, args),
+                    Postfix::Field(name) => Expr::Field(f, name),
+                    Postfix::Index(index) => Expr::Index(f, index),
+                };
+                Box::new((expr, e.span()))
+            })
+            .boxed();
 
-        let op = sym("!").to(Op::Not).or(sym("-").to(Op::Neg));
-        let unary = op
-            .repeated()
-            .foldr_with(at.clone(), |op, rhs, e| {
+        // `**` binds tighter than the prefix operators, and to the right:
+        // `-2 ** 2` is -4 and `2 ** 3 ** 2` is 512
+        let op = choice((
+            sym("!").to(Op::Not),
+            sym("-").to(Op::Neg),
+            sym("~").to(Op::BitNot),
+        ));
+        let unary = recursive(|unary| {
+            let power = at
+                .clone()
+                .then(sym("**").ignore_then(unary).or_not())
+                .map_with(|(base, exp), e| match exp {
+                    Some(exp) => Box::new((Expr::BnOp(base, Op::Pow, exp), e.span())),
+                    None => base,
+                });
+            op.repeated().foldr_with(power, |op, rhs, e| {
                 Box::new((Expr::UnOp(op, rhs), e.span()))
             })
-            .recover_with(via_parser(
-                any()
-                    .filter(|tok| {
-                        !matches!(
-                            tok,
-                            Tkn::Keyword(_)
-                                | Tkn::Delimiter(_)
-                                | Tkn::Identifier(_)
-                                | Tkn::Number(_)
-                                | Tkn::Symbol(";")
-                        )
-                    })
-                    .map_with(|_, e| Box::new((Expr::Error, e.span()))),
-            ));
+        })
+        .recover_with(via_parser(
+            any()
+                .filter(|tok| {
+                    !matches!(
+                        tok,
+                        Tkn::Keyword(_)
+                            | Tkn::Delimiter(_)
+                            | Tkn::Identifier(_)
+                            | Tkn::Int(_)
+                            | Tkn::BigInt(_)
+                            | Tkn::Decimal(_)
+                            | Tkn::Number(_)
+                            | Tkn::Symbol(";")
+                    )
+                })
+                .map_with(|_, e| Box::new((Expr::Error, e.span()))),
+        ));
 
-        let op = sym("*").to(Op::Mul).or(sym("/").to(Op::Div));
+        // Binary operators from the tightest binding down, all associating
+        // to the left
+        let op = choice((
+            sym("*").to(Op::Mul),
+            sym("/").to(Op::Div),
+            sym("~/").to(Op::IntDiv),
+            sym("%").to(Op::Mod),
+        ));
         let product = unary
             .clone()
             .foldl_with(op.then(unary.clone()).repeated(), |a, (op, b), e| {
                 Box::new((Expr::BnOp(a, op, b), e.span()))
-            });
+            })
+            .boxed();
 
         let op = sym("+").to(Op::Add).or(sym("-").to(Op::Sub));
         let sum = product
             .clone()
             .foldl_with(op.then(product).repeated(), |a, (op, b), e| {
                 Box::new((Expr::BnOp(a, op, b), e.span()))
-            });
+            })
+            .boxed();
+
+        // Only a `>` right after another makes a shift
+        let gt = sym(">").map_with(|_, e| e.span());
+        let shr =
+            gt.clone()
+                .then(gt)
+                .try_map(|(a, b): (Span, Span), span| match a.end == b.start {
+                    true => Ok(Op::Shr),
+                    false => Err(Rich::custom(span, "Expected '>>'.")),
+                });
+        let op = sym("<<").to(Op::Shl).or(shr);
+        let shift = sum
+            .clone()
+            .foldl_with(op.then(sum).repeated(), |a, (op, b), e| {
+                Box::new((Expr::BnOp(a, op, b), e.span()))
+            })
+            .boxed();
+
+        let bit_and = shift
+            .clone()
+            .foldl_with(
+                sym("&").to(Op::BitAnd).then(shift).repeated(),
+                |a, (op, b), e| Box::new((Expr::BnOp(a, op, b), e.span())),
+            )
+            .boxed();
+
+        let bit_xor = bit_and
+            .clone()
+            .foldl_with(
+                sym("^").to(Op::BitXor).then(bit_and).repeated(),
+                |a, (op, b), e| Box::new((Expr::BnOp(a, op, b), e.span())),
+            )
+            .boxed();
+
+        let bit_or = bit_xor
+            .clone()
+            .foldl_with(
+                sym("|").to(Op::BitOr).then(bit_xor).repeated(),
+                |a, (op, b), e| Box::new((Expr::BnOp(a, op, b), e.span())),
+            )
+            .boxed();
 
         let op = choice((
-            sym("&").then_ignore(sym("&")).to(Op::And),
-            sym("|").then_ignore(sym("|")).to(Op::Or),
-            sym("%").to(Op::Mod),
             sym("==").to(Op::Eq),
             sym("!=").to(Op::Neq),
             sym(">=").to(Op::Gte),
             sym("<=").to(Op::Lte),
             sym(">").to(Op::Gt),
             sym("<").to(Op::Lt),
-        ))
-        /*.recover_with(via_parser(
-            any()
-                .and_is(just(Tkn::Symbol(";")).not())
-                .and_is(just(Tkn::Symbol(",")).not())
-                .filter(|tok| {
-                    !matches!(
-                        tok,
-                        Tkn::Keyword(_) | Tkn::Delimiter(_) | Tkn::Identifier(_)
-                    )
-                })
-                .map(|_| Op::Error),
-        )) */;
-
-        let compare = choice((
-            sum.clone()
-                .foldl_with(op.then(sum).repeated(), |a, (op, b), e| {
-                    Box::new((Expr::BnOp(a, op, b), e.span()))
-                }),
-            at.clone(),
-        ))
-        .recover_with(via_parser(nested_delimiters(
-            del_('('),
-            del_(')'),
-            [(del_('['), del_(']')), (del_('{'), del_('}'))],
-            |span| Box::new((Expr::Error, span)),
-        )))
-        .recover_with(via_parser(nested_delimiters(
-            del_('['),
-            del_(']'),
-            [(del_('('), del_(')')), (del_('{'), del_('}'))],
-            |span| Box::new((Expr::Error, span)),
-        )))
-        .recover_with(via_parser(
-            any()
-                .filter(|tok| !matches!(tok, Tkn::Keyword(_) | Tkn::Delimiter(_)))
-                .map_with(|_, e| Box::new((Expr::Error, e.span()))),
         ));
+        let comparison = bit_or
+            .clone()
+            .foldl_with(op.then(bit_or).repeated(), |a, (op, b), e| {
+                Box::new((Expr::BnOp(a, op, b), e.span()))
+            })
+            .boxed();
+
+        let and = comparison
+            .clone()
+            .foldl_with(
+                sym("&&").to(Op::And).then(comparison).repeated(),
+                |a, (op, b), e| Box::new((Expr::BnOp(a, op, b), e.span())),
+            )
+            .boxed();
+
+        let or = and.clone().foldl_with(
+            sym("||").to(Op::Or).then(and).repeated(),
+            |a, (op, b), e| Box::new((Expr::BnOp(a, op, b), e.span())),
+        );
+
+        let compare = choice((or, at.clone()))
+            .recover_with(via_parser(nested_delimiters(
+                del_('('),
+                del_(')'),
+                [(del_('['), del_(']')), (del_('{'), del_('}'))],
+                |span| Box::new((Expr::Error, span)),
+            )))
+            .recover_with(via_parser(nested_delimiters(
+                del_('['),
+                del_(']'),
+                [(del_('('), del_(')')), (del_('{'), del_('}'))],
+                |span| Box::new((Expr::Error, span)),
+            )))
+            .recover_with(via_parser(
+                any()
+                    .filter(|tok| !matches!(tok, Tkn::Keyword(_) | Tkn::Delimiter(_)))
+                    .map_with(|_, e| Box::new((Expr::Error, e.span()))),
+            ));
 
         compare.labelled("expression")
     })
@@ -1,36 +1,68 @@
-use super::super::types::{Span, Spanned, Tkn};
+use super::super::interpreter::operators;
+use super::super::types::{Span, Spanned, Symbol, Tkn};
 use chumsky::prelude::*;
+use num_bigint::BigInt;
+
+const KEYWORDS: &[&str] = &[
+    "let", "const", "fn", "return", "if", "else", "while", "import", "export", "throw", "try",
+    "catch", "finally",
+];
 
 pub fn lex<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Tkn<'a>>>, extra::Err<Rich<'a, char, Span>>>
 {
     let newline = text::newline().map(|_| Tkn::Newline);
 
-    let keyword = choice((
-        just("let"),
-        just("fn"),
-        just("return"),
-        just("if"),
-        just("else"),
-        just("while"),
-    ))
-    .map(Tkn::Keyword);
+    // Keywords and booleans are whole words, so `iffy` and `trueish` are
+    // plain identifiers
+    let keyword = text::ascii::ident()
+        .filter(|word: &&str| KEYWORDS.contains(word))
+        .map(Tkn::Keyword);
 
-    let boolean = choice((
-        just("true").map(|_| Tkn::Bool(true)),
-        just("false").map(|_| Tkn::Bool(false)),
-    ));
+    let boolean = text::ascii::ident().try_map(|word: &str, span| match word {
+        "true" => Ok(Tkn::Bool(true)),
+        "false" => Ok(Tkn::Bool(false)),
+        _ => Err(Rich::custom(span, "expected boolean")),
+    });
 
-    let digits = text::digits(10).to_slice();
+    // Digits can be grouped with `_`, as in `1_000_000`
+    let digits = |radix| text::digits(radix).separated_by(just('_')).at_least(1);
 
-    let frac = just('.').then(digits);
+    let prefixed = |prefix, radix| {
+        just(prefix)
+            .ignore_then(digits(radix).to_slice())
+            .map(move |s: &str| {
+                let s = s.replace('_', "");
+                match i64::from_str_radix(&s, radix) {
+                    Ok(n) => Tkn::Int(n),
+                    Err(_) => Tkn::BigInt(BigInt::parse_bytes(s.as_bytes(), radix).unwrap()),
+                }
+            })
+    };
 
-    let number = just('-')
-        .or_not()
-        .then(text::int(10))
+    let frac = just('.').then(digits(10));
+    let exponent = one_of("eE")
+        .then(one_of("+-").or_not())
+        .then(text::digits(10));
+
+    // A leading `-` is the unary operator, which binds more loosely than `**`.
+    // Numbers with a `d` suffix are exact decimals. Otherwise those with a
+    // fraction or a negative exponent are floats, and the rest integers, so
+    // `1e6` is a million exactly; past the `i64`s they are `BigInt`s.
+    let decimal = digits(10)
         .then(frac.or_not())
+        .then(exponent.or_not())
         .to_slice()
-        .map(|s: &str| s.parse().unwrap())
-        .map(Tkn::Number);
+        .then(just('d').or_not())
+        .validate(|(s, suffix): (&str, _), e, emitter| {
+            // A literal that cannot be read is reported, not backtracked over,
+            // so the error points at it rather than at what else could follow
+            literal(&s.replace('_', ""), suffix.is_some()).unwrap_or_else(|msg| {
+                emitter.emit(Rich::custom(e.span(), msg));
+                Tkn::Int(0)
+            })
+        });
+
+    let number = choice((prefixed("0x", 16), prefixed("0b", 2), decimal));
 
     /* let escape = just("\\")
     .then(choice((
@@ -63,23 +95,33 @@ pub fn lex<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Tkn<'a>>>, extra::Err<R
         .map(Tkn::Str);
 
     let identifier = text::ascii::ident()
-        .and_is(keyword.not())
-        .map(Tkn::Identifier);
+        .filter(|word: &&str| !KEYWORDS.contains(word))
+        .map(|name| Tkn::Identifier(Symbol::intern(name)));
 
     let math_sym = choice((
         just("++"),
         just("--"),
+        just("+="),
+        just("-="),
+        just("*="),
+        just("/="),
         just("/"),
         just("'"),
         just("+"),
         just("-"),
+        just("**"),
         just("*"),
     ))
     .map(Tkn::Symbol);
 
+    // Longer symbols go before their prefixes. `>>` is left as two `>`s, so
+    // that `list<list<num>>` closes both lists; the expression parser puts
+    // them back together.
     let logic_sym = choice((
+        just("->"),
+        just("<<"),
         just("<="),
-        just("<="),
+        just(">="),
         just("=="),
         just("!="),
         just("/*"),
@@ -88,13 +130,13 @@ pub fn lex<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Tkn<'a>>>, extra::Err<R
         just("\\"),
         just("\""),
         just("'"),
-        just("&"),
-        just("|"),
         just("!"),
         just(">"),
         just("<"),
         just("="),
+        just("%="),
         just("%"),
+        just("??="),
         just(","),
         just("."),
         just(":"),
@@ -103,6 +145,17 @@ pub fn lex<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Tkn<'a>>>, extra::Err<R
     ))
     .map(Tkn::Symbol);
 
+    let bit_sym = choice((
+        just("&&"),
+        just("||"),
+        just("&"),
+        just("|"),
+        just("^"),
+        just("~/"),
+        just("~"),
+    ))
+    .map(Tkn::Symbol);
+
     let delimiter = choice((
         just('('),
         just(')'),
@@ -126,13 +179,42 @@ pub fn lex<'a>() -> impl Parser<'a, &'a str, Vec<Spanned<Tkn<'a>>>, extra::Err<R
         .or(boolean)
         .or(keyword)
         .or(logic_sym)
+        .or(bit_sym)
         .or(math_sym)
         .or(delimiter)
         .or(identifier)
+        // Spans leave out the padding, so the parser can tell `>>` from `> >`
+        .map_with(|tok, e| (tok, e.span()))
         .padded_by(text::inline_whitespace())
         .padded_by(comment.repeated())
-        .map_with(|tok, e| (tok, e.span()))
         .repeated()
         .collect()
         .boxed()
 }
+
+// The token for the decimal literal `s`, without its `_`s
+fn literal(s: &str, decimal: bool) -> Result<Tkn<'static>, &'static str> {
+    if decimal {
+        return Ok(Tkn::Decimal(s.parse().unwrap()));
+    }
+    let (mantissa, exp) = s.split_once(['e', 'E']).unwrap_or((s, "0"));
+    if mantissa.contains('.') || exp.starts_with('-') {
+        return Ok(Tkn::Number(s.parse().unwrap()));
+    }
+    let exp: u32 = exp
+        .trim_start_matches('+')
+        .parse()
+        .map_err(|_| "Exponent out of range.")?;
+    let small = mantissa
+        .parse::<i64>()
+        .ok()
+        .zip(10i64.checked_pow(exp))
+        .and_then(|(m, scale)| m.checked_mul(scale));
+    let ten = BigInt::from(10);
+    Ok(match small {
+        Some(n) => Tkn::Int(n),
+        // The same limit as `**`, so `1e200000000` fails right away
+        None if !operators::fits(&ten, exp) => return Err("Exponent too large."),
+        None => Tkn::BigInt(mantissa.parse::<BigInt>().unwrap() * ten.pow(exp)),
+    })
+}
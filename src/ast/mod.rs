@@ -1,34 +1,45 @@
-use super::types::{Expr, Spanned, Tkn};
+use super::types::{BSE, Spanned, Tkn};
 use ariadne::{Color, Label, Report, ReportKind, sources};
 use chumsky::prelude::*;
 use statement::statement;
 
+mod annotation;
 mod expression;
 mod lex;
 mod statement;
 
-pub fn lex<'a>(
-    source: &'a str,
-) -> (
-    Option<Vec<Spanned<Tkn<'a>>>>,
-    Vec<chumsky::error::Rich<'a, char>>,
-) {
-    lex::lex().parse(&source).into_output_errors()
+pub type Output<'a, T, E> = (Option<T>, Vec<Rich<'a, E>>);
+
+pub fn lex(source: &str) -> Output<'_, Vec<Spanned<Tkn<'_>>>, char> {
+    lex::lex().parse(source).into_output_errors()
 }
 
-pub fn parse<'a>(
-    tkn: &'a [Spanned<Tkn<'a>>],
-    source: &str,
-) -> (
-    Option<Vec<Box<(Expr<'a>, SimpleSpan)>>>,
-    Vec<chumsky::error::Rich<'a, Tkn<'a>>>,
-) {
+pub fn parse<'a>(tkn: &'a [Spanned<Tkn<'a>>], source: &str) -> Output<'a, Vec<BSE>, Tkn<'a>> {
     statement()
         .parse(tkn.map((source.len()..source.len()).into(), |(t, s)| (t, s)))
         .into_output_errors()
 }
 
-pub fn show_errors<'a, T>(errs: Vec<Rich<'a, T>>, filename: String, src: &String)
+/// Lexes and parses a whole file, reporting any errors under `filename`.
+pub fn read(filename: &str, source: &str) -> Option<Vec<BSE>> {
+    // Literals that cannot be read are reported while lexing carries on
+    let (tokens, lex_errs) = lex(source);
+    let Some(tokens) = tokens.filter(|_| lex_errs.is_empty()) else {
+        show_errors(lex_errs, filename.to_string(), source);
+        return None;
+    };
+
+    // A tree the parser recovered from errors in still holds them, so it is
+    // not run either
+    let (ast, parse_errs) = parse(&tokens, source);
+    if !parse_errs.is_empty() {
+        show_errors(parse_errs, filename.to_string(), source);
+        return None;
+    }
+    ast
+}
+
+pub fn show_errors<T>(errs: Vec<Rich<'_, T>>, filename: String, src: &str)
 where
     T: ToString + Clone,
 {
@@ -48,7 +59,37 @@ where
                         .with_color(Color::Yellow)
                 }))
                 .finish()
-                .print(sources([(filename.clone(), src.clone())]))
+                .print(sources([(filename.clone(), src.to_string())]))
                 .unwrap()
         })
 }
+
+pub fn show_spanned_errors(errs: Vec<Spanned<String>>, filename: String, src: &str) {
+    errs.into_iter().for_each(|(msg, span)| {
+        Report::build(ReportKind::Error, (filename.clone(), span.into_range()))
+            .with_message(msg.clone())
+            .with_label(
+                Label::new((filename.clone(), span.into_range()))
+                    .with_message(msg)
+                    .with_color(Color::Red),
+            )
+            .finish()
+            .print(sources([(filename.clone(), src.to_string())]))
+            .unwrap()
+    })
+}
+
+/// 1-based line and column of a byte offset into `source`.
+pub fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source.as_bytes()[..offset.min(source.len())];
+    let line_start = before
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+    let col = String::from_utf8_lossy(&before[line_start..])
+        .chars()
+        .count()
+        + 1;
+    (line, col)
+}
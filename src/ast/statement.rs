@@ -1,10 +1,13 @@
-use super::super::types::{Expr, Key, Span, Spanned, Tkn, Value};
+use super::super::types::{
+    AssignOp, BSE, Expr, Imports, Op, Param, Place, Signature, Slot, Span, Spanned, Tkn, Value,
+};
+use super::annotation::annotation;
 use super::expression::expression;
 use chumsky::{input::ValueInput, prelude::*};
-use ordered_float::OrderedFloat as Float;
+use std::rc::Rc;
 
 pub fn statement<'a, I>()
--> impl Parser<'a, I, Vec<Box<Spanned<Expr<'a>>>>, extra::Err<Rich<'a, Tkn<'a>, Span>>> + Clone
+-> impl Parser<'a, I, Vec<Box<Spanned<Expr>>>, extra::Err<Rich<'a, Tkn<'a>, Span>>> + Clone
 where
     I: ValueInput<'a, Token = Tkn<'a>, Span = Span>,
 {
@@ -18,8 +21,6 @@ where
         .labelled("';'")
         .or(just(Tkn::Newline).labelled("'\\n'"));
 
-    let nl = just(Tkn::Newline).or_not();
-
     let complex_term = recursive(|complex_term| {
         let complex = recursive(|complex| {
             let block_recovery = nested_delimiters(
@@ -40,56 +41,39 @@ where
                 .map_with(|v, e| (Expr::Block(v), e.span()))
                 .recover_with(via_parser(block_recovery));
 
-            let primitive = select! {
-            Tkn::Number(f) => Value::Num(f),
-            Tkn::Str(f) => Value::Str(f)
-            };
+            let expression = expression().boxed();
 
-            let object = primitive
-                .clone()
-                .map(|v| match v {
-                    Value::Num(a) => Key::Num(Float(a)),
-                    Value::Str(a) => Key::Str(a),
-                    _ => Key::Str("".to_string()),
-                })
-                .padded_by(nl.clone())
-                .then_ignore(sym(":"))
-                .padded_by(nl.clone())
-                .then(complex.clone())
-                .padded_by(nl.clone())
-                .separated_by(sym(",").padded_by(nl.clone()))
-                .allow_trailing()
-                .collect()
-                .delimited_by(del('{').padded_by(nl.clone()), del('}'))
-                .map(Value::Obj)
-                .map(Expr::Val)
-                .map_with(|expr, e| Box::new((expr, e.span())));
-
-            let expression = expression(object.clone());
+            let typed = sym(":").ignore_then(annotation()).or_not();
 
             let r#let = kw("let")
                 .ignore_then(ident)
+                .then(typed.clone())
                 .then(choice((
                     (any().and_is(sym("=").not()).rewind())
                         .map_with(|_, e| Box::new((Expr::Val(Value::Undefined), e.span()))),
                     sym("=").ignore_then(expression.clone()),
                 )))
-                .map(|(name, rhs)| Expr::Let(name, rhs));
+                .map(|((name, ty), rhs)| Expr::Let(name, Slot::Global, ty, rhs));
 
-            let assign = ident
+            let r#const = kw("const")
+                .ignore_then(ident)
+                .then(typed.clone())
                 .then_ignore(sym("="))
                 .then(expression.clone())
-                .map(|(name, rhs)| Expr::Assign(name, rhs));
+                .map(|((name, ty), rhs)| Expr::Const(name, Slot::Global, ty, rhs));
 
             let r#fn = kw("fn")
                 .ignore_then(ident)
                 .then(
                     ident
+                        .then(typed.clone())
+                        .map(|(name, ty)| Param { name, ty })
                         .separated_by(sym(","))
                         .allow_trailing()
                         .collect::<Vec<_>>()
                         .delimited_by(del('('), del(')')),
                 )
+                .then(sym("->").ignore_then(annotation()).or_not())
                 .then(choice((
                     sym("=").ignore_then(
                         expression
@@ -98,21 +82,26 @@ where
                     ),
                     block.clone(),
                 )))
-                .map(|((name, args), body)| Expr::Fn(name, args, Box::new(body)));
+                .map(|(((name, params), ret), mut body)| {
+                    mark_tail_calls(&mut body);
+                    let signature = Rc::new(Signature { params, ret });
+                    Expr::Fn(name, Slot::Global, signature, Box::new(body))
+                });
 
             let r#return = kw("return")
                 .ignore_then(expression.clone())
                 .map(Expr::Return);
 
+            // A body starting with `{` is a block, never an object
+            let body = choice((block.clone().map(Box::new), complex.clone()));
+
             let r#if = kw("if")
                 .ignore_then(expression.clone())
                 .then_ignore(stmt_term.clone().or_not())
-                .then(choice((complex.clone(), block.clone().map(Box::new))))
+                .then(body.clone())
                 .then(
                     (
-                        kw("else").ignore_then(
-                            choice((complex.clone(), block.clone().map(Box::new))).or_not(),
-                        )
+                        kw("else").ignore_then(body.clone().or_not())
                         //.then_ignore(stmt_term.clone().or_not())
                     )
                     .or_not(),
@@ -133,14 +122,109 @@ where
 
             let r#while = kw("while")
                 .ignore_then(expression.clone())
-                .then(choice((complex.clone(), block.clone().map(Box::new))))
+                .then(body)
                 .map(|(condition, body)| Expr::While(condition, body));
 
-            assign
+            let r#throw = kw("throw").ignore_then(expression.clone()).map(Expr::Throw);
+
+            let r#try = kw("try")
+                .ignore_then(block.clone().map(Box::new))
+                .then(
+                    kw("catch")
+                        .ignore_then(ident.delimited_by(del('('), del(')')))
+                        .then(block.clone().map(Box::new))
+                        .map(|(name, handler)| (name, Slot::Global, handler))
+                        .or_not(),
+                )
+                .then(
+                    kw("finally")
+                        .ignore_then(block.clone().map(Box::new))
+                        .or_not(),
+                )
+                .try_map(|((body, catch), finally), span| match (&catch, &finally) {
+                    (None, None) => Err(Rich::custom(span, "Expected 'catch' or 'finally'.")),
+                    _ => Ok(Expr::Try(body, catch, finally)),
+                });
+
+            // `target op= value`, `target++` and `++target`, on a variable, a
+            // field or an element. Like `=`, these are statements.
+            let assign_op = choice((
+                sym("=").to(AssignOp::Replace),
+                sym("+=").to(AssignOp::Apply(Op::Add)),
+                sym("-=").to(AssignOp::Apply(Op::Sub)),
+                sym("*=").to(AssignOp::Apply(Op::Mul)),
+                sym("/=").to(AssignOp::Apply(Op::Div)),
+                sym("%=").to(AssignOp::Apply(Op::Mod)),
+                sym("??=").to(AssignOp::IfUndefined),
+            ));
+            let step = sym("++").to(Op::Add).or(sym("--").to(Op::Sub));
+            let one = |span| Box::new((Expr::Val(Value::Int(1)), span));
+            let update = choice((
+                expression
+                    .clone()
+                    .then(choice((
+                        assign_op.then(expression.clone()),
+                        step.clone()
+                            .map_with(move |op, e| (AssignOp::Apply(op), one(e.span()))),
+                    )))
+                    .map(|(target, (op, value))| (target, op, value)),
+                step.map_with(move |op, e| (op, one(e.span())))
+                    .then(expression.clone())
+                    .map(|((op, one), target)| (target, AssignOp::Apply(op), one)),
+            ))
+            .validate(|(target, op, value), e, emitter| {
+                match assignment(*target, op, value, e.span()) {
+                    Ok(expr) => expr,
+                    Err(error) => {
+                        emitter.emit(error);
+                        Expr::Error
+                    }
+                }
+            });
+
+            // `from` and `as` are only special here, so they stay usable as
+            // ordinary names
+            let word = |w: &'static str| {
+                select! { Tkn::Identifier(name) if name.as_str() == w => () }.labelled(w)
+            };
+            let path = select! { Tkn::Str(path) => path }.labelled("module path");
+
+            let import = kw("import")
+                .ignore_then(path)
+                .then_ignore(word("as"))
+                .then(ident)
+                .map(|(path, name)| Expr::Import(path, Imports::Module(name, Slot::Global)));
+
+            let from_import = word("from")
+                .ignore_then(path)
+                .then_ignore(kw("import"))
+                .then(
+                    ident
+                        .map(|name| (name, Slot::Global))
+                        .separated_by(sym(","))
+                        .at_least(1)
+                        .collect(),
+                )
+                .map(|(path, names)| Expr::Import(path, Imports::Names(names)));
+
+            let export = kw("export")
+                .ignore_then(
+                    choice((r#let.clone(), r#const.clone(), r#fn.clone()))
+                        .map_with(|expr, e| Box::new((expr, e.span()))),
+                )
+                .map(Expr::Export);
+
+            import
+                .or(from_import)
+                .or(export)
                 .or(r#let)
+                .or(r#const)
                 .or(r#fn)
                 .or(r#return)
                 .or(r#while)
+                .or(r#throw)
+                .or(r#try)
+                .or(update)
                 .map_with(|expr, e| (expr, e.span()))
                 .or(r#if)
                 .map(Box::new)
@@ -159,3 +243,79 @@ where
 
     complex_term.repeated().collect::<Vec<_>>()
 }
+
+// Turns the calls whose value is a function body's result into tail calls:
+// the last expression of the body, through blocks, both branches of an `if`
+// and `return`, and the value of a `return` anywhere else, since that ends
+// the function too. Nothing in a `try` is in tail position, as it still has
+// to handle the call's errors.
+fn mark_tail_calls(body: &mut Spanned<Expr>) {
+    match &mut body.0 {
+        Expr::Call(..) => {
+            if let Expr::Call(callee, args) = std::mem::replace(&mut body.0, Expr::Error) {
+                body.0 = Expr::TailCall(callee, args);
+            }
+        }
+        Expr::Return(value) => mark_tail_calls(value),
+        Expr::Block(stmts) => {
+            if let Some((last, rest)) = stmts.split_last_mut() {
+                rest.iter_mut().for_each(|stmt| mark_returns(stmt));
+                mark_tail_calls(last);
+            }
+        }
+        Expr::If(_, then, r#else) => {
+            mark_tail_calls(then);
+            mark_tail_calls(r#else);
+        }
+        Expr::While(_, body) => mark_returns(body),
+        _ => {}
+    }
+}
+
+// The tail calls in a statement whose own value is not the result
+fn mark_returns(stmt: &mut Spanned<Expr>) {
+    match &mut stmt.0 {
+        Expr::Return(value) => mark_tail_calls(value),
+        Expr::Block(stmts) => stmts.iter_mut().for_each(|stmt| mark_returns(stmt)),
+        Expr::If(_, then, r#else) => {
+            mark_returns(then);
+            mark_returns(r#else);
+        }
+        Expr::While(_, body) => mark_returns(body),
+        _ => {}
+    }
+}
+
+// Assignments to a variable become plain `Assign`s, which read the variable
+// again for its current value; only fields and elements need `Set`.
+fn assignment<'a>(
+    (target, at): Spanned<Expr>,
+    op: AssignOp,
+    value: BSE,
+    span: Span,
+) -> Result<Expr, Rich<'a, Tkn<'a>, Span>> {
+    match target {
+        Expr::Var(name, slot) => {
+            let current = Box::new((Expr::Var(name, slot), at));
+            Ok(match op {
+                AssignOp::Replace => Expr::Assign(name, slot, value),
+                AssignOp::Apply(op) => {
+                    let value = Box::new((Expr::BnOp(current, op, value), span));
+                    Expr::Assign(name, slot, value)
+                }
+                AssignOp::IfUndefined => {
+                    let undefined = || Box::new((Expr::Val(Value::Undefined), span));
+                    let unset = Box::new((Expr::BnOp(current, Op::Eq, undefined()), span));
+                    let assign = Box::new((Expr::Assign(name, slot, value), span));
+                    Expr::If(unset, assign, undefined())
+                }
+            })
+        }
+        Expr::Field(object, name) => Ok(Expr::Set(Place::Field(object, name), op, value)),
+        Expr::Index(object, index) => Ok(Expr::Set(Place::Index(object, index), op, value)),
+        _ => Err(Rich::custom(
+            at,
+            "Only variables, fields and elements can be assigned to.",
+        )),
+    }
+}
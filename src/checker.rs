@@ -0,0 +1,848 @@
+use super::interpreter::operators;
+use super::types::{
+    BSE, Expr, Imports, Op, Place, PropKey, Shared, Signature, Slot, Span, Spanned, Symbol, Type,
+    Value,
+};
+use super::vm::Captures;
+use indexmap::IndexMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+// Silent passes over the program before giving up on the functions whose
+// types are still changing. Each pass feeds the argument types seen at call
+// sites back into the parameter types.
+const INFERENCE_PASSES: usize = 8;
+
+// Static type checking and local inference, run by `pulse check` after the
+// resolver. Annotated code is held to its types. Unannotated bindings take
+// the type of whatever was last assigned to them (widened across branches
+// and loops), and unannotated parameters the types of the arguments passed
+// at direct call sites. Anything that cannot be followed statically is
+// `Any`, which is compatible with every type and is left to the runtime
+// checks below.
+struct Checker {
+    // Innermost last, like the resolver's scopes
+    scopes: Vec<HashMap<Symbol, Var>>,
+    // Index of the first scope belonging to the innermost function
+    fn_base: usize,
+    // Nesting of branches, loop bodies and functions
+    level: usize,
+    // Declared return type and the types actually returned, per function
+    returns: Vec<(Option<Type>, Vec<Type>)>,
+
+    // What the previous pass inferred for each function, keyed by the start
+    // of its declaration, and what this pass is inferring
+    fns: HashMap<usize, Inferred>,
+    next: HashMap<usize, Inferred>,
+    // Functions whose inference did not settle; they are typed `Any`
+    pinned: HashSet<usize>,
+
+    // Bindings assigned anywhere, and those assigned from inside a function
+    // that does not own them, as (name, is global); plus names used as a
+    // value rather than called directly. See `scan`.
+    assigned: HashSet<(Symbol, bool)>,
+    remote: HashSet<(Symbol, bool)>,
+    escaping: HashSet<Symbol>,
+
+    quiet: bool,
+    errors: Vec<Spanned<String>>,
+    types: Vec<(Span, Symbol, Type)>,
+}
+
+struct Var {
+    ty: Type,
+    declared: Option<Type>,
+    level: usize,
+    // Declaration of the function bound by `fn`
+    r#fn: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Inferred {
+    params: Vec<Type>,
+    ret: Type,
+}
+
+/// The outcome of `check`: errors, plus the type inferred for every
+/// binding and function, in source order.
+pub struct Checked {
+    pub errors: Vec<Spanned<String>>,
+    pub types: Vec<(Span, Symbol, Type)>,
+}
+
+/// Where a value meets an annotation, for error messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Boundary {
+    Binding(Symbol),
+    Param(Symbol),
+    Return,
+}
+
+pub fn mismatch(boundary: Boundary, expected: &Type, found: impl fmt::Display) -> String {
+    match boundary {
+        Boundary::Binding(name) => format!(
+            "Type mismatch: '{}' is declared as {}, found {}.",
+            name, expected, found
+        ),
+        Boundary::Param(name) => format!(
+            "Type mismatch: parameter '{}' expects {}, found {}.",
+            name, expected, found
+        ),
+        Boundary::Return => format!(
+            "Type mismatch: expected return type {}, found {}.",
+            expected, found
+        ),
+    }
+}
+
+/// Runtime counterpart of the checker, run wherever an annotated binding,
+/// parameter or return type receives a value.
+pub fn check_value(expected: &Type, value: &Value, boundary: Boundary) -> Option<String> {
+    match expected.admits(value) {
+        true => None,
+        false => Some(mismatch(boundary, expected, Type::of(value))),
+    }
+}
+
+pub fn check(ast: &[BSE]) -> Checked {
+    let mut checker = Checker {
+        scopes: Vec::new(),
+        fn_base: 0,
+        level: 0,
+        returns: Vec::new(),
+        fns: HashMap::new(),
+        next: HashMap::new(),
+        pinned: HashSet::new(),
+        assigned: HashSet::new(),
+        remote: HashSet::new(),
+        escaping: HashSet::new(),
+        quiet: true,
+        errors: Vec::new(),
+        types: Vec::new(),
+    };
+    ast.iter().for_each(|stmt| checker.scan(stmt, None));
+
+    loop {
+        let mut changed = Vec::new();
+        for _ in 0..INFERENCE_PASSES {
+            checker.pass(ast);
+            let next = std::mem::take(&mut checker.next);
+            changed = next
+                .iter()
+                .filter(|(id, inferred)| {
+                    !checker.pinned.contains(id) && checker.fns.get(id) != Some(inferred)
+                })
+                .map(|(id, _)| *id)
+                .collect();
+            checker.fns = next;
+            if changed.is_empty() {
+                break;
+            }
+        }
+        if changed.is_empty() {
+            break;
+        }
+        // Types that keep growing (a function wrapping its own argument in
+        // a list, say) are given up on, and the rest inferred again.
+        checker.pinned.extend(changed);
+    }
+
+    checker.quiet = false;
+    checker.pass(ast);
+
+    checker.errors.sort_by_key(|(_, span)| span.start);
+    checker.types.sort_by_key(|(span, ..)| span.start);
+    Checked {
+        errors: checker.errors,
+        types: checker.types,
+    }
+}
+
+impl Type {
+    /// The type of a runtime value. Lists report the union of their
+    /// elements' types.
+    pub fn of(value: &Value) -> Type {
+        Type::of_seen(value, &mut Vec::new())
+    }
+
+    // `seen` holds the lists `value` is inside of; a list that holds itself
+    // is typed `[any]` where it comes round again.
+    fn of_seen(value: &Value, seen: &mut Vec<*const Shared<Vec<Value>>>) -> Type {
+        match value {
+            Value::Undefined => Type::Undefined,
+            Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) | Value::Num(_) => Type::Num,
+            Value::Str(_) => Type::Str,
+            Value::Bool(_) => Type::Bool,
+            Value::Obj(_) => Type::Obj,
+            Value::Vec(list) if seen.contains(&Rc::as_ptr(list)) => Type::List(Box::new(Type::Any)),
+            Value::Vec(list) => {
+                seen.push(Rc::as_ptr(list));
+                let items = list
+                    .data
+                    .borrow()
+                    .iter()
+                    .map(|item| Type::of_seen(item, seen))
+                    .collect();
+                seen.pop();
+                Type::List(Box::new(Type::union(items)))
+            }
+            Value::Fn(signature, ..) => signature.r#type(),
+            Value::Proto(..) | Value::Builtin(_) | Value::Method(..) => {
+                Type::Fn(Vec::new(), Box::new(Type::Any))
+            }
+        }
+    }
+
+    /// Joins `types` into one, dropping duplicates. No types at all is `Any`;
+    /// nothing but `Never` is `Never`.
+    pub fn union(types: Vec<Type>) -> Type {
+        let mut members: Vec<Type> = Vec::new();
+        let mut never = false;
+        for ty in types {
+            let flat = match ty {
+                Type::Union(inner) => inner,
+                ty => vec![ty],
+            };
+            for ty in flat {
+                match ty {
+                    Type::Any => return Type::Any,
+                    Type::Never => never = true,
+                    ty if !members.contains(&ty) => members.push(ty),
+                    _ => {}
+                }
+            }
+        }
+        match members.len() {
+            0 if never => Type::Never,
+            0 => Type::Any,
+            1 => members.pop().unwrap(),
+            _ => Type::Union(members),
+        }
+    }
+
+    pub fn admits(&self, value: &Value) -> bool {
+        match (self, value) {
+            (Type::Any, _) => true,
+            (Type::Union(types), value) => types.iter().any(|t| t.admits(value)),
+            (Type::Undefined, Value::Undefined)
+            | (Type::Num, Value::Int(_) | Value::BigInt(_) | Value::Decimal(_) | Value::Num(_))
+            | (Type::Str, Value::Str(_))
+            | (Type::Bool, Value::Bool(_))
+            | (Type::Obj, Value::Obj(_)) => true,
+            (Type::List(item), Value::Vec(list)) => {
+                list.data.borrow().iter().all(|v| item.admits(v))
+            }
+            // Only the arity of VM functions is known at runtime, so a
+            // function's own annotations are trusted here.
+            (
+                Type::Fn(..),
+                Value::Fn(..) | Value::Proto(..) | Value::Builtin(_) | Value::Method(..),
+            ) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether a value of type `self` may flow into a slot of type `target`.
+    pub fn assignable(&self, target: &Type) -> bool {
+        match (self, target) {
+            (Type::Any | Type::Never, _) | (_, Type::Any) => true,
+            (Type::Union(types), target) => types.iter().all(|t| t.assignable(target)),
+            (ty, Type::Union(types)) => types.iter().any(|t| ty.assignable(t)),
+            (Type::List(a), Type::List(b)) => a.assignable(b),
+            (Type::Fn(ps, r), Type::Fn(qs, s)) => {
+                ps.len() == qs.len()
+                    && qs.iter().zip(ps).all(|(q, p)| q.assignable(p))
+                    && r.assignable(s)
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    // One value of each type this stands for, to try operators on. `Any`
+    // and `Never` have none.
+    fn samples(&self) -> Option<Vec<Value>> {
+        Some(match self {
+            Type::Any | Type::Never => return None,
+            Type::Undefined => vec![Value::Undefined],
+            Type::Num => vec![Value::Num(1.0)],
+            Type::Str => vec![Value::Str("a".into())],
+            Type::Bool => vec![Value::Bool(true)],
+            Type::Obj => vec![Value::Obj(Shared::new(IndexMap::new()))],
+            Type::List(_) => vec![Value::Vec(Shared::new(Vec::new()))],
+            Type::Fn(..) => vec![Value::Proto(0, Captures::default())],
+            Type::Union(types) => {
+                let mut samples = Vec::new();
+                for ty in types {
+                    samples.extend(ty.samples()?);
+                }
+                samples
+            }
+        })
+    }
+
+    fn items(&self) -> Vec<Type> {
+        match self {
+            Type::List(item) => vec![*item.clone()],
+            Type::Union(types) => types.iter().flat_map(Type::items).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Signature {
+    pub fn r#type(&self) -> Type {
+        let params = self
+            .params
+            .iter()
+            .map(|p| p.ty.clone().unwrap_or(Type::Any))
+            .collect();
+        Type::Fn(params, Box::new(self.ret.clone().unwrap_or(Type::Any)))
+    }
+}
+
+// Result type of applying `op` to operands of the given types, found by
+// running `interpreter::operators` on sample values so the two cannot
+// disagree. Fails only if every combination of operand types fails.
+fn operate(op: &Op, operands: &[&Type]) -> Result<Type, String> {
+    if operands.contains(&&Type::Never) {
+        return Ok(Type::Never);
+    }
+    let samples: Option<Vec<_>> = operands.iter().map(|ty| ty.samples()).collect();
+    let Some(samples) = samples else {
+        return Ok(Type::Any);
+    };
+
+    let combinations: Vec<Vec<Value>> = match samples.as_slice() {
+        [l] => l.iter().map(|l| vec![l.clone()]).collect(),
+        [l, r] => l
+            .iter()
+            .flat_map(|l| r.iter().map(move |r| vec![l.clone(), r.clone()]))
+            .collect(),
+        _ => unreachable!("operators take one or two operands"),
+    };
+
+    let mut results = Vec::new();
+    let mut error = None;
+    for mut values in combinations {
+        let res = match values.len() {
+            1 => operators::unary(op, values.pop().unwrap()),
+            _ => {
+                let r = values.pop().unwrap();
+                operators::binary(op, values.pop().unwrap(), r)
+            }
+        };
+        match res.into_result() {
+            // List results hold the elements of the list operands
+            Ok(Value::Vec(_)) => {
+                let items = operands.iter().flat_map(|ty| ty.items()).collect();
+                results.push(Type::List(Box::new(Type::union(items))));
+            }
+            Ok(value) => results.push(Type::of(&value)),
+            Err(e) => error = Some(e.msg),
+        }
+    }
+
+    match (results.is_empty(), error) {
+        (true, Some(msg)) => Err(msg),
+        _ => Ok(Type::union(results)),
+    }
+}
+
+impl Checker {
+    fn error(&mut self, msg: String, span: Span) {
+        if !self.quiet {
+            self.errors.push((msg, span));
+        }
+    }
+
+    fn expect(&mut self, found: &Type, expected: &Type, boundary: Boundary, span: Span) {
+        if !found.assignable(expected) {
+            self.error(mismatch(boundary, expected, found), span);
+        }
+    }
+
+    fn pass(&mut self, ast: &[BSE]) {
+        self.scopes = vec![HashMap::new()];
+        self.types.clear();
+        self.hoist(ast);
+        ast.iter().for_each(|stmt| {
+            self.expr(stmt);
+        });
+    }
+
+    // Collects the bindings `read` has to be careful with. `scopes` counts
+    // the resolver scopes opened since entering the innermost function, so a
+    // local slot at least that deep belongs to an enclosing one.
+    fn scan(&mut self, expr: &BSE, scopes: Option<usize>) {
+        let inner = scopes.map(|n| n + 1);
+        match &expr.0 {
+            Expr::Assign(name, slot, value) => {
+                let global = *slot == Slot::Global;
+                self.assigned.insert((*name, global));
+                let remote = match (slot, scopes) {
+                    (_, None) => false,
+                    (Slot::Global, Some(_)) => true,
+                    (Slot::Local(depth, _), Some(n)) => *depth >= n,
+                };
+                if remote {
+                    self.remote.insert((*name, global));
+                }
+                self.scan(value, scopes);
+            }
+            Expr::Var(name, _) => {
+                self.escaping.insert(*name);
+            }
+            Expr::Call(callee, args) | Expr::TailCall(callee, args) => {
+                if !matches!(callee.0, Expr::Var(..)) {
+                    self.scan(callee, scopes);
+                }
+                args.iter().for_each(|arg| self.scan(arg, scopes));
+            }
+            Expr::Fn(_, _, _, body) => self.scan(body, Some(1)),
+            Expr::Let(_, _, _, value) | Expr::Const(_, _, _, value) => self.scan(value, scopes),
+            Expr::UnOp(_, value)
+            | Expr::Return(value)
+            | Expr::Field(value, _)
+            | Expr::Throw(value) => self.scan(value, scopes),
+            Expr::Export(decl) => self.scan(decl, scopes),
+            Expr::BnOp(left, _, right)
+            | Expr::Index(left, right)
+            | Expr::Set(Place::Field(left, _), _, right) => {
+                self.scan(left, scopes);
+                self.scan(right, scopes);
+            }
+            Expr::Set(Place::Index(object, index), _, value) => {
+                self.scan(object, scopes);
+                self.scan(index, scopes);
+                self.scan(value, scopes);
+            }
+            Expr::While(cond, body) => {
+                self.scan(cond, scopes);
+                self.scan(body, inner);
+            }
+            Expr::If(cond, then, els) => {
+                self.scan(cond, scopes);
+                self.scan(then, inner);
+                self.scan(els, inner);
+            }
+            Expr::Try(body, catch, finally) => {
+                self.scan(body, inner);
+                if let Some((_, _, handler)) = catch {
+                    self.scan(handler, inner);
+                }
+                if let Some(finally) = finally {
+                    self.scan(finally, inner);
+                }
+            }
+            Expr::Block(items) | Expr::List(items) | Expr::ReservedCall(_, items) => {
+                items.iter().for_each(|item| self.scan(item, scopes))
+            }
+            Expr::Obj(entries) => {
+                for (key, value) in entries {
+                    if let PropKey::Computed(key) = key {
+                        self.scan(key, scopes);
+                    }
+                    self.scan(value, scopes);
+                }
+            }
+            Expr::Val(_)
+            | Expr::Error
+            | Expr::Comment(_)
+            | Expr::Nl
+            | Expr::ReservedVar(_)
+            | Expr::Import(..) => {}
+        }
+    }
+
+    fn reassigned(&self, name: Symbol) -> bool {
+        self.assigned.contains(&(name, true)) || self.assigned.contains(&(name, false))
+    }
+
+    fn bind(&mut self, name: Symbol, ty: Type, declared: Option<Type>, r#fn: Option<usize>) {
+        let var = Var {
+            ty,
+            declared,
+            level: self.level,
+            r#fn,
+        };
+        self.scopes.last_mut().unwrap().insert(name, var);
+    }
+
+    fn find(&self, name: Symbol) -> Option<(usize, &Var)> {
+        self.scopes
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, scope)| scope.get(&name).map(|var| (i, var)))
+    }
+
+    fn read(&self, name: Symbol) -> Type {
+        match self.find(name) {
+            Some((_, var)) if var.declared.is_some() => var.ty.clone(),
+            // A function may run at any point, so neither what it assigns nor
+            // what it reads from outside can be followed.
+            Some((i, _)) if self.remote.contains(&(name, i == 0)) => Type::Any,
+            Some((i, _)) if i < self.fn_base && self.assigned.contains(&(name, i == 0)) => {
+                Type::Any
+            }
+            Some((_, var)) => var.ty.clone(),
+            None => Type::Any,
+        }
+    }
+
+    // The type of a function declaration as of the previous pass
+    fn signature(&self, id: usize, name: Symbol, signature: &Signature) -> (Vec<Type>, Type) {
+        let inferred = self.fns.get(&id);
+        let pinned = self.pinned.contains(&id);
+        let fixed = pinned || self.escaping.contains(&name) || self.reassigned(name);
+        let params = signature
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| match (&param.ty, inferred) {
+                (Some(ty), _) => ty.clone(),
+                (None, _) if fixed => Type::Any,
+                (None, Some(inferred)) => match inferred.params.get(i) {
+                    // Never called; nothing is known about the arguments
+                    Some(Type::Never) if !self.quiet => Type::Any,
+                    Some(ty) => ty.clone(),
+                    None => Type::Any,
+                },
+                (None, None) => Type::Never,
+            })
+            .collect();
+        let ret = match (&signature.ret, inferred) {
+            (Some(ty), _) => ty.clone(),
+            (None, _) if pinned => Type::Any,
+            (None, Some(inferred)) => inferred.ret.clone(),
+            (None, None) => Type::Never,
+        };
+        (params, ret)
+    }
+
+    // Functions may be called before their declaration, so every function
+    // in a block is bound before the block is checked.
+    fn hoist(&mut self, stmts: &[BSE]) {
+        for stmt in stmts {
+            let stmt = match &stmt.0 {
+                Expr::Export(decl) => decl,
+                _ => stmt,
+            };
+            if let Expr::Fn(name, _, signature, _) = &stmt.0 {
+                let id = stmt.1.start;
+                let (params, ret) = self.signature(id, *name, signature);
+                self.bind(*name, Type::Fn(params, Box::new(ret)), None, Some(id));
+            }
+        }
+    }
+
+    fn scoped(&mut self, expr: &BSE) -> Type {
+        self.scopes.push(HashMap::new());
+        self.level += 1;
+        let ty = self.expr(expr);
+        self.level -= 1;
+        self.scopes.pop();
+        ty
+    }
+
+    fn expr(&mut self, expr: &BSE) -> Type {
+        let span = expr.1;
+        match &expr.0 {
+            Expr::Val(value) => Type::of(value),
+            Expr::List(items) => {
+                let items = items.iter().map(|item| self.expr(item)).collect();
+                Type::List(Box::new(Type::union(items)))
+            }
+            Expr::Obj(entries) => {
+                for (key, value) in entries {
+                    if let PropKey::Computed(key) = key {
+                        self.expr(key);
+                    }
+                    self.expr(value);
+                }
+                Type::Obj
+            }
+
+            Expr::Var(name, _) => self.read(*name),
+
+            Expr::Let(name, _, ty, value) | Expr::Const(name, _, ty, value) => {
+                let found = self.expr(value);
+                let bound = match ty {
+                    Some(ty) => {
+                        self.expect(&found, ty, Boundary::Binding(*name), value.1);
+                        ty.clone()
+                    }
+                    None => found,
+                };
+                if !self.quiet {
+                    self.types.push((span, *name, bound.clone()));
+                }
+                self.bind(*name, bound, ty.clone(), None);
+                Type::Undefined
+            }
+
+            Expr::Assign(name, _, value) => {
+                let found = self.expr(value);
+                let level = self.level;
+                let declared = match self.scopes.iter_mut().rev().find_map(|s| s.get_mut(name)) {
+                    Some(var) if var.declared.is_some() => var.declared.clone(),
+                    // Only an assignment at the binding's own level replaces
+                    // its type; one in a branch or loop may not happen.
+                    Some(var) if var.level == level => {
+                        var.ty = found.clone();
+                        None
+                    }
+                    Some(var) => {
+                        var.ty = Type::union(vec![var.ty.clone(), found.clone()]);
+                        None
+                    }
+                    None => None,
+                };
+                if let Some(declared) = declared {
+                    self.expect(&found, &declared, Boundary::Binding(*name), span);
+                }
+                Type::Undefined
+            }
+
+            Expr::BnOp(left, op, right) => {
+                let l = self.expr(left);
+                let r = self.expr(right);
+                match op {
+                    // Logic and comparison accept any operands
+                    Op::And | Op::Or | Op::Eq | Op::Neq | Op::Gt | Op::Gte | Op::Lt | Op::Lte => {
+                        Type::Bool
+                    }
+                    op => self.operate(op, &[&l, &r], span),
+                }
+            }
+            Expr::UnOp(op, value) => {
+                let ty = self.expr(value);
+                self.operate(op, &[&ty], span)
+            }
+
+            Expr::Block(stmts) => {
+                self.hoist(stmts);
+                let mut ty = Type::Undefined;
+                for stmt in stmts {
+                    ty = self.expr(stmt);
+                }
+                ty
+            }
+
+            Expr::Fn(name, _, signature, body) => {
+                let id = span.start;
+                let (params, ret) = self.signature(id, *name, signature);
+                let ty = Type::Fn(params.clone(), Box::new(ret));
+                self.bind(*name, ty, None, Some(id));
+
+                let fn_base = std::mem::replace(&mut self.fn_base, self.scopes.len());
+                self.scopes.push(HashMap::new());
+                self.level += 1;
+                for (param, ty) in signature.params.iter().zip(&params) {
+                    self.bind(param.name, ty.clone(), param.ty.clone(), None);
+                }
+                self.returns.push((signature.ret.clone(), Vec::new()));
+                let found = self.expr(body);
+                let (_, mut returned) = self.returns.pop().unwrap();
+                self.level -= 1;
+                self.scopes.pop();
+                self.fn_base = fn_base;
+
+                if let Some(ret) = &signature.ret {
+                    self.expect(&found, ret, Boundary::Return, body.1);
+                }
+                returned.push(found);
+                let inferred = Inferred {
+                    // Filled in from call sites, see `Expr::Call`
+                    params: self
+                        .next
+                        .get(&id)
+                        .map(|i| i.params.clone())
+                        .unwrap_or_else(|| vec![Type::Never; params.len()]),
+                    ret: Type::union(returned),
+                };
+
+                if !self.quiet {
+                    let ret = signature.ret.clone().unwrap_or(inferred.ret.clone());
+                    self.types
+                        .push((span, *name, Type::Fn(params, Box::new(ret))));
+                }
+                self.next.insert(id, inferred);
+                Type::Undefined
+            }
+
+            Expr::Return(value) => {
+                let found = self.expr(value);
+                if let Some((declared, returned)) = self.returns.last_mut() {
+                    returned.push(found.clone());
+                    if let Some(ret) = declared.clone() {
+                        self.expect(&found, &ret, Boundary::Return, value.1);
+                    }
+                }
+                // The value leaves through the function's result, so the
+                // enclosing body neither reports nor infers it again.
+                Type::Never
+            }
+
+            Expr::Call(callee, args) | Expr::TailCall(callee, args) => {
+                let callee_ty = self.expr(callee);
+                let found: Vec<_> = args.iter().map(|arg| (self.expr(arg), arg.1)).collect();
+                match callee_ty {
+                    Type::Fn(params, ret) => {
+                        // Missing arguments are passed as undefined
+                        let found: Vec<_> = (0..params.len())
+                            .map(|i| found.get(i).cloned().unwrap_or((Type::Undefined, span)))
+                            .collect();
+                        for (i, (expected, (found, span))) in params.iter().zip(&found).enumerate()
+                        {
+                            if !found.assignable(expected) {
+                                let msg = format!(
+                                    "Type mismatch: argument {} expects {}, found {}.",
+                                    i + 1,
+                                    expected,
+                                    found
+                                );
+                                self.error(msg, *span);
+                            }
+                        }
+                        if let Expr::Var(name, _) = &callee.0 {
+                            let types = found.into_iter().map(|(ty, _)| ty).collect();
+                            self.called(*name, types);
+                        }
+                        *ret
+                    }
+                    Type::Never => Type::Never,
+                    Type::Any | Type::Union(_) => Type::Any,
+                    other => {
+                        self.error(
+                            format!("Type mismatch: {} is not a function.", other),
+                            callee.1,
+                        );
+                        Type::Any
+                    }
+                }
+            }
+
+            Expr::ReservedCall(name, args) => {
+                let types: Vec<_> = args.iter().map(|arg| self.expr(arg)).collect();
+                match name.as_str() {
+                    "freeze" => types.into_iter().next().unwrap_or(Type::Undefined),
+                    "print" => Type::Undefined,
+                    "range" => Type::List(Box::new(Type::Num)),
+                    _ => Type::Any,
+                }
+            }
+
+            Expr::If(cond, then, els) => {
+                self.expr(cond);
+                let then = self.scoped(then);
+                let els = self.scoped(els);
+                Type::union(vec![then, els])
+            }
+
+            Expr::While(cond, body) => {
+                // A first, silent run widens whatever the body assigns, so
+                // the second sees the types of later iterations too.
+                let quiet = std::mem::replace(&mut self.quiet, true);
+                self.expr(cond);
+                self.scoped(body);
+                self.quiet = quiet;
+
+                self.expr(cond);
+                let body = self.scoped(body);
+                // The loop's value is its last iteration's, if any ran
+                Type::union(vec![Type::Undefined, body])
+            }
+
+            Expr::Throw(value) => {
+                self.expr(value);
+                Type::Never
+            }
+            // The body may stop anywhere, so its value or the handler's
+            Expr::Try(body, catch, finally) => {
+                let mut types = vec![self.scoped(body)];
+                if let Some((name, _, handler)) = catch {
+                    self.scopes.push(HashMap::new());
+                    self.level += 1;
+                    self.bind(*name, Type::Obj, None, None);
+                    types.push(self.expr(handler));
+                    self.level -= 1;
+                    self.scopes.pop();
+                }
+                if let Some(finally) = finally {
+                    self.scoped(finally);
+                }
+                Type::union(types)
+            }
+
+            // Other modules are not checked, so nothing is known about what
+            // they export
+            Expr::Import(_, imports) => {
+                match imports {
+                    Imports::Module(name, _) => self.bind(*name, Type::Any, None, None),
+                    Imports::Names(names) => {
+                        for (name, _) in names {
+                            self.bind(*name, Type::Any, None, None);
+                        }
+                    }
+                }
+                Type::Undefined
+            }
+            Expr::Export(decl) => self.expr(decl),
+            Expr::Field(object, _) => {
+                self.expr(object);
+                Type::Any
+            }
+            Expr::Index(object, index) => {
+                self.expr(object);
+                self.expr(index);
+                Type::Any
+            }
+            // Fields and elements are not tracked, so any value will do
+            Expr::Set(place, _, value) => {
+                match place {
+                    Place::Field(object, _) => {
+                        self.expr(object);
+                    }
+                    Place::Index(object, index) => {
+                        self.expr(object);
+                        self.expr(index);
+                    }
+                }
+                self.expr(value);
+                Type::Undefined
+            }
+
+            Expr::Error | Expr::Comment(_) | Expr::Nl | Expr::ReservedVar(_) => Type::Any,
+        }
+    }
+
+    fn operate(&mut self, op: &Op, operands: &[&Type], span: Span) -> Type {
+        match operate(op, operands) {
+            Ok(ty) => ty,
+            Err(msg) => {
+                self.error(msg, span);
+                Type::Any
+            }
+        }
+    }
+
+    // Records the argument types of a direct call to a `fn` declaration
+    fn called(&mut self, name: Symbol, args: Vec<Type>) {
+        let Some((_, Var { r#fn: Some(id), .. })) = self.find(name) else {
+            return;
+        };
+        if self.reassigned(name) {
+            return;
+        }
+        let id = *id;
+        let entry = self.next.entry(id).or_insert_with(|| Inferred {
+            params: vec![Type::Never; args.len()],
+            ret: Type::Never,
+        });
+        for (param, arg) in entry.params.iter_mut().zip(args) {
+            *param = Type::union(vec![param.clone(), arg]);
+        }
+    }
+}
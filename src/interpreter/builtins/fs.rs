@@ -0,0 +1,102 @@
+use super::super::super::types::{Shared, Value};
+use super::super::Error;
+use super::{Call, func, run, str};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+
+// Paths are relative to the working directory. Failures are runtime errors
+// naming the function and the path.
+pub const FUNCTIONS: &[&str] = &[
+    "read",
+    "write",
+    "append",
+    "exists",
+    "list_dir",
+    "mkdir",
+    "remove",
+    "each_line",
+];
+
+/// Runs `fs.<f>`. `each_line` calls its function with each line and its
+/// index.
+pub fn call(f: &str, args: &[Value], call: Call) -> Result<Value, Error> {
+    let name = format!("fs.{}", f);
+    let path = str(&name, args, 0)?;
+    let failed = |e: io::Error| {
+        let msg = format!("{}: cannot {} '{}': {}.", name, verb(f), path, e);
+        Error::new("IOError", msg)
+    };
+    Ok(match f {
+        "read" => Value::Str(fs::read_to_string(&path).map_err(failed)?),
+        // Both create the file if it is missing
+        "write" => {
+            fs::write(&path, str(&name, args, 1)?).map_err(failed)?;
+            Value::Undefined
+        }
+        "append" => {
+            let text = str(&name, args, 1)?;
+            fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .and_then(|mut file| file.write_all(text.as_bytes()))
+                .map_err(failed)?;
+            Value::Undefined
+        }
+        "exists" => Value::Bool(fs::exists(&path).map_err(failed)?),
+        // Entry names, sorted
+        "list_dir" => {
+            let mut names = Vec::new();
+            for entry in fs::read_dir(&path).map_err(failed)? {
+                let entry = entry.map_err(failed)?;
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+            names.sort();
+            let names = names.into_iter().map(Value::Str).collect();
+            Value::Vec(Shared::new(names))
+        }
+        // Creates missing parents too; an existing directory is fine
+        "mkdir" => {
+            fs::create_dir_all(&path).map_err(failed)?;
+            Value::Undefined
+        }
+        // A file, or a directory that is empty
+        "remove" => {
+            let removed = match fs::metadata(&path) {
+                Ok(meta) if meta.is_dir() => fs::remove_dir(&path),
+                _ => fs::remove_file(&path),
+            };
+            removed.map_err(failed)?;
+            Value::Undefined
+        }
+        // Reads one line at a time, so the file never has to fit in memory.
+        // The function returning `false` stops early. Gives the number of
+        // lines read.
+        "each_line" => {
+            let callback = func(&name, args, 1)?;
+            let file = fs::File::open(&path).map_err(failed)?;
+            let mut count = 0;
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(failed)?;
+                let args = vec![Value::Str(line), Value::Int(count as i64)];
+                count += 1;
+                if run(call, &callback, args)? == Value::Bool(false) {
+                    break;
+                }
+            }
+            Value::Int(count as i64)
+        }
+        _ => return Err(format!("Unknown builtin '{}'.", name).into()),
+    })
+}
+
+fn verb(f: &str) -> &'static str {
+    match f {
+        "read" | "each_line" => "read",
+        "write" | "append" => "write",
+        "exists" => "check",
+        "list_dir" => "list",
+        "mkdir" => "create",
+        _ => "remove",
+    }
+}
@@ -0,0 +1,318 @@
+use super::super::super::types::{List, Shared, Value};
+use super::super::Error;
+use super::{Call, func, index, int, run, str};
+use std::cmp::Ordering;
+
+pub const METHODS: &[&str] = &[
+    "len",
+    "push",
+    "pop",
+    "insert",
+    "remove",
+    "slice",
+    "reverse",
+    "sort",
+    "map",
+    "filter",
+    "reduce",
+    "find",
+    "any",
+    "all",
+    "zip",
+    "enumerate",
+    "flatten",
+    "unique",
+    "join",
+];
+
+/// Runs the method `f` of `list`. Callbacks get each item and its index.
+pub fn call(f: &str, list: &List, args: &[Value], call: Call) -> Result<Value, Error> {
+    let name = format!("list.{}", f);
+    // Callbacks may change the list, so they run on a copy
+    let items = || list.data.borrow().clone();
+    Ok(match f {
+        "len" => Value::Int(list.data.borrow().len() as i64),
+
+        // These change the list in place
+        "push" => {
+            let mut data = mutable(&name, list)?;
+            data.extend(args.iter().cloned());
+            Value::Int(data.len() as i64)
+        }
+        "pop" => mutable(&name, list)?.pop().unwrap_or(Value::Undefined),
+        "insert" => {
+            let i = int(&name, args, 0)?;
+            let mut data = mutable(&name, list)?;
+            let i = index(i, data.len());
+            data.insert(i, args.get(1).cloned().unwrap_or(Value::Undefined));
+            Value::Undefined
+        }
+        "remove" => {
+            let i = int(&name, args, 0)?;
+            let mut data = mutable(&name, list)?;
+            let len = data.len() as f64;
+            if i >= len || i < -len {
+                return Err(
+                    format!("{}: index {} is out of range for {} items.", name, i, len).into(),
+                );
+            }
+            let i = index(i, data.len());
+            data.remove(i)
+        }
+        "reverse" => {
+            mutable(&name, list)?.reverse();
+            Value::Vec(list.clone())
+        }
+        // Without a comparator, numbers and strings sort in ascending order.
+        // A comparator returns a negative number, zero or a positive one.
+        "sort" => {
+            mutable(&name, list)?;
+            let sorted = match args.first() {
+                None | Some(Value::Undefined) => merge_sort(items(), &mut |a, b| {
+                    a.partial_cmp(b).ok_or_else(|| {
+                        let (a, b) = (a.type_name(), b.type_name());
+                        format!("{} cannot compare {} with {}.", name, a, b).into()
+                    })
+                })?,
+                Some(_) => {
+                    let cmp = func(&name, args, 0)?;
+                    merge_sort(items(), &mut |a, b| match run(
+                        call,
+                        &cmp,
+                        vec![a.clone(), b.clone()],
+                    )? {
+                        n if n.as_f64().is_some() => {
+                            Ok(n.partial_cmp(&Value::Int(0)).unwrap_or(Ordering::Equal))
+                        }
+                        other => Err(format!(
+                            "{} expects the comparator to return a Num, found {}.",
+                            name,
+                            other.type_name()
+                        )
+                        .into()),
+                    })?
+                }
+            };
+            *mutable(&name, list)? = sorted;
+            Value::Vec(list.clone())
+        }
+
+        // These leave the list as it is
+        "slice" => {
+            let items = items();
+            let start = index(int(&name, args, 0)?, items.len());
+            let end = match args.get(1) {
+                None | Some(Value::Undefined) => items.len(),
+                Some(_) => index(int(&name, args, 1)?, items.len()),
+            };
+            new(items[start..end.max(start)].to_vec())
+        }
+        "map" => {
+            let callback = func(&name, args, 0)?;
+            let mut out = Vec::new();
+            for (i, item) in items().iter().enumerate() {
+                out.push(each(call, &callback, item, i)?);
+            }
+            new(out)
+        }
+        "filter" => {
+            let callback = func(&name, args, 0)?;
+            let mut out = Vec::new();
+            for (i, item) in items().into_iter().enumerate() {
+                if each(call, &callback, &item, i)?.is_truthy() {
+                    out.push(item);
+                }
+            }
+            new(out)
+        }
+        // Without an initial value, the first item is one
+        "reduce" => {
+            let callback = func(&name, args, 0)?;
+            let items = items();
+            let (mut acc, skip) = match args.get(1) {
+                Some(init) => (init.clone(), 0),
+                None => match items.first() {
+                    Some(first) => (first.clone(), 1),
+                    None => {
+                        let msg = format!("{} of an empty list needs an initial value.", name);
+                        return Err(msg.into());
+                    }
+                },
+            };
+            for (i, item) in items.iter().enumerate().skip(skip) {
+                let args = vec![acc, item.clone(), Value::Int(i as i64)];
+                acc = run(call, &callback, args)?;
+            }
+            acc
+        }
+        "find" | "any" | "all" => {
+            let callback = func(&name, args, 0)?;
+            for (i, item) in items().into_iter().enumerate() {
+                match (f, each(call, &callback, &item, i)?.is_truthy()) {
+                    ("find", true) => return Ok(item),
+                    ("any", true) => return Ok(Value::Bool(true)),
+                    ("all", false) => return Ok(Value::Bool(false)),
+                    _ => {}
+                }
+            }
+            match f {
+                "find" => Value::Undefined,
+                _ => Value::Bool(f == "all"),
+            }
+        }
+        // Pairs up items at the same position, as long as every list has one
+        "zip" => {
+            let mut lists = vec![items()];
+            for (i, arg) in args.iter().enumerate() {
+                match arg {
+                    Value::Vec(other) => lists.push(other.data.borrow().clone()),
+                    other => {
+                        return Err(format!(
+                            "{} expects a Vec as argument {}, found {}.",
+                            name,
+                            i + 1,
+                            other.type_name()
+                        )
+                        .into());
+                    }
+                }
+            }
+            let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+            new((0..len)
+                .map(|i| new(lists.iter().map(|list| list[i].clone()).collect()))
+                .collect())
+        }
+        "enumerate" => new(items()
+            .into_iter()
+            .enumerate()
+            .map(|(i, item)| new(vec![Value::Int(i as i64), item]))
+            .collect()),
+        // Only one level deep
+        "flatten" => {
+            let mut out = Vec::new();
+            for item in items() {
+                match item {
+                    Value::Vec(inner) => out.extend(inner.data.borrow().iter().cloned()),
+                    item => out.push(item),
+                }
+            }
+            new(out)
+        }
+        // Keeps the first of each group of equal items
+        "unique" => {
+            let mut out: Vec<Value> = Vec::new();
+            for item in items() {
+                if !out.contains(&item) {
+                    out.push(item);
+                }
+            }
+            new(out)
+        }
+        // Items are written as `print` would; the separator defaults to none
+        "join" => {
+            let sep = match args.first() {
+                None | Some(Value::Undefined) => String::new(),
+                Some(_) => str(&name, args, 0)?,
+            };
+            let items: Vec<_> = items().iter().map(|item| item.to_string()).collect();
+            Value::Str(items.join(&sep))
+        }
+        _ => return Err(format!("Unknown builtin '{}'.", name).into()),
+    })
+}
+
+/// `range(end)`, `range(start, end)` or `range(start, end, step)`: the
+/// numbers from `start` (0 by default) up to but not including `end`. They
+/// are `Int`s if every argument is.
+pub fn range(args: &[Value]) -> Result<Value, Error> {
+    if let Some(ints) = super::ints(args).filter(|ints| !ints.is_empty()) {
+        let (start, end) = match ints[..] {
+            [end] => (0, end),
+            [start, end, ..] => (start, end),
+            [] => unreachable!(),
+        };
+        let step = ints.get(2).copied().unwrap_or(1);
+        if step == 0 {
+            return Err("range: step cannot be 0.".into());
+        }
+        let mut out = Vec::new();
+        let mut i = start;
+        while (step > 0 && i < end) || (step < 0 && i > end) {
+            out.push(Value::Int(i));
+            match i.checked_add(step) {
+                Some(next) => i = next,
+                None => break,
+            }
+        }
+        return Ok(new(out));
+    }
+
+    // Bounds and steps that are not finite would never run out
+    let n = |i| match super::num("range", args, i)? {
+        n if n.is_finite() => Ok(n),
+        n => {
+            let msg = format!("range expects finite numbers, found {}.", n);
+            Err(Error::new("TypeError", msg))
+        }
+    };
+    let (start, end) = match args.len() {
+        0 | 1 => (0.0, n(0)?),
+        _ => (n(0)?, n(1)?),
+    };
+    let step = if args.len() > 2 { n(2)? } else { 1.0 };
+    if step == 0.0 {
+        return Err("range: step cannot be 0.".into());
+    }
+    let mut out = Vec::new();
+    let mut i = start;
+    while (step > 0.0 && i < end) || (step < 0.0 && i > end) {
+        out.push(Value::Num(i));
+        i += step;
+    }
+    Ok(new(out))
+}
+
+fn each(call: Call, callback: &Value, item: &Value, i: usize) -> Result<Value, Error> {
+    run(call, callback, vec![item.clone(), Value::Int(i as i64)])
+}
+
+fn new(items: Vec<Value>) -> Value {
+    Value::Vec(Shared::new(items))
+}
+
+fn mutable<'a>(name: &str, list: &'a List) -> Result<std::cell::RefMut<'a, Vec<Value>>, Error> {
+    match list.frozen.get() {
+        true => {
+            let msg = format!("{}: cannot change a frozen list.", name);
+            Err(Error::new("TypeError", msg))
+        }
+        false => Ok(list.data.borrow_mut()),
+    }
+}
+
+// A stable sort that stops at the first failed comparison. Unlike
+// `slice::sort_by`, it copes with comparators that are not consistent.
+fn merge_sort(
+    mut items: Vec<Value>,
+    cmp: &mut dyn FnMut(&Value, &Value) -> Result<Ordering, Error>,
+) -> Result<Vec<Value>, Error> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, cmp)?;
+    let right = merge_sort(right, cmp)?;
+
+    let mut out = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        match cmp(l, r)? {
+            Ordering::Greater => out.push(right.next().unwrap()),
+            _ => out.push(left.next().unwrap()),
+        }
+    }
+    out.extend(left);
+    out.extend(right);
+    Ok(out)
+}
@@ -0,0 +1,127 @@
+use super::super::super::types::Value;
+use super::super::Error;
+use super::{int, ints, num};
+use num_bigint::BigInt;
+use num_traits::Signed;
+use std::f64::consts;
+
+pub const FUNCTIONS: &[&str] = &[
+    "abs", "floor", "ceil", "round", "trunc", "sign", "sqrt", "cbrt", "pow", "exp", "ln", "log",
+    "log2", "log10", "sin", "cos", "tan", "asin", "acos", "atan", "atan2", "hypot", "min", "max",
+    "clamp", "gcd", "is_int",
+];
+
+pub const CONSTANTS: &[(&str, f64)] = &[
+    ("pi", consts::PI),
+    ("e", consts::E),
+    ("inf", f64::INFINITY),
+    ("nan", f64::NAN),
+];
+
+/// Runs `math.<f>`.
+pub fn call(f: &str, args: &[Value]) -> Result<Value, Error> {
+    let name = format!("math.{}", f);
+    if let Some(result) = whole(f, args) {
+        return Ok(result);
+    }
+    let x = |i| num(&name, args, i);
+    let n = match f {
+        "abs" => x(0)?.abs(),
+        // These give `Int`s where the result fits in one
+        "floor" => return Ok(int_or_num(x(0)?.floor())),
+        "ceil" => return Ok(int_or_num(x(0)?.ceil())),
+        // Halves round away from zero
+        "round" => return Ok(int_or_num(x(0)?.round())),
+        "trunc" => return Ok(int_or_num(x(0)?.trunc())),
+        "sign" => match x(0)? {
+            0.0 => 0.0,
+            n => n.signum(),
+        },
+        "sqrt" => x(0)?.sqrt(),
+        "cbrt" => x(0)?.cbrt(),
+        "pow" => x(0)?.powf(x(1)?),
+        "exp" => x(0)?.exp(),
+        "ln" => x(0)?.ln(),
+        // Base 10 unless a base is given
+        "log" if args.len() > 1 => x(0)?.log(x(1)?),
+        "log" | "log10" => x(0)?.log10(),
+        "log2" => x(0)?.log2(),
+        "sin" => x(0)?.sin(),
+        "cos" => x(0)?.cos(),
+        "tan" => x(0)?.tan(),
+        "asin" => x(0)?.asin(),
+        "acos" => x(0)?.acos(),
+        "atan" => x(0)?.atan(),
+        "atan2" => x(0)?.atan2(x(1)?),
+        "hypot" => x(0)?.hypot(x(1)?),
+        "min" | "max" => {
+            if args.is_empty() {
+                return Err(format!("{} expects at least one argument.", name).into());
+            }
+            let mut result = x(0)?;
+            for i in 1..args.len() {
+                result = match f {
+                    "min" => result.min(x(i)?),
+                    _ => result.max(x(i)?),
+                };
+            }
+            result
+        }
+        "clamp" => {
+            let (value, low, high) = (x(0)?, x(1)?, x(2)?);
+            // `f64::clamp` panics on these, NaN bounds included
+            if low > high || low.is_nan() || high.is_nan() {
+                return Err(format!(
+                    "math.clamp: lower bound {} is above upper bound {}.",
+                    low, high
+                )
+                .into());
+            }
+            value.clamp(low, high)
+        }
+        "gcd" => {
+            let (mut a, mut b) = (int(&name, args, 0)?.abs(), int(&name, args, 1)?.abs());
+            while b != 0.0 {
+                (a, b) = (b, a % b);
+            }
+            return Ok(int_or_num(a));
+        }
+        "is_int" => return Ok(Value::Bool(x(0)?.fract() == 0.0)),
+        _ => return Err(format!("Unknown builtin '{}'.", name).into()),
+    };
+    Ok(Value::Num(n))
+}
+
+// `math.<f>` of `Int` arguments, where it is a whole number too and so can
+// be worked out without going through floats
+fn whole(f: &str, args: &[Value]) -> Option<Value> {
+    let ints = ints(args)?;
+    Some(Value::Int(match (f, &ints[..]) {
+        // The one `Int` without a positive counterpart has a `BigInt` one
+        ("abs", [n, ..]) => return Some(Value::big(BigInt::from(*n).abs())),
+        ("floor" | "ceil" | "round" | "trunc", [n, ..]) => *n,
+        ("sign", [n, ..]) => n.signum(),
+        ("min", [_, ..]) => *ints.iter().min().unwrap(),
+        ("max", [_, ..]) => *ints.iter().max().unwrap(),
+        ("clamp", [n, low, high, ..]) if low <= high => *n.clamp(low, high),
+        ("gcd", [a, b, ..]) => {
+            let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+            while b != 0 {
+                (a, b) = (b, a % b);
+            }
+            return Some(Value::big(a.into()));
+        }
+        ("is_int", [_, ..]) => return Some(Value::Bool(true)),
+        _ => return None,
+    }))
+}
+
+// A whole float that fits in an `Int` as one
+fn int_or_num(n: f64) -> Value {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+    if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(&n) {
+        Value::Int(n as i64)
+    } else {
+        Value::Num(n)
+    }
+}
@@ -0,0 +1,187 @@
+use super::super::types::{Key, Shared, Symbol, Value};
+use super::{Error, Res};
+
+mod fs;
+mod list;
+mod math;
+mod object;
+mod string;
+
+thread_local! {
+    static MATH: Value = module("math", math::FUNCTIONS, math::CONSTANTS);
+    static OBJECT: Value = module("object", object::FUNCTIONS, &[]);
+    static FS: Value = module("fs", fs::FUNCTIONS, &[]);
+}
+
+/// How builtins call the Pulse functions they are given: the tree-walker
+/// and the VM each pass one that runs a function value on arguments.
+pub type Call<'a> = &'a mut dyn FnMut(Value, Vec<Value>) -> Res;
+
+/// Runs a builtin other than `print` on its already evaluated arguments.
+/// Shared by the tree-walker and the bytecode VM.
+pub fn call(name: Symbol, args: Vec<Value>) -> Res {
+    let result = match name.as_str() {
+        "freeze" => {
+            let value = args.into_iter().next().unwrap_or(Value::Undefined);
+            freeze(&value);
+            Ok(value)
+        }
+        "range" => list::range(&args),
+        name => match name.split_once('.') {
+            Some(("math", f)) => math::call(f, &args),
+            _ => Err(format!("Unknown builtin '{}'.", name).into()),
+        },
+    };
+    match result {
+        Ok(value) => Res::V(value),
+        Err(e) => Res::E(e),
+    }
+}
+
+/// Calls a `Value::Builtin` or `Value::Method`.
+pub fn native(callee: Value, args: Vec<Value>, call: Call) -> Res {
+    let result = match callee {
+        // Only these take callbacks
+        Value::Builtin(name) => match name.as_str().split_once('.') {
+            Some(("object", f)) => object::call(f, &args, call),
+            Some(("fs", f)) => fs::call(f, &args, call),
+            _ => return self::call(name, args),
+        },
+        Value::Method(this, f) => match *this {
+            Value::Str(s) => string::call(f.as_str(), &s, &args),
+            Value::Vec(list) => list::call(f.as_str(), &list, &args, call),
+            _ => Err(format!("Unknown builtin '{}'.", f).into()),
+        },
+        _ => Err(Error::new("TypeError", "Not a function.")),
+    };
+    match result {
+        Ok(value) => Res::V(value),
+        Err(e) => Res::E(e),
+    }
+}
+
+// Runs a Pulse function a builtin was given
+fn run(call: Call, f: &Value, args: Vec<Value>) -> Result<Value, Error> {
+    call(f.clone(), args).into_result()
+}
+
+/// The method `name` of `value`, if its type has one.
+pub fn method(value: &Value, name: Symbol) -> Option<Value> {
+    let methods = match value {
+        Value::Str(_) => string::METHODS,
+        Value::Vec(_) => list::METHODS,
+        _ => return None,
+    };
+    methods
+        .contains(&name.as_str())
+        .then(|| Value::Method(Box::new(value.clone()), name))
+}
+
+/// Whether strings or lists have a method called `name`.
+pub fn is_method(name: &str) -> bool {
+    string::METHODS.contains(&name) || list::METHODS.contains(&name)
+}
+
+/// The standard library module called `name`, if there is one.
+pub fn namespace(name: Symbol) -> Option<Value> {
+    match name.as_str() {
+        "math" => Some(MATH.with(Value::clone)),
+        "object" => Some(OBJECT.with(Value::clone)),
+        "fs" => Some(FS.with(Value::clone)),
+        _ => None,
+    }
+}
+
+// A frozen object holding a module's functions, as `Value::Builtin`s named
+// `<module>.<function>`, and its constants
+fn module(name: &str, functions: &[&str], constants: &[(&str, f64)]) -> Value {
+    let functions = functions.iter().map(|f| {
+        let builtin = Symbol::intern(&format!("{}.{}", name, f));
+        (Key::Str(f.to_string()), Value::Builtin(builtin))
+    });
+    let constants = constants
+        .iter()
+        .map(|(c, n)| (Key::Str(c.to_string()), Value::Num(*n)));
+    let module = Shared::new(functions.chain(constants).collect());
+    module.frozen.set(true);
+    Value::Obj(module)
+}
+
+// Argument `i` of the builtin `name`, which has to be a number
+fn num(name: &str, args: &[Value], i: usize) -> Result<f64, Error> {
+    let arg = args.get(i).unwrap_or(&Value::Undefined);
+    arg.as_f64()
+        .ok_or_else(|| type_error(name, "a Num", i, arg))
+}
+
+// Every argument, if all of them are `Int`s
+fn ints(args: &[Value]) -> Option<Vec<i64>> {
+    args.iter()
+        .map(|arg| match arg {
+            Value::Int(n) => Some(*n),
+            _ => None,
+        })
+        .collect()
+}
+
+// Argument `i` of `name` as an integer
+fn int(name: &str, args: &[Value], i: usize) -> Result<f64, Error> {
+    match num(name, args, i)? {
+        n if n.fract() == 0.0 => Ok(n),
+        n => {
+            let msg = format!("{} expects integers, found {}.", name, n);
+            Err(Error::new("TypeError", msg))
+        }
+    }
+}
+
+fn func(name: &str, args: &[Value], i: usize) -> Result<Value, Error> {
+    match args.get(i).unwrap_or(&Value::Undefined) {
+        f @ (Value::Fn(..) | Value::Proto(..) | Value::Builtin(_) | Value::Method(..)) => {
+            Ok(f.clone())
+        }
+        other => Err(type_error(name, "a Function", i, other)),
+    }
+}
+
+fn str(name: &str, args: &[Value], i: usize) -> Result<String, Error> {
+    match args.get(i).unwrap_or(&Value::Undefined) {
+        Value::Str(s) => Ok(s.clone()),
+        other => Err(type_error(name, "a Str", i, other)),
+    }
+}
+
+// The error for argument `i` of `name` not being `expected`
+fn type_error(name: &str, expected: &str, i: usize, found: &Value) -> Error {
+    let msg = format!(
+        "{} expects {} as argument {}, found {}.",
+        name,
+        expected,
+        i + 1,
+        found.type_name()
+    );
+    Error::new("TypeError", msg)
+}
+
+// A position in a sequence of `len` items; negative ones count from the
+// end. Out of range positions are clamped to it.
+fn index(i: f64, len: usize) -> usize {
+    let i = if i < 0.0 { len as f64 + i } else { i };
+    i.clamp(0.0, len as f64) as usize
+}
+
+// Marks a list or object and everything reachable from it as immutable.
+// Containers that are already frozen are skipped, which also stops cycles.
+fn freeze(value: &Value) {
+    match value {
+        Value::Vec(list) if !list.frozen.get() => {
+            list.frozen.set(true);
+            list.data.borrow().iter().for_each(freeze);
+        }
+        Value::Obj(obj) if !obj.frozen.get() => {
+            obj.frozen.set(true);
+            obj.data.borrow().values().for_each(freeze);
+        }
+        _ => {}
+    }
+}
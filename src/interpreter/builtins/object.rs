@@ -0,0 +1,149 @@
+use super::super::super::types::{Key, Object, Shared, Value};
+use super::super::Error;
+use super::{Call, func, run, type_error};
+use indexmap::IndexMap;
+
+// Every function takes the object as its first argument, since a method
+// would be shadowed by a field of the same name. Entries come out in
+// insertion order.
+pub const FUNCTIONS: &[&str] = &[
+    "keys",
+    "values",
+    "entries",
+    "has",
+    "get",
+    "set",
+    "delete",
+    "from_entries",
+    "map_values",
+];
+
+/// Runs `object.<f>`. `map_values` calls its function with each value and
+/// its key.
+pub fn call(f: &str, args: &[Value], call: Call) -> Result<Value, Error> {
+    let name = format!("object.{}", f);
+    if f == "from_entries" {
+        return from_entries(&name, args);
+    }
+    let obj = object(&name, args, 0)?;
+    Ok(match f {
+        "keys" => list(obj.data.borrow().keys().map(value).collect()),
+        "values" => list(obj.data.borrow().values().cloned().collect()),
+        "entries" => list(
+            obj.data
+                .borrow()
+                .iter()
+                .map(|(k, v)| list(vec![value(k), v.clone()]))
+                .collect(),
+        ),
+        "has" => Value::Bool(obj.data.borrow().contains_key(&key(&name, args, 1)?)),
+        // A missing key gives the default, or undefined without one
+        "get" => {
+            let key = key(&name, args, 1)?;
+            match obj.data.borrow().get(&key) {
+                Some(v) => v.clone(),
+                None => args.get(2).cloned().unwrap_or(Value::Undefined),
+            }
+        }
+        // These change the object in place
+        "set" => {
+            let key = key(&name, args, 1)?;
+            let v = args.get(2).cloned().unwrap_or(Value::Undefined);
+            mutable(&name, &obj)?;
+            obj.data.borrow_mut().insert(key, v);
+            Value::Obj(obj)
+        }
+        // Gives the removed value, or undefined if the key was missing
+        "delete" => {
+            let key = key(&name, args, 1)?;
+            mutable(&name, &obj)?;
+            let removed = obj.data.borrow_mut().shift_remove(&key);
+            removed.unwrap_or(Value::Undefined)
+        }
+        "map_values" => {
+            let callback = func(&name, args, 1)?;
+            // The callback may change the object, so it runs on a copy
+            let entries = obj.data.borrow().clone();
+            let mut out = IndexMap::with_capacity(entries.len());
+            for (k, v) in entries {
+                let v = run(call, &callback, vec![v, value(&k)])?;
+                out.insert(k, v);
+            }
+            Value::Obj(Shared::new(out))
+        }
+        _ => return Err(format!("Unknown builtin '{}'.", name).into()),
+    })
+}
+
+// The inverse of `entries`: a list of `[key, value]` pairs, where later
+// pairs override earlier ones
+fn from_entries(name: &str, args: &[Value]) -> Result<Value, Error> {
+    let entries = match args.first().unwrap_or(&Value::Undefined) {
+        Value::Vec(list) => list.data.borrow().clone(),
+        other => {
+            let found = other.type_name();
+            let msg = format!("{} expects a Vec as argument 1, found {}.", name, found);
+            return Err(Error::new("TypeError", msg));
+        }
+    };
+    let mut out = IndexMap::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        let pair = match entry {
+            Value::Vec(pair) => pair.data.borrow().clone(),
+            _ => Vec::new(),
+        };
+        match pair.as_slice() {
+            [k, v] => {
+                out.insert(key(name, std::slice::from_ref(k), 0)?, v.clone());
+            }
+            _ => {
+                let found = entry.type_name();
+                let msg = format!(
+                    "{} expects [key, value] pairs, found {} at {}.",
+                    name, found, i
+                );
+                return Err(Error::new("TypeError", msg));
+            }
+        }
+    }
+    Ok(Value::Obj(Shared::new(out)))
+}
+
+fn object(name: &str, args: &[Value], i: usize) -> Result<Object, Error> {
+    match args.get(i).unwrap_or(&Value::Undefined) {
+        Value::Obj(obj) => Ok(obj.clone()),
+        other => Err(type_error(name, "an Obj", i, other)),
+    }
+}
+
+// Object keys are strings or numbers
+fn key(name: &str, args: &[Value], i: usize) -> Result<Key, Error> {
+    match args.get(i).unwrap_or(&Value::Undefined) {
+        Value::Str(s) => Ok(Key::Str(s.clone())),
+        Value::Int(n) => Ok(Key::Int(*n)),
+        Value::Num(n) => Ok(Key::num(*n)),
+        other => Err(type_error(name, "a Str or Num key", i, other)),
+    }
+}
+
+fn value(key: &Key) -> Value {
+    match key {
+        Key::Str(s) => Value::Str(s.clone()),
+        Key::Int(n) => Value::Int(*n),
+        Key::Num(n) => Value::Num(n.0),
+    }
+}
+
+fn list(items: Vec<Value>) -> Value {
+    Value::Vec(Shared::new(items))
+}
+
+fn mutable(name: &str, obj: &Object) -> Result<(), Error> {
+    match obj.frozen.get() {
+        true => {
+            let msg = format!("{}: cannot change a frozen object.", name);
+            Err(Error::new("TypeError", msg))
+        }
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,139 @@
+use super::super::super::types::{Shared, Value};
+use super::super::Error;
+use super::{index, int, str};
+use std::fmt::Write;
+
+// Positions and lengths count characters, not bytes
+pub const METHODS: &[&str] = &[
+    "len",
+    "upper",
+    "lower",
+    "trim",
+    "split",
+    "replace",
+    "contains",
+    "starts_with",
+    "ends_with",
+    "find",
+    "slice",
+    "chars",
+    "pad_left",
+    "pad_right",
+    "format",
+];
+
+/// Runs the method `f` of the string `s`.
+pub fn call(f: &str, s: &str, args: &[Value]) -> Result<Value, Error> {
+    let name = format!("str.{}", f);
+    let arg = |i| str(&name, args, i);
+    Ok(match f {
+        "len" => Value::Int(s.chars().count() as i64),
+        "upper" => Value::Str(s.to_uppercase()),
+        "lower" => Value::Str(s.to_lowercase()),
+        "trim" => Value::Str(s.trim().to_string()),
+        // Without a separator, splits on runs of whitespace
+        "split" => {
+            let parts: Vec<_> = match args.first() {
+                None | Some(Value::Undefined) => s.split_whitespace().collect(),
+                Some(_) if arg(0)?.is_empty() => return call("chars", s, &[]),
+                Some(_) => s.split(arg(0)?.as_str()).collect(),
+            };
+            strings(parts)
+        }
+        "replace" => Value::Str(s.replace(&arg(0)?, &arg(1)?)),
+        "contains" => Value::Bool(s.contains(&arg(0)?)),
+        "starts_with" => Value::Bool(s.starts_with(&arg(0)?)),
+        "ends_with" => Value::Bool(s.ends_with(&arg(0)?)),
+        // The position of the first match, or undefined if there is none
+        "find" => match s.find(&arg(0)?) {
+            Some(byte) => Value::Int(s[..byte].chars().count() as i64),
+            None => Value::Undefined,
+        },
+        // Negative positions count from the end; the end defaults to it
+        "slice" => {
+            let chars: Vec<char> = s.chars().collect();
+            let start = index(int(&name, args, 0)?, chars.len());
+            let end = match args.get(1) {
+                None | Some(Value::Undefined) => chars.len(),
+                Some(_) => index(int(&name, args, 1)?, chars.len()),
+            };
+            Value::Str(chars[start..end.max(start)].iter().collect())
+        }
+        "chars" => strings(s.chars().map(String::from).collect()),
+        "pad_left" | "pad_right" => {
+            let width = int(&name, args, 0)?.max(0.0) as usize;
+            let fill = match args.get(1) {
+                None | Some(Value::Undefined) => ' ',
+                Some(_) => {
+                    let fill = arg(1)?;
+                    let mut chars = fill.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => c,
+                        _ => {
+                            return Err(format!(
+                                "{} pads with a single character, found '{}'.",
+                                name, fill
+                            )
+                            .into());
+                        }
+                    }
+                }
+            };
+            let padding: String =
+                std::iter::repeat_n(fill, width.saturating_sub(s.chars().count())).collect();
+            match f {
+                "pad_left" => Value::Str(padding + s),
+                _ => Value::Str(s.to_string() + &padding),
+            }
+        }
+        "format" => Value::Str(format(s, args)?),
+        _ => return Err(format!("Unknown builtin '{}'.", name).into()),
+    })
+}
+
+fn strings(parts: Vec<impl Into<String>>) -> Value {
+    let parts = parts.into_iter().map(|s| Value::Str(s.into())).collect();
+    Value::Vec(Shared::new(parts))
+}
+
+// `{}` takes the next argument and `{n}` the nth, counting from 0; `{{` and
+// `}}` are literal braces
+fn format(template: &str, args: &[Value]) -> Result<String, String> {
+    let mut out = String::new();
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '}' if chars.peek() == Some(&c) => {
+                chars.next();
+                out.push(c);
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err("str.format: unclosed '{' in template.".into()),
+                    }
+                }
+                let i = match placeholder.trim() {
+                    "" => {
+                        next += 1;
+                        next - 1
+                    }
+                    i => i.parse().map_err(|_| {
+                        format!("str.format: invalid placeholder '{{{}}}'.", placeholder)
+                    })?,
+                };
+                match args.get(i) {
+                    Some(arg) => write!(out, "{}", arg).unwrap(),
+                    None => return Err(format!("str.format: no argument {} to format.", i)),
+                }
+            }
+            '}' => return Err("str.format: unmatched '}' in template.".into()),
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
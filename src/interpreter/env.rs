@@ -1,48 +1,150 @@
-use super::super::types::Value;
+use super::super::checker::{Boundary, check_value};
+use super::super::types::{Slot, Symbol, Type, Value};
+use super::Error;
+use core::fmt;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::rc::Rc;
 
+// Locals are stored by the index the resolver gave them; only the root
+// environment uses `globals`. Each module has a root of its own, and
+// `module` is its id in the loader, inherited by every child environment.
 #[derive(Clone, Debug)]
-pub struct Environment<'a> {
-    values: HashMap<&'a str, Value<'a>>,
-    parent: Option<EnvPtr<'a>>,
+pub struct Environment {
+    values: Vec<Binding>,
+    globals: HashMap<Symbol, Binding>,
+    parent: Option<EnvPtr>,
+    module: usize,
 }
-pub type EnvPtr<'a> = Rc<RefCell<Environment<'a>>>;
 
-pub fn new<'a>(parent: Option<EnvPtr<'a>>) -> EnvPtr<'a> {
+// `ty` is the binding's annotation, checked again on every assignment
+#[derive(Clone, Debug)]
+pub struct Binding {
+    pub value: Value,
+    pub mutable: bool,
+    pub ty: Option<Type>,
+}
+
+impl Binding {
+    pub fn new(value: Value) -> Self {
+        Binding {
+            value,
+            mutable: true,
+            ty: None,
+        }
+    }
+}
+
+impl Default for Binding {
+    fn default() -> Self {
+        Binding::new(Value::Undefined)
+    }
+}
+pub type EnvPtr = Rc<RefCell<Environment>>;
+
+/// The environment a function value closes over.
+#[derive(Clone)]
+pub struct Scope(pub EnvPtr);
+
+impl fmt::Debug for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Scope")
+    }
+}
+
+impl PartialEq for Scope {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+pub fn new(parent: Option<EnvPtr>) -> EnvPtr {
+    let module = parent.as_ref().map_or(0, |p| p.borrow().module);
     Rc::new(RefCell::new(Environment {
-        values: HashMap::new(),
+        values: Vec::new(),
+        globals: HashMap::new(),
         parent,
+        module,
     }))
 }
 
-pub fn get<'a>(env: EnvPtr<'a>, name: &str) -> Option<Value<'a>> {
-    if let Some(val) = env.borrow().values.get(name) {
-        Some(val.clone())
-    } else if let Some(parent) = env.borrow().parent.clone() {
-        get(parent, name)
-    } else {
-        None
+/// The global environment of module `module`.
+pub fn root(module: usize) -> EnvPtr {
+    let env = new(None);
+    env.borrow_mut().module = module;
+    env
+}
+
+pub fn module(env: &EnvPtr) -> usize {
+    env.borrow().module
+}
+
+// Runs `f` on the environment `depth` scopes up (or the root for `None`)
+// without cloning the `Rc`s along the way.
+fn with<R>(env: &EnvPtr, depth: Option<usize>, f: impl FnOnce(&mut Environment) -> R) -> R {
+    if depth != Some(0)
+        && let Some(parent) = &env.borrow().parent
+    {
+        return with(parent, depth.map(|d| d - 1), f);
+    }
+    f(&mut env.borrow_mut())
+}
+
+pub fn get(env: &EnvPtr, name: Symbol, slot: Slot) -> Option<Value> {
+    match slot {
+        Slot::Local(depth, idx) => with(env, Some(depth), |env| {
+            env.values.get(idx).map(|local| local.value.clone())
+        }),
+        Slot::Global => with(env, None, |env| {
+            env.globals.get(&name).map(|global| global.value.clone())
+        }),
     }
 }
 
-pub fn set<'a>(env: &EnvPtr<'a>, name: &'a str, value: Value<'a>) -> Option<String> {
-    if env.borrow().values.contains_key(name) {
-        env.borrow_mut().values.insert(name, value);
-        None
-    } else if let Some(ref parent) = env.borrow().parent {
-        set(parent, name, value)
-    } else {
-        Some(format!("Undefined variable '{}'.", name))
+pub fn set(env: &EnvPtr, name: Symbol, slot: Slot, value: Value) -> Option<Error> {
+    let assign = |binding: Option<&mut Binding>| match binding {
+        Some(binding) if !binding.mutable => {
+            let msg = format!("Cannot assign to constant '{}'.", name);
+            Some(Error::new("TypeError", msg))
+        }
+        Some(binding) => {
+            let ty = binding.ty.as_ref();
+            match ty.and_then(|ty| check_value(ty, &value, Boundary::Binding(name))) {
+                Some(msg) => Some(Error::new("TypeError", msg)),
+                None => {
+                    binding.value = value;
+                    None
+                }
+            }
+        }
+        None => {
+            let msg = format!("Undefined variable '{}'.", name);
+            Some(Error::new("ReferenceError", msg))
+        }
+    };
+    match slot {
+        Slot::Local(depth, idx) => with(env, Some(depth), |env| assign(env.values.get_mut(idx))),
+        Slot::Global => with(env, None, |env| assign(env.globals.get_mut(&name))),
     }
 }
 
-pub fn define<'a>(env: EnvPtr<'a>, name: &'a str, value: Value<'a>) -> Option<String> {
-    if env.borrow().values.get(&name).is_none() {
-        env.borrow_mut().values.insert(name, value);
-        None
-    } else {
-        Some(format!("Variable '{}' is already declared.", name))
+pub fn define(env: &EnvPtr, name: Symbol, slot: Slot, binding: Binding) -> Option<String> {
+    match slot {
+        Slot::Local(_, idx) => {
+            let mut env = env.borrow_mut();
+            if idx >= env.values.len() {
+                env.values.resize_with(idx + 1, Binding::default);
+            }
+            env.values[idx] = binding;
+            None
+        }
+        Slot::Global => with(env, None, |root| match root.globals.entry(name) {
+            Entry::Occupied(_) => Some(format!("Variable '{}' is already declared.", name)),
+            Entry::Vacant(slot) => {
+                slot.insert(binding);
+                None
+            }
+        }),
     }
 }
@@ -1,117 +1,225 @@
-use super::types::{Expr, Op, Spanned, Value};
-use crate::interpreter::env::EnvPtr;
+use super::checker::{Boundary, check_value};
+use super::types::{
+    AssignOp, BSE, Expr, Imports, Key, Place, PropKey, Shared, Signature, Slot, Span, Symbol, Type,
+    Value,
+};
+use crate::interpreter::env::{Binding, EnvPtr};
+pub use env::Scope;
+use indexmap::IndexMap;
+use std::rc::Rc;
+pub mod builtins;
 mod env;
-mod operators;
+mod modules;
+pub mod operators;
+mod stack;
+pub use stack::Limits;
 
 #[derive(Clone)]
-pub enum Res<'a> {
-    V(Value<'a>),
-    E(String),
+pub enum Res {
+    V(Value),
+    E(Error),
+    // A `return` on its way out to the call it ends
+    Return(Value),
 }
 
-pub fn run<'a>(ast: Vec<Box<Spanned<Expr<'a>>>>) {
-    let globals: EnvPtr = env::new(None);
+impl Res {
+    /// The value or the error. Operators and builtins only give those, and a
+    /// `return` has stopped at its call before a caller sees it.
+    pub fn into_result(self) -> Result<Value, Error> {
+        match self {
+            Res::V(v) | Res::Return(v) => Ok(v),
+            Res::E(e) => Err(e),
+        }
+    }
+}
+
+// Errors are raised without a location and pick up the span of the
+// innermost expression they escape from, the calls in progress there, and
+// the module that span is in.
+// `kind` sorts them, like `TypeError`; `value` is what a `throw` raised.
+#[derive(Clone, Debug)]
+pub struct Error {
+    pub msg: String,
+    pub kind: String,
+    pub value: Option<Box<Value>>,
+    // The calls in progress where the error was raised, innermost first
+    pub trace: Vec<Frame>,
+    pub span: Option<Span>,
+    pub module: Option<usize>,
+}
+
+// A call an error escaped from: the function's name and the call's span
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub name: String,
+    pub span: Span,
+    pub module: usize,
+}
+
+impl Error {
+    pub fn new(kind: &str, msg: impl Into<String>) -> Self {
+        Error {
+            msg: msg.into(),
+            kind: kind.to_string(),
+            value: None,
+            trace: Vec::new(),
+            span: None,
+            module: None,
+        }
+    }
+
+    // The error raised by `throw value`. An object can give its own
+    // `message` and `kind`; anything else is the message itself.
+    fn thrown(value: Value) -> Self {
+        let field = |name: &str| match &value {
+            Value::Obj(obj) => match obj.data.borrow().get(&Key::Str(name.into())) {
+                Some(Value::Str(s)) => Some(s.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        let msg = field("message").unwrap_or_else(|| value.to_string());
+        let kind = field("kind").unwrap_or_else(|| "Error".into());
+        Error {
+            value: Some(Box::new(value)),
+            ..Error::new(&kind, msg)
+        }
+    }
+
+    // What `catch (e)` binds: an object with the error's `message`, `kind`
+    // and `stack`, the locations it passed through as "file:line:col"
+    // strings, and the thrown `value` if there is one.
+    fn to_value(&self, module: usize) -> Value {
+        let module = self.module.unwrap_or(module);
+        let mut stack = Vec::with_capacity(self.trace.len() + 1);
+        if let Some(span) = self.span {
+            stack.push(Value::Str(modules::location(module, span)));
+        }
+        for frame in &self.trace {
+            let at = modules::location(frame.module, frame.span);
+            stack.push(Value::Str(format!("{} in call to {}", at, frame.name)));
+        }
+        let mut obj = IndexMap::new();
+        obj.insert(Key::Str("message".into()), Value::Str(self.msg.clone()));
+        obj.insert(Key::Str("kind".into()), Value::Str(self.kind.clone()));
+        obj.insert(Key::Str("stack".into()), Value::Vec(Shared::new(stack)));
+        if let Some(value) = &self.value {
+            obj.insert(Key::Str("value".into()), (**value).clone());
+        }
+        Value::Obj(Shared::new(obj))
+    }
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Self {
+        Error::new("Error", msg)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(msg: &str) -> Self {
+        msg.to_string().into()
+    }
+}
+
+/// Sets how deep later runs on this thread may go. Going further raises a
+/// `StackOverflowError` rather than exhausting the native stack.
+pub fn set_limits(limits: Limits) {
+    stack::set_limits(limits);
+}
+
+pub fn run(ast: Vec<BSE>, filename: &str, source: &str) {
+    let globals: EnvPtr = env::root(modules::main(filename, source));
 
     for expr in ast {
-        match exec(expr, globals.clone()) {
-            Res::E(msg) => println!("{}", msg),
-            Res::V(_) => {}
+        let span = expr.1;
+        if let Res::E(e) = exec(expr, globals.clone()) {
+            modules::report(e, span);
         }
     }
 
     //println!("{:#?}", globals);
 }
 
-fn exec<'a>(expr: Box<Spanned<Expr<'a>>>, env: EnvPtr<'a>) -> Res<'a> {
-    match expr.clone().0 {
-        Expr::Let(name, value) => {
-            let r = exec(value, env.clone());
-            match r {
-                Res::V(value) => {
-                    env::define(env.clone(), name, value);
-                    Res::V(Value::Undefined)
-                }
-                Res::E(e) => Res::E(e),
-            }
+fn exec(expr: BSE, env: EnvPtr) -> Res {
+    let span = expr.1;
+    let r = match stack::descend() {
+        Ok(()) => {
+            let r = eval(expr, env);
+            stack::ascend();
+            r
         }
-        Expr::Assign(name, value) => {
-            let r = exec(value, env.clone());
-            match r {
-                Res::V(value) => {
-                    env::set(&env.clone(), name, value);
-                    Res::V(Value::Undefined)
-                }
-                Res::E(e) => Res::E(e),
+        Err(e) => Res::E(e),
+    };
+    match r {
+        Res::E(mut e) => {
+            if e.span.is_none() {
+                e.span = Some(span);
+                e.trace = stack::trace();
             }
+            Res::E(e)
         }
-        Expr::Var(name) => {
-            let r = env::get(env.clone(), name);
-            match r {
-                Some(value) => Res::V(value),
-                None => Res::E("Undefined variable.".into()),
+        r => r,
+    }
+}
+
+fn eval(expr: BSE, env: EnvPtr) -> Res {
+    let span = expr.1;
+    match expr.0 {
+        Expr::Let(name, slot, ty, value) => declare(name, slot, ty, true, value, env),
+        Expr::Const(name, slot, ty, value) => declare(name, slot, ty, false, value, env),
+        Expr::Assign(name, slot, value) => match exec(value, env.clone()) {
+            Res::V(value) => match env::set(&env, name, slot, value) {
+                None => Res::V(Value::Undefined),
+                Some(e) => Res::E(e),
+            },
+            r => r,
+        },
+        Expr::Var(name, slot) => match env::get(&env, name, slot) {
+            Some(value) => Res::V(value),
+            None => {
+                let msg = format!("Undefined variable '{}'.", name);
+                Res::E(Error::new("ReferenceError", msg))
             }
-        }
+        },
 
         Expr::BnOp(left, op, right) => {
             let ls = exec(left, env.clone());
             let rs = exec(right, env.clone());
             match (ls, rs) {
-                (Res::V(l), Res::V(r)) => match op {
-                    Op::Add => l + r,
-                    Op::Sub => l - r,
-                    Op::Mul => l * r,
-                    Op::Div => l / r,
-                    Op::And => Res::V(Value::Bool(l.is_truthy() && r.is_truthy())),
-                    Op::Or => Res::V(Value::Bool(l.is_truthy() || r.is_truthy())),
-                    Op::Eq => Res::V(Value::Bool(l == r)),
-                    Op::Neq => Res::V(Value::Bool(l != r)),
-                    Op::Gt => Res::V(Value::Bool(l > r)),
-                    Op::Gte => Res::V(Value::Bool(l >= r)),
-                    Op::Lt => Res::V(Value::Bool(l < r)),
-                    Op::Lte => Res::V(Value::Bool(l <= r)),
-
-                    _ => {
-                        println!("{:#?}", expr);
-                        Res::E("Not implemented".into())
-                    }
-                },
-
-                (Res::E(e), _) => Res::E(e),
-                (_, Res::E(e)) => Res::E(e),
+                (Res::V(l), Res::V(r)) => operators::binary(&op, l, r),
+                (Res::V(_), r) | (r, _) => r,
             }
         }
 
-        Expr::UnOp(op, value) => {
-            let v = exec(value, env.clone());
-            match v {
-                Res::V(v) => match op {
-                    Op::Neg => -v,
-                    Op::Not => Res::V(Value::Bool(!v.is_truthy())),
-                    _ => {
-                        println!("{:#?}", expr);
-                        Res::E("Not implemented".into())
-                    }
-                },
-                Res::E(msg) => Res::E(msg),
-            }
-        }
+        Expr::UnOp(op, value) => match exec(value, env.clone()) {
+            Res::V(v) => operators::unary(&op, v),
+            r => r,
+        },
 
         Expr::Block(stmts) => {
             let mut r = Res::V(Value::Undefined);
             for stmt in stmts {
                 r = exec(stmt, env.clone());
+                if !matches!(r, Res::V(_)) {
+                    break;
+                }
             }
             r
         }
 
-        Expr::Fn(name, args, body) => {
-            let r = env::define(env.clone(), name, Value::Fn(args, body));
+        Expr::Fn(name, slot, signature, body) => {
+            let r#fn = Value::Fn(signature, body, Scope(env.clone()));
+            let r = env::define(&env, name, slot, Binding::new(r#fn));
             match r {
                 None => Res::V(Value::Undefined),
-                Some(_) => Res::E("Function already defined.".into()),
+                Some(msg) => Res::E(msg.into()),
             }
         }
-        Expr::Return(value) => exec(value, env.clone()),
+        Expr::Return(value) => match exec(value, env.clone()) {
+            Res::V(v) => Res::Return(v),
+            r => r,
+        },
 
         Expr::If(cond, then, els) => {
             let c = exec(cond, env.clone());
@@ -124,7 +232,7 @@ fn exec<'a>(expr: Box<Spanned<Expr<'a>>>, env: EnvPtr<'a>) -> Res<'a> {
                         exec(els, env_.clone())
                     }
                 }
-                Res::E(e) => Res::E(e),
+                r => r,
             }
         }
 
@@ -132,53 +240,160 @@ fn exec<'a>(expr: Box<Spanned<Expr<'a>>>, env: EnvPtr<'a>) -> Res<'a> {
             let mut result = Res::V(Value::Undefined);
             loop {
                 match exec(condition.clone(), env.clone()) {
-                    Res::V(v) => {
-                        if !v.is_truthy() {
-                            break;
-                        }
-                        let loop_env = env::new(Some(env.clone()));
-                        result = exec(body.clone(), loop_env);
-                        if let Res::E(_) = result {
-                            break;
-                        }
-                    }
-                    Res::E(_) => break,
+                    Res::V(v) if !v.is_truthy() => break,
+                    Res::V(_) => {}
+                    r => return r,
+                }
+                let loop_env = env::new(Some(env.clone()));
+                result = exec(body.clone(), loop_env);
+                if !matches!(result, Res::V(_)) {
+                    break;
                 }
             }
             result
         }
 
         Expr::Call(r#fn, args) => {
-            let r = exec(r#fn, env.clone());
-            match r {
-                Res::V(v) => match v {
-                    Value::Fn(params, body) => {
-                        let env_ = env::new(Some(env.clone()));
-
-                        // Evaluate all call arguments
-                        let mut evaluated_args = Vec::new();
-                        for arg in args {
-                            match exec(arg, env.clone()) {
-                                Res::V(v) => evaluated_args.push(v),
-                                Res::E(e) => return Res::E(e),
-                            }
-                        }
+            let frame = Frame {
+                name: callee_name(&r#fn),
+                span,
+                module: env::module(&env),
+            };
+            match arguments(r#fn, args, &env) {
+                Ok((callee, values)) => call(callee, values, frame),
+                Err(e) => Res::E(e),
+            }
+        }
+        // Nothing is left to do in this body, so the function it belongs to
+        // is finished before the call is made; see `apply`
+        Expr::TailCall(r#fn, args) => {
+            let frame = Frame {
+                name: callee_name(&r#fn),
+                span,
+                module: env::module(&env),
+            };
+            match arguments(r#fn, args, &env) {
+                Ok((callee @ Value::Fn(..), values)) => {
+                    stack::defer(callee, values, frame);
+                    Res::V(Value::Undefined)
+                }
+                Ok((callee, values)) => call(callee, values, frame),
+                Err(e) => Res::E(e),
+            }
+        }
+        Expr::Field(object, name) => match exec(object, env.clone()) {
+            Res::V(v) => operators::field(v, name),
+            r => r,
+        },
+        Expr::Index(object, index) => {
+            let object = exec(object, env.clone());
+            let index = exec(index, env.clone());
+            match (object, index) {
+                (Res::V(object), Res::V(index)) => operators::index(object, index),
+                (Res::V(_), r) | (r, _) => r,
+            }
+        }
+        Expr::Set(place, op, value) => match set(place, op, value, &env) {
+            Ok(()) => Res::V(Value::Undefined),
+            Err(e) => Res::E(e),
+        },
 
-                        // Assign arguments to parameter names
-                        for (i, param) in params.iter().enumerate() {
-                            let value = evaluated_args.get(i).cloned().unwrap_or(Value::Undefined);
-                            env::define(env_.clone(), param.clone(), value);
+        Expr::Import(path, imports) => {
+            let exports = match modules::import(env::module(&env), &path) {
+                Ok(exports) => exports,
+                Err(e) => return Res::E(e),
+            };
+            let bindings = match imports {
+                Imports::Module(name, slot) => vec![(name, slot, Value::Obj(exports))],
+                Imports::Names(names) => {
+                    let exports = exports.data.borrow();
+                    let mut bindings = Vec::with_capacity(names.len());
+                    for (name, slot) in names {
+                        match exports.get(&Key::Str(name.to_string())) {
+                            Some(value) => bindings.push((name, slot, value.clone())),
+                            None => {
+                                let msg = format!("Module '{}' has no export '{}'.", path, name);
+                                return Res::E(msg.into());
+                            }
                         }
-
-                        exec(body, env_.clone())
                     }
-                    _ => Res::E("Not a function.".into()),
-                },
-                Res::E(msg) => Res::E(msg),
+                    bindings
+                }
+            };
+            for (name, slot, value) in bindings {
+                let binding = Binding {
+                    value,
+                    mutable: false,
+                    ty: None,
+                };
+                if let Some(msg) = env::define(&env, name, slot, binding) {
+                    return Res::E(msg.into());
+                }
+            }
+            Res::V(Value::Undefined)
+        }
+        Expr::Export(decl) => exec(decl, env),
+
+        Expr::Throw(value) => match exec(value, env.clone()) {
+            Res::V(v) => Res::E(Error::thrown(v)),
+            r => r,
+        },
+        // The handler sees errors from the body, and `finally` runs after
+        // both either way, even when they return. An error or a `return` in
+        // `finally` replaces the result.
+        Expr::Try(body, catch, finally) => {
+            let mut result = exec(body, env::new(Some(env.clone())));
+            if let (Res::E(e), Some((name, slot, handler))) = (&result, catch) {
+                let scope = env::new(Some(env.clone()));
+                let error = e.to_value(env::module(&env));
+                env::define(&scope, name, slot, Binding::new(error));
+                result = exec(handler, scope);
+            }
+            if let Some(finally) = finally
+                && let r @ (Res::E(_) | Res::Return(_)) = exec(finally, env::new(Some(env.clone())))
+            {
+                return r;
             }
+            result
         }
+
         Expr::ReservedCall(name, args) => exec_reserved_call(name, args, env.clone()),
+        Expr::ReservedVar(name) => match builtins::namespace(name) {
+            Some(module) => Res::V(module),
+            None => Res::E(format!("Unknown builtin '{}'.", name).into()),
+        },
         Expr::Val(n) => Res::V(n),
+
+        Expr::List(items) => {
+            let mut list = Vec::with_capacity(items.len());
+            for item in items {
+                match exec(item, env.clone()) {
+                    Res::V(v) => list.push(v),
+                    r => return r,
+                }
+            }
+            Res::V(Value::Vec(Shared::new(list)))
+        }
+        Expr::Obj(entries) => {
+            let mut obj = IndexMap::with_capacity(entries.len());
+            for (key, value) in entries {
+                let key = match key {
+                    PropKey::Fixed(key) => key.clone(),
+                    PropKey::Computed(expr) => match exec(expr, env.clone()) {
+                        Res::V(v) => match operators::key(v) {
+                            Ok(key) => key,
+                            Err(e) => return Res::E(e),
+                        },
+                        r => return r,
+                    },
+                };
+                match exec(value, env.clone()) {
+                    Res::V(v) => obj.insert(key, v),
+                    r => return r,
+                };
+            }
+            Res::V(Value::Obj(Shared::new(obj)))
+        }
         _ => {
             println!("{:#?}", expr);
             Res::E("Not implemented".into())
@@ -186,33 +401,190 @@ fn exec<'a>(expr: Box<Spanned<Expr<'a>>>, env: EnvPtr<'a>) -> Res<'a> {
     }
 }
 
-fn exec_reserved_call<'a>(
-    name: &'a str,
-    args: Vec<Box<Spanned<Expr<'a>>>>,
-    env: EnvPtr<'a>,
-) -> Res<'a> {
-    match name {
+// A field or element `Set` assigns to, with the object, and the index if
+// there is one, already evaluated
+enum Target {
+    Field(Value, Symbol),
+    Index(Value, Value),
+}
+
+impl Target {
+    fn get(&self) -> Result<Value, Error> {
+        let r = match self {
+            Target::Field(object, name) => operators::field(object.clone(), *name),
+            Target::Index(object, index) => operators::index(object.clone(), index.clone()),
+        };
+        r.into_result()
+    }
+
+    fn put(self, value: Value) -> Result<(), Error> {
+        match self {
+            Target::Field(object, name) => operators::set_field(object, name, value),
+            Target::Index(object, index) => operators::set_index(object, index, value),
+        }
+    }
+}
+
+// Evaluates the target's parts once, then the value; `??=` only evaluates
+// it if the target is undefined
+fn set(place: Place, op: AssignOp, value: BSE, env: &EnvPtr) -> Result<(), Error> {
+    let target = match place {
+        Place::Field(object, name) => Target::Field(exec(object, env.clone()).into_result()?, name),
+        Place::Index(object, index) => {
+            let object = exec(object, env.clone()).into_result()?;
+            Target::Index(object, exec(index, env.clone()).into_result()?)
+        }
+    };
+    let value = match op {
+        AssignOp::Replace => exec(value, env.clone()).into_result()?,
+        AssignOp::Apply(op) => {
+            let current = target.get()?;
+            let value = exec(value, env.clone()).into_result()?;
+            operators::binary(&op, current, value).into_result()?
+        }
+        AssignOp::IfUndefined => match target.get()? {
+            Value::Undefined => exec(value, env.clone()).into_result()?,
+            _ => return Ok(()),
+        },
+    };
+    target.put(value)
+}
+
+// Evaluates a call's function, then its arguments
+fn arguments(r#fn: BSE, args: Vec<BSE>, env: &EnvPtr) -> Result<(Value, Vec<Value>), Error> {
+    let callee = match exec(r#fn, env.clone()).into_result()? {
+        v @ (Value::Fn(..) | Value::Builtin(_) | Value::Method(..)) => v,
+        _ => return Err(Error::new("TypeError", "Not a function.")),
+    };
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        values.push(exec(arg, env.clone()).into_result()?);
+    }
+    Ok((callee, values))
+}
+
+fn call(callee: Value, args: Vec<Value>, frame: Frame) -> Res {
+    if let Err(e) = stack::enter(frame) {
+        return Res::E(e);
+    }
+    let r = apply(callee, args);
+    stack::leave();
+    r
+}
+
+// How a call's function is named in stack traces
+fn callee_name(callee: &BSE) -> String {
+    match &callee.0 {
+        Expr::Var(name, _) | Expr::Field(_, name) => name.to_string(),
+        _ => "a function".into(),
+    }
+}
+
+/// Calls a function value on evaluated arguments. Builtins taking callbacks
+/// come back through here to run them.
+///
+/// A body ending in a tail call leaves the call to be made here, in a loop,
+/// so tail-recursive functions run in constant native stack. The result
+/// still has to satisfy the return annotation of every function the loop
+/// went through.
+fn apply(mut callee: Value, mut args: Vec<Value>) -> Res {
+    let mut returns: Vec<Rc<Signature>> = Vec::new();
+    loop {
+        let (signature, body, captured) = match callee {
+            Value::Fn(signature, body, Scope(captured)) => (signature, body, captured),
+            native @ (Value::Builtin(_) | Value::Method(..)) => {
+                return builtins::native(native, args, &mut apply);
+            }
+            _ => return Res::E(Error::new("TypeError", "Not a function.")),
+        };
+        let module = env::module(&captured);
+        let env_ = env::new(Some(captured));
+
+        // Assign arguments to parameter names
+        for (i, param) in signature.params.iter().enumerate() {
+            let value = args.get(i).cloned().unwrap_or(Value::Undefined);
+            if let Some(ty) = &param.ty
+                && let Some(msg) = check_value(ty, &value, Boundary::Param(param.name))
+            {
+                return Res::E(Error::new("TypeError", msg));
+            }
+            env::define(&env_, param.name, Slot::Local(0, i), Binding::new(value));
+        }
+
+        let value = match exec(body, env_) {
+            Res::V(v) | Res::Return(v) => v,
+            // The body may be in another module than the call
+            Res::E(mut e) => {
+                e.module.get_or_insert(module);
+                return Res::E(e);
+            }
+        };
+        if signature.ret.is_some() && !returns.iter().any(|s| Rc::ptr_eq(s, &signature)) {
+            returns.push(signature);
+        }
+        match stack::deferred() {
+            Some((next, next_args)) => (callee, args) = (next, next_args),
+            None => {
+                for signature in returns.iter().rev() {
+                    let ty = signature.ret.as_ref().unwrap();
+                    if let Some(msg) = check_value(ty, &value, Boundary::Return) {
+                        return Res::E(Error::new("TypeError", msg));
+                    }
+                }
+                return Res::V(value);
+            }
+        }
+    }
+}
+
+// `let` and `const`; an annotated binding checks its initializer here and
+// every later assignment in `env::set`.
+fn declare(
+    name: Symbol,
+    slot: Slot,
+    ty: Option<Type>,
+    mutable: bool,
+    value: BSE,
+    env: EnvPtr,
+) -> Res {
+    let value = match exec(value, env.clone()) {
+        Res::V(value) => value,
+        r => return r,
+    };
+    if let Some(ty) = &ty
+        && let Some(msg) = check_value(ty, &value, Boundary::Binding(name))
+    {
+        return Res::E(Error::new("TypeError", msg));
+    }
+    match env::define(&env, name, slot, Binding { value, mutable, ty }) {
+        None => Res::V(Value::Undefined),
+        Some(msg) => Res::E(msg.into()),
+    }
+}
+
+fn exec_reserved_call(name: Symbol, args: Vec<BSE>, env: EnvPtr) -> Res {
+    match name.as_str() {
         "print" => {
-            let len = args.len() - 1;
+            let len = args.len();
             for (i, arg) in args.into_iter().enumerate() {
-                let r = exec(arg, env.clone());
-                match r {
-                    Res::V(v) => {
-                        if i < len {
-                            print!("{} ", v);
-                        } else {
-                            print!("{}", v);
-                        }
-                    }
-                    Res::E(_) => {}
+                match exec(arg, env.clone()) {
+                    Res::V(v) if i + 1 < len => print!("{} ", v),
+                    Res::V(v) => print!("{}", v),
+                    r => return r,
                 }
             }
             println!();
             Res::V(Value::Undefined)
         }
         _ => {
-            //println!("{:#?}", expr);
-            Res::E("Not implemented".into())
+            let mut values = Vec::with_capacity(args.len());
+            for arg in args {
+                match exec(arg, env.clone()) {
+                    Res::V(v) => values.push(v),
+                    r => return r,
+                }
+            }
+            builtins::call(name, values)
         }
     }
 }
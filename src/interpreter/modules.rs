@@ -0,0 +1,192 @@
+use super::super::ast::{position, read, show_spanned_errors};
+use super::super::resolver;
+use super::super::types::{Expr, Key, Object, Shared, Slot, Span, Value};
+use super::{Error, Frame, Res, env, exec};
+use ariadne::{Color, Label, Report, ReportKind, sources};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// Every file taking part in a run. A module is identified by its index in
+// `files`; the main program is module 0.
+#[derive(Default)]
+struct Loader {
+    // Display name and source text, for error reports
+    files: Vec<(String, String)>,
+    // Export namespaces of the modules that finished loading, so each runs
+    // at most once however often it is imported
+    cache: HashMap<PathBuf, Object>,
+    // Modules whose top level is running, outermost first
+    loading: Vec<(PathBuf, usize)>,
+}
+
+thread_local! {
+    static LOADER: RefCell<Loader> = RefCell::new(Loader::default());
+}
+
+/// Registers the main program as module 0.
+pub fn main(filename: &str, source: &str) -> usize {
+    let path = fs::canonicalize(filename).unwrap_or_else(|_| filename.into());
+    LOADER.with_borrow_mut(|loader| {
+        loader
+            .files
+            .push((filename.to_string(), source.to_string()));
+        loader.loading.push((path, 0));
+    });
+    0
+}
+
+/// Reports a runtime error against the file it was raised in, with a label
+/// on each call it was raised under. Values nobody caught are shown with
+/// their kind.
+pub fn report(e: Error, fallback: Span) {
+    let files = LOADER.with_borrow(|loader| loader.files.clone());
+    let name = |module: usize| files[module].0.clone();
+    let msg = match e.value {
+        Some(_) => format!("Uncaught {}: {}", e.kind, e.msg),
+        None => e.msg,
+    };
+    let at = (
+        name(e.module.unwrap_or(0)),
+        e.span.unwrap_or(fallback).into_range(),
+    );
+
+    let mut report = Report::build(ReportKind::Error, at.clone())
+        .with_message(&msg)
+        .with_label(Label::new(at).with_message(msg).with_color(Color::Red));
+    for (i, (frame, count)) in calls(&e.trace).into_iter().enumerate() {
+        let msg = match count {
+            1 => format!("in call to {}", frame.name),
+            n => format!("in call to {} ({} times)", frame.name, n),
+        };
+        report = report.with_label(
+            Label::new((name(frame.module), frame.span.into_range()))
+                .with_message(msg)
+                .with_color(Color::Yellow)
+                .with_order(i as i32 + 1),
+        );
+    }
+    report.finish().print(sources(files)).unwrap();
+}
+
+// The distinct calls in a trace, innermost first, with how often each
+// occurs, so deep recursion doesn't label the same call site over and over
+fn calls(trace: &[Frame]) -> Vec<(&Frame, usize)> {
+    let mut calls: Vec<(&Frame, usize)> = Vec::new();
+    for frame in trace {
+        let seen = calls
+            .iter_mut()
+            .find(|(f, _)| (f.module, f.span, &f.name) == (frame.module, frame.span, &frame.name));
+        match seen {
+            Some((_, count)) => *count += 1,
+            None => calls.push((frame, 1)),
+        }
+    }
+    calls
+}
+
+/// `span` in module `module` as "file:line:col".
+pub fn location(module: usize, span: Span) -> String {
+    LOADER.with_borrow(|loader| {
+        let (name, source) = &loader.files[module];
+        let (line, col) = position(source, span.start);
+        format!("{}:{}:{}", name, line, col)
+    })
+}
+
+/// Loads the module at `path`, relative to the file of module `importer`,
+/// and returns its exports.
+pub fn import(importer: usize, path: &str) -> Result<Object, Error> {
+    let name = LOADER.with_borrow(|loader| {
+        let dir = Path::new(&loader.files[importer].0).parent();
+        normalize(&dir.unwrap_or(Path::new("")).join(path))
+    });
+    let canonical =
+        fs::canonicalize(&name).map_err(|e| format!("Cannot read module '{}': {}.", path, e))?;
+
+    let cycle = LOADER.with_borrow(|loader| {
+        let start = loader.loading.iter().position(|(p, _)| *p == canonical)?;
+        let mut chain: Vec<_> = loader.loading[start..]
+            .iter()
+            .map(|(_, id)| loader.files[*id].0.clone())
+            .collect();
+        chain.push(name.clone());
+        Some(chain.join(" -> "))
+    });
+    if let Some(chain) = cycle {
+        return Err(format!("Import cycle: {}.", chain).into());
+    }
+    if let Some(exports) = LOADER.with_borrow(|loader| loader.cache.get(&canonical).cloned()) {
+        return Ok(exports);
+    }
+
+    let source = fs::read_to_string(&canonical)
+        .map_err(|e| format!("Cannot read module '{}': {}.", path, e))?;
+    let failed = || Error::from(format!("Failed to load module '{}'.", path));
+    let mut ast = read(&name, &source).ok_or_else(failed)?;
+    let errs = resolver::resolve(&mut ast);
+    if !errs.is_empty() {
+        show_spanned_errors(errs, name, &source);
+        return Err(failed());
+    }
+
+    let id = LOADER.with_borrow_mut(|loader| {
+        loader.files.push((name, source));
+        loader
+            .loading
+            .push((canonical.clone(), loader.files.len() - 1));
+        loader.files.len() - 1
+    });
+    let globals = env::root(id);
+    let mut exported = Vec::new();
+    let mut result = Ok(());
+    // Unlike the main program, a module stops at its first error, which
+    // then fails the import
+    for stmt in ast {
+        if let Expr::Export(decl) = &stmt.0
+            && let Expr::Let(name, ..) | Expr::Const(name, ..) | Expr::Fn(name, ..) = decl.0
+        {
+            exported.push(name);
+        }
+        let span = stmt.1;
+        if let Res::E(mut e) = exec(stmt, globals.clone()) {
+            e.span.get_or_insert(span);
+            e.module.get_or_insert(id);
+            result = Err(e);
+            break;
+        }
+    }
+    LOADER.with_borrow_mut(|loader| loader.loading.pop());
+    result?;
+
+    let exports = exported
+        .into_iter()
+        .map(|name| {
+            let value = env::get(&globals, name, Slot::Global).unwrap_or(Value::Undefined);
+            (Key::Str(name.to_string()), value)
+        })
+        .collect();
+    let exports = Shared::new(exports);
+    exports.frozen.set(true);
+    LOADER.with_borrow_mut(|loader| loader.cache.insert(canonical, exports.clone()));
+    Ok(exports)
+}
+
+// Resolves `.` and `..` without touching the file system, so error
+// messages show paths the way the program spelled them
+fn normalize(path: &Path) -> String {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out.display().to_string()
+}
@@ -1,51 +1,556 @@
 #![allow(unused_imports)]
-use super::{super::types::Value, Res};
+use super::{
+    super::types::{Key, Object, Op, Shared, Symbol, Value},
+    Error, Res, builtins,
+};
+use bigdecimal::{BigDecimal, RoundingMode};
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive, Zero};
+use ordered_float::OrderedFloat;
 use std::cmp::{Ordering, PartialEq, PartialOrd};
-use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use std::rc::Rc;
+
+impl Value {
+    /// A whole number as an `Int` if it fits in one, else a `BigInt`.
+    pub fn big(n: BigInt) -> Value {
+        match i64::try_from(&n) {
+            Ok(n) => Value::Int(n),
+            Err(_) => Value::BigInt(Rc::new(n)),
+        }
+    }
 
-impl<'a> Value<'a> {
-    fn type_name(&self) -> &'static str {
+    /// The nearest float to a number, which for a big one may be infinite.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(n) => Some(*n as f64),
+            Value::BigInt(n) => n.to_f64(),
+            Value::Decimal(n) => n.to_f64(),
+            Value::Num(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
         match self {
             Value::Undefined => "Undefined",
+            Value::Int(_) => "Int",
+            Value::BigInt(_) => "BigInt",
+            Value::Decimal(_) => "Decimal",
             Value::Num(_) => "Num",
             Value::Str(_) => "Str",
             Value::Bool(_) => "Bool",
             Value::Vec(_) => "Vec",
             Value::Obj(_) => "Obj",
-            Value::Fn(_, _) => "Function",
+            Value::Fn(..) | Value::Proto(..) | Value::Builtin(_) | Value::Method(..) => "Function",
         }
     }
 
     pub fn is_truthy(&self) -> bool {
         match self {
             Value::Undefined => false,
-            Value::Fn(_, _) => true,
+            Value::Fn(..) | Value::Proto(..) | Value::Builtin(_) | Value::Method(..) => true,
             Value::Bool(b) => *b,
+            Value::Int(n) => *n != 0,
+            Value::BigInt(n) => !n.is_zero(),
+            Value::Decimal(n) => !n.is_zero(),
             Value::Num(n) => *n != 0.0,
             Value::Str(s) => !s.is_empty(),
-            Value::Vec(v) => !v.is_empty(),
-            Value::Obj(o) => !o.is_empty(),
+            Value::Vec(v) => !v.data.borrow().is_empty(),
+            Value::Obj(o) => !o.data.borrow().is_empty(),
+        }
+    }
+}
+
+/// Applies a binary operator to two evaluated operands. Both the tree-walker
+/// and the bytecode VM go through here so they agree on semantics.
+pub fn binary(op: &Op, l: Value, r: Value) -> Res {
+    match op {
+        Op::Add => l + r,
+        Op::Sub => l - r,
+        Op::Mul => l * r,
+        Op::Div => l / r,
+        Op::Mod => l % r,
+        Op::Pow => pow(l, r),
+        Op::IntDiv => int_div(l, r),
+        Op::BitAnd | Op::BitOr | Op::BitXor | Op::Shl | Op::Shr => bitwise(op, l, r),
+        Op::And => Res::V(Value::Bool(l.is_truthy() && r.is_truthy())),
+        Op::Or => Res::V(Value::Bool(l.is_truthy() || r.is_truthy())),
+        Op::Eq => Res::V(Value::Bool(l == r)),
+        Op::Neq => Res::V(Value::Bool(l != r)),
+        Op::Gt => Res::V(Value::Bool(l > r)),
+        Op::Gte => Res::V(Value::Bool(l >= r)),
+        Op::Lt => Res::V(Value::Bool(l < r)),
+        Op::Lte => Res::V(Value::Bool(l <= r)),
+        _ => Res::E("Not implemented".into()),
+    }
+}
+
+pub fn unary(op: &Op, v: Value) -> Res {
+    match op {
+        Op::Neg => -v,
+        Op::Not => Res::V(Value::Bool(!v.is_truthy())),
+        Op::BitNot => match integer(&v) {
+            Some(Ok(n)) => Res::V(Value::Int(!n)),
+            Some(Err(e)) => Res::E(e),
+            None => {
+                let msg = format!("Bitwise not not supported: {}", v.type_name());
+                Res::E(Error::new("TypeError", msg))
+            }
+        },
+        _ => Res::E("Not implemented".into()),
+    }
+}
+
+/// Reads `name` from an object, where a missing field is undefined, or
+/// looks up a builtin method of a string or list.
+pub fn field(value: Value, name: Symbol) -> Res {
+    match value {
+        Value::Obj(obj) => {
+            let key = Key::Str(name.to_string());
+            Res::V(
+                obj.data
+                    .borrow()
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or(Value::Undefined),
+            )
+        }
+        _ => match builtins::method(&value, name) {
+            Some(method) => Res::V(method),
+            None => {
+                let msg = format!("Cannot read field '{}' of {}.", name, value.type_name());
+                Res::E(Error::new("TypeError", msg))
+            }
+        },
+    }
+}
+
+/// The key a computed `[expr]` in an object literal stands for.
+pub fn key(value: Value) -> Result<Key, Error> {
+    match value {
+        Value::Str(s) => Ok(Key::Str(s)),
+        Value::Int(n) => Ok(Key::Int(n)),
+        Value::Num(n) => Ok(Key::num(n)),
+        other => {
+            let msg = format!(
+                "Object keys must be Str or Num, found {}.",
+                other.type_name()
+            );
+            Err(Error::new("TypeError", msg))
+        }
+    }
+}
+
+/// Reads `target[index]`: an element of a list, a character of a string,
+/// counting from the end if negative, or a field of an object. Anything
+/// missing is undefined.
+pub fn index(target: Value, index: Value) -> Res {
+    match target {
+        Value::Vec(list) => {
+            let list = list.data.borrow();
+            match position(&index, list.len()) {
+                Ok(i) => Res::V(i.map(|i| list[i].clone()).unwrap_or(Value::Undefined)),
+                Err(e) => Res::E(e),
+            }
+        }
+        Value::Str(s) => {
+            let chars: Vec<char> = s.chars().collect();
+            match position(&index, chars.len()) {
+                Ok(i) => Res::V(
+                    i.map(|i| Value::Str(chars[i].to_string()))
+                        .unwrap_or(Value::Undefined),
+                ),
+                Err(e) => Res::E(e),
+            }
+        }
+        Value::Obj(obj) => match key(index) {
+            Ok(key) => Res::V(
+                obj.data
+                    .borrow()
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or(Value::Undefined),
+            ),
+            Err(e) => Res::E(e),
+        },
+        other => {
+            let msg = format!("Cannot index {}.", other.type_name());
+            Res::E(Error::new("TypeError", msg))
+        }
+    }
+}
+
+/// Assigns `target.name = value`.
+pub fn set_field(target: Value, name: Symbol, value: Value) -> Result<(), Error> {
+    match target {
+        Value::Obj(obj) => store(&obj, Key::Str(name.to_string()), value),
+        other => {
+            let msg = format!("Cannot set field '{}' of {}.", name, other.type_name());
+            Err(Error::new("TypeError", msg))
+        }
+    }
+}
+
+/// Assigns `target[index] = value`. A list only has the elements it has;
+/// an object gains the field if it is missing.
+pub fn set_index(target: Value, index: Value, value: Value) -> Result<(), Error> {
+    match target {
+        Value::Vec(list) => {
+            if list.frozen.get() {
+                return Err(Error::new("TypeError", "Cannot change a frozen list."));
+            }
+            let mut items = list.data.borrow_mut();
+            let len = items.len();
+            match position(&index, len)? {
+                Some(i) => {
+                    items[i] = value;
+                    Ok(())
+                }
+                None => {
+                    let msg = format!("Index {} is out of range for a list of {}.", index, len);
+                    Err(Error::new("IndexError", msg))
+                }
+            }
+        }
+        Value::Obj(obj) => store(&obj, key(index)?, value),
+        other => {
+            let msg = format!("Cannot assign to an element of {}.", other.type_name());
+            Err(Error::new("TypeError", msg))
+        }
+    }
+}
+
+fn store(obj: &Object, key: Key, value: Value) -> Result<(), Error> {
+    if obj.frozen.get() {
+        return Err(Error::new("TypeError", "Cannot change a frozen object."));
+    }
+    obj.data.borrow_mut().insert(key, value);
+    Ok(())
+}
+
+// Where `index` falls in a sequence of `len`, if anywhere
+fn position(index: &Value, len: usize) -> Result<Option<usize>, Error> {
+    match index {
+        Value::Int(n) => {
+            let i = if *n < 0 { *n + len as i64 } else { *n };
+            Ok((0..len as i64).contains(&i).then_some(i as usize))
+        }
+        // Past the end of any list, whichever end
+        Value::BigInt(_) => Ok(None),
+        Value::Num(n) if n.fract() == 0.0 => {
+            let i = if *n < 0.0 { *n + len as f64 } else { *n };
+            Ok((i >= 0.0 && i < len as f64).then_some(i as usize))
+        }
+        Value::Num(n) => {
+            let msg = format!("List indexes must be integers, found {}.", n);
+            Err(Error::new("TypeError", msg))
+        }
+        other => {
+            let msg = format!(
+                "List indexes must be integers, found {}.",
+                other.type_name()
+            );
+            Err(Error::new("TypeError", msg))
+        }
+    }
+}
+
+// A whole number to a negative power is a fraction, so a `Num`, and so is
+// a `Decimal` to a fractional one
+fn pow(l: Value, r: Value) -> Res {
+    match promote(l, r) {
+        (Value::Int(a), Value::Int(b)) if b >= 0 => {
+            match u32::try_from(b).ok().and_then(|e| a.checked_pow(e)) {
+                Some(n) => Res::V(Value::Int(n)),
+                None => big_power(&a.into(), &b.into()),
+            }
+        }
+        (Value::BigInt(a), Value::BigInt(b)) if !b.is_negative() => big_power(&a, &b),
+        // Exact: the digits to the power, with the scale multiplied to match
+        (Value::Decimal(a), Value::Decimal(b)) if b.is_integer() => {
+            let (digits, scale) = a.as_bigint_and_exponent();
+            let power = b
+                .abs()
+                .to_u32()
+                .filter(|e| fits(&digits, *e))
+                .and_then(|e| Some((e, scale.checked_mul(e.into())?)));
+            match power {
+                Some(_) if b.is_negative() && a.is_zero() => {
+                    Res::E(Error::new("ZeroDivisionError", "Division by zero"))
+                }
+                Some((e, scale)) => {
+                    let n = BigDecimal::new(digits.pow(e), scale);
+                    // A fraction, divided out as `/` would
+                    Res::V(Value::Decimal(Rc::new(if b.is_negative() {
+                        BigDecimal::from(1) / n
+                    } else {
+                        n
+                    })))
+                }
+                None => {
+                    let msg = format!("Exponent too large: {} ** {}", a, b);
+                    Res::E(Error::new("OverflowError", msg))
+                }
+            }
+        }
+        (Value::Num(a), Value::Num(b)) => Res::V(Value::Num(a.powf(b))),
+        (
+            a @ (Value::Int(_) | Value::BigInt(_) | Value::Decimal(_)),
+            b @ (Value::Int(_) | Value::BigInt(_) | Value::Decimal(_)),
+        ) => Res::V(Value::Num(a.as_f64().unwrap().powf(b.as_f64().unwrap()))),
+        (lhs, rhs) => unsupported("Exponentiation", "**", lhs, rhs),
+    }
+}
+
+// Past this many bits, a power would take too long to work out
+const POWER_BITS: u64 = 1 << 20;
+
+// A whole number to a whole, non-negative power, unless the result would be
+// too big. Only 0, 1 and -1 survive an exponent past the `u32`s.
+fn big_power(a: &BigInt, b: &BigInt) -> Res {
+    match b.to_u32() {
+        Some(e) if fits(a, e) => Res::V(Value::big(a.pow(e))),
+        None if a.abs() <= BigInt::from(1) => {
+            let odd = b.bit(0);
+            Res::V(Value::big(if odd { a.clone() } else { a.abs() }))
+        }
+        _ => {
+            let msg = format!("Exponent too large: {} ** {}", a, b);
+            Res::E(Error::new("OverflowError", msg))
+        }
+    }
+}
+
+/// Whether `digits ** e` is small enough to work out.
+pub fn fits(digits: &BigInt, e: u32) -> bool {
+    digits.bits().saturating_sub(1).saturating_mul(e.into()) <= POWER_BITS
+}
+
+// Division rounded down, so `-7 ~/ 2` is -4
+fn int_div(l: Value, r: Value) -> Res {
+    match promote(l, r) {
+        (Value::Int(a), Value::Int(b)) => {
+            if b == 0 {
+                return Res::E(Error::new("ZeroDivisionError", "Division by zero"));
+            }
+            match a.checked_div(b) {
+                Some(q) if a % b != 0 && (a < 0) != (b < 0) => Res::V(Value::Int(q - 1)),
+                q => checked(q, a, b, |a, b| a / b),
+            }
+        }
+        (Value::BigInt(a), Value::BigInt(b)) => {
+            if b.is_zero() {
+                return Res::E(Error::new("ZeroDivisionError", "Division by zero"));
+            }
+            let (q, r) = (&*a / &*b, &*a % &*b);
+            if !r.is_zero() && r.sign() != b.sign() {
+                Res::V(Value::big(q - 1))
+            } else {
+                Res::V(Value::big(q))
+            }
+        }
+        (Value::Decimal(a), Value::Decimal(b)) => {
+            if b.is_zero() {
+                return Res::E(Error::new("ZeroDivisionError", "Division by zero"));
+            }
+            let q = (&*a / &*b).with_scale_round(0, RoundingMode::Floor);
+            Res::V(Value::Decimal(Rc::new(q)))
+        }
+        (Value::Num(a), Value::Num(b)) => {
+            if b == 0.0 {
+                return Res::E(Error::new("ZeroDivisionError", "Division by zero"));
+            }
+            Res::V(Value::Num((a / b).floor()))
+        }
+        (Value::Undefined, Value::Undefined) => Res::V(Value::Undefined),
+        (lhs, Value::Undefined) => Res::V(lhs),
+        (Value::Undefined, _) => Res::V(Value::Undefined),
+        (lhs, rhs) => unsupported("Integer division", "~/", lhs, rhs),
+    }
+}
+
+fn bitwise(op: &Op, l: Value, r: Value) -> Res {
+    let (what, sym) = match op {
+        Op::BitAnd => ("Bitwise and", "&"),
+        Op::BitOr => ("Bitwise or", "|"),
+        Op::BitXor => ("Bitwise xor", "^"),
+        Op::Shl => ("Left shift", "<<"),
+        _ => ("Right shift", ">>"),
+    };
+    let (a, b) = match (integer(&l), integer(&r)) {
+        (Some(Ok(a)), Some(Ok(b))) => (a, b),
+        (Some(Err(e)), Some(_)) | (Some(_), Some(Err(e))) => return Res::E(e),
+        _ => return unsupported(what, sym, l, r),
+    };
+    if matches!(op, Op::Shl | Op::Shr) && !(0..64).contains(&b) {
+        let msg = format!("Shift amount must be between 0 and 63, found {}.", b);
+        return Res::E(Error::new("ValueError", msg));
+    }
+    let n = match op {
+        Op::BitAnd => a & b,
+        Op::BitOr => a | b,
+        Op::BitXor => a ^ b,
+        // Bits shifted past the 64th are lost
+        Op::Shl => a << b,
+        _ => a >> b,
+    };
+    Res::V(Value::Int(n))
+}
+
+// The 64-bit integer a number stands for, if it is a whole one in range.
+// `None` if it is not a number bitwise operators work on at all.
+fn integer(v: &Value) -> Option<Result<i64, Error>> {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+    match v {
+        Value::Int(n) => Some(Ok(*n)),
+        Value::BigInt(n) => {
+            let msg = format!("Bitwise operators need 64-bit integers, found {}.", n);
+            Some(Err(Error::new("OverflowError", msg)))
+        }
+        Value::Num(n) if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(n) => Some(Ok(*n as i64)),
+        Value::Num(n) => {
+            let msg = format!("Bitwise operators need whole numbers, found {}.", n);
+            Some(Err(Error::new("TypeError", msg)))
+        }
+        _ => None,
+    }
+}
+
+// Mixing two kinds of number makes both the later kind of `Int`, `BigInt`,
+// `Decimal` and `Num`. Each holds the ones before it exactly, except that a
+// float only ever holds a number approximately.
+fn promote(l: Value, r: Value) -> (Value, Value) {
+    match (rank(&l), rank(&r)) {
+        (Some(a), Some(b)) if a < b => (raise(l, b), r),
+        (Some(a), Some(b)) if a > b => (l, raise(r, a)),
+        _ => (l, r),
+    }
+}
+
+fn rank(v: &Value) -> Option<u8> {
+    match v {
+        Value::Int(_) => Some(0),
+        Value::BigInt(_) => Some(1),
+        Value::Decimal(_) => Some(2),
+        Value::Num(_) => Some(3),
+        _ => None,
+    }
+}
+
+fn raise(v: Value, rank: u8) -> Value {
+    match (v, rank) {
+        (Value::Int(n), 1) => Value::BigInt(Rc::new(n.into())),
+        (Value::Int(n), 2) => Value::Decimal(Rc::new(n.into())),
+        (Value::BigInt(n), 2) => Value::Decimal(Rc::new(BigDecimal::from((*n).clone()))),
+        (v, _) => Value::Num(v.as_f64().unwrap()),
+    }
+}
+
+// The result of checked `Int` arithmetic, which is `None` if it overflowed,
+// when `big` does it again on `BigInt`s
+fn checked(n: Option<i64>, a: i64, b: i64, big: fn(BigInt, BigInt) -> BigInt) -> Res {
+    match n {
+        Some(n) => Res::V(Value::Int(n)),
+        None => Res::V(Value::big(big(a.into(), b.into()))),
+    }
+}
+
+// How numbers compare when one is a `BigInt` or a `Decimal`: exactly, as
+// decimals, with NaN unordered and the infinities past everything
+fn exact(a: &Value, b: &Value) -> Option<Ordering> {
+    fn decimal(v: &Value) -> Option<BigDecimal> {
+        match v {
+            Value::Int(n) => Some(BigDecimal::from(*n)),
+            Value::BigInt(n) => Some(BigDecimal::from((**n).clone())),
+            Value::Decimal(n) => Some((**n).clone()),
+            Value::Num(n) => BigDecimal::try_from(*n).ok(),
+            _ => None,
         }
     }
+    match (a, b) {
+        (Value::Num(n), _) | (_, Value::Num(n)) if n.is_nan() => None,
+        (Value::Num(n), _) if n.is_infinite() && rank(b).is_some() => Some(if *n > 0.0 {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        }),
+        (_, Value::Num(n)) if n.is_infinite() && rank(a).is_some() => Some(if *n > 0.0 {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        }),
+        _ => Some(decimal(a)?.cmp(&decimal(b)?)),
+    }
 }
 
-impl<'a> Add for Value<'a> {
-    type Output = Res<'a>;
+fn is_big(v: &Value) -> bool {
+    matches!(v, Value::BigInt(_) | Value::Decimal(_))
+}
+
+// How an `Int` compares with a `Num`. Casting either one to the other's
+// type could round, so this compares whole parts and then the fraction.
+fn compare(a: i64, b: f64) -> Option<Ordering> {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+    if b.is_nan() {
+        None
+    } else if b >= LIMIT {
+        Some(Ordering::Less)
+    } else if b < -LIMIT {
+        Some(Ordering::Greater)
+    } else {
+        match a.cmp(&(b.trunc() as i64)) {
+            Ordering::Equal => 0.0.partial_cmp(&b.fract()),
+            order => Some(order),
+        }
+    }
+}
 
-    fn add(self, other: Self) -> Res<'a> {
+fn unsupported(what: &str, sym: &str, lhs: Value, rhs: Value) -> Res {
+    let msg = format!(
+        "{} not supported: {} {} {}",
+        what,
+        lhs.type_name(),
+        sym,
+        rhs.type_name()
+    );
+    Res::E(Error::new("TypeError", msg))
+}
+
+impl Add for Value {
+    type Output = Res;
+
+    fn add(self, other: Self) -> Res {
         // Destructure once for all 25 possible (LHS, RHS) pairs
-        match (self, other) {
+        match promote(self, other) {
             // — Numeric + numeric
+            (Value::Int(a), Value::Int(b)) => checked(a.checked_add(b), a, b, |a, b| a + b),
+            (Value::BigInt(a), Value::BigInt(b)) => Res::V(Value::big(&*a + &*b)),
+            (Value::Decimal(a), Value::Decimal(b)) => Res::V(Value::Decimal(Rc::new(&*a + &*b))),
             (Value::Num(a), Value::Num(b)) => Res::V(Value::Num(a + b)),
 
             // — String/number, string/bool concatenations
+            (Value::Int(a), Value::Str(b)) => Res::V(Value::Str(format!("{}{}", a, b))),
+            (Value::Str(a), Value::Int(b)) => Res::V(Value::Str(format!("{}{}", a, b))),
             (Value::Num(a), Value::Str(b)) => Res::V(Value::Str(format!("{}{}", a, b))),
             (Value::Str(a), Value::Num(b)) => Res::V(Value::Str(format!("{}{}", a, b))),
+            (a @ (Value::BigInt(_) | Value::Decimal(_)), Value::Str(b)) => {
+                Res::V(Value::Str(format!("{}{}", a, b)))
+            }
+            (Value::Str(a), b @ (Value::BigInt(_) | Value::Decimal(_))) => {
+                Res::V(Value::Str(format!("{}{}", a, b)))
+            }
             (Value::Bool(a), Value::Str(b)) => Res::V(Value::Str(format!("{}{}", a, b))),
             (Value::Str(a), Value::Bool(b)) => Res::V(Value::Str(format!("{}{}", a, b))),
             (Value::Str(a), Value::Str(b)) => Res::V(Value::Str(format!("{}{}", a, b))),
 
             // — Boolean acts like 0(false) or 1(true):
+            (Value::Int(a), Value::Bool(b)) => {
+                checked(a.checked_add(b as i64), a, b as i64, |a, b| a + b)
+            }
+            (Value::Bool(a), Value::Int(b)) => {
+                checked(b.checked_add(a as i64), b, a as i64, |a, b| a + b)
+            }
             (Value::Num(mut a), Value::Bool(b)) => {
                 if b {
                     a += 1.0
@@ -62,9 +567,14 @@ impl<'a> Add for Value<'a> {
 
             // — Undefined acts like additive zero or empty:
             (Value::Undefined, Value::Undefined) => Res::V(Value::Undefined),
+            (Value::Undefined, Value::Int(n)) | (Value::Int(n), Value::Undefined) => {
+                Res::V(Value::Int(n))
+            }
             (Value::Undefined, Value::Num(n)) | (Value::Num(n), Value::Undefined) => {
                 Res::V(Value::Num(n))
             }
+            (Value::Undefined, n @ (Value::BigInt(_) | Value::Decimal(_)))
+            | (n @ (Value::BigInt(_) | Value::Decimal(_)), Value::Undefined) => Res::V(n),
             (Value::Undefined, Value::Str(s)) | (Value::Str(s), Value::Undefined) => {
                 Res::V(Value::Str(s))
             }
@@ -76,36 +586,44 @@ impl<'a> Add for Value<'a> {
             }
 
             // — Vector concatenation
-            (Value::Vec(mut a), Value::Vec(b)) => {
-                a.extend(b);
-                Res::V(Value::Vec(a))
+            (Value::Vec(a), Value::Vec(b)) => {
+                let mut list = a.data.borrow().clone();
+                list.extend(b.data.borrow().iter().cloned());
+                Res::V(Value::Vec(Shared::new(list)))
             }
 
-            // — Object (map) merge: RHS entries override LHS on key conflict
-            (Value::Obj(mut a), Value::Obj(b)) => {
-                a.extend(b);
-                Res::V(Value::Obj(a))
+            // — Object (map) merge: RHS entries override LHS on key conflict,
+            //   keeping the LHS order; new keys go last
+            (Value::Obj(a), Value::Obj(b)) => {
+                let mut obj = a.data.borrow().clone();
+                obj.extend(b.data.borrow().iter().map(|(k, v)| (k.clone(), v.clone())));
+                Res::V(Value::Obj(Shared::new(obj)))
             }
 
             // — Everything else is unsupported
-            (lhs, rhs) => Res::E(format!(
-                "Addition not supported: {} + {}",
-                lhs.type_name(),
-                rhs.type_name(),
-            )),
+            (lhs, rhs) => unsupported("Addition", "+", lhs, rhs),
         }
     }
 }
 
-impl<'a> Sub for Value<'a> {
-    type Output = Res<'a>;
+impl Sub for Value {
+    type Output = Res;
 
-    fn sub(self, other: Self) -> Res<'a> {
-        match (self, other) {
+    fn sub(self, other: Self) -> Res {
+        match promote(self, other) {
             // — Numeric subtraction
+            (Value::Int(a), Value::Int(b)) => checked(a.checked_sub(b), a, b, |a, b| a - b),
+            (Value::BigInt(a), Value::BigInt(b)) => Res::V(Value::big(&*a - &*b)),
+            (Value::Decimal(a), Value::Decimal(b)) => Res::V(Value::Decimal(Rc::new(&*a - &*b))),
             (Value::Num(a), Value::Num(b)) => Res::V(Value::Num(a - b)),
 
             // — Boolean acts like 0(false) or 1(true):
+            (Value::Int(a), Value::Bool(b)) => {
+                checked(a.checked_sub(b as i64), a, b as i64, |a, b| a - b)
+            }
+            (Value::Bool(a), Value::Int(b)) => {
+                checked(b.checked_sub(a as i64), b, a as i64, |a, b| a - b)
+            }
             (Value::Num(mut a), Value::Bool(b)) => {
                 if b {
                     a -= 1.0
@@ -121,9 +639,14 @@ impl<'a> Sub for Value<'a> {
 
             // — Undefined as zero/empty
             (Value::Undefined, Value::Undefined) => Res::V(Value::Undefined),
+            (Value::Undefined, Value::Int(n)) | (Value::Int(n), Value::Undefined) => {
+                Res::V(Value::Int(n))
+            }
             (Value::Undefined, Value::Num(n)) | (Value::Num(n), Value::Undefined) => {
                 Res::V(Value::Num(n))
             } // 0 - n = n, n - 0 = n
+            (Value::Undefined, n @ (Value::BigInt(_) | Value::Decimal(_)))
+            | (n @ (Value::BigInt(_) | Value::Decimal(_)), Value::Undefined) => Res::V(n),
             (Value::Undefined, Value::Str(s)) | (Value::Str(s), Value::Undefined) => {
                 Res::V(Value::Str(s))
             } // "" - s = s, s - "" = s
@@ -156,32 +679,35 @@ impl<'a> Sub for Value<'a> {
                 Res::V(Value::Vec(diff)) // element‑wise via zip/map :contentReference[oaicite:5]{index=5} :contentReference[oaicite:6]{index=6}
             } */
             // — Obj key‑removal: drop any key in `b` from `a`
-            (Value::Obj(mut a), Value::Obj(b)) => {
-                for key in b.keys() {
-                    a.remove(key); // HashMap::remove :contentReference[oaicite:7]{index=7}
+            (Value::Obj(a), Value::Obj(b)) => {
+                let mut obj = a.data.borrow().clone();
+                for key in b.data.borrow().keys() {
+                    obj.shift_remove(key); // keeps the order of the rest
                 }
-                Res::V(Value::Obj(a))
+                Res::V(Value::Obj(Shared::new(obj)))
             }
 
             // — All other combinations are unsupported
-            (lhs, rhs) => Res::E(format!(
-                "Subtraction not supported: {} - {}",
-                lhs.type_name(),
-                rhs.type_name(),
-            )),
+            (lhs, rhs) => unsupported("Subtraction", "-", lhs, rhs),
         }
     }
 }
 
-impl<'a> Mul for Value<'a> {
-    type Output = Res<'a>;
+impl Mul for Value {
+    type Output = Res;
 
-    fn mul(self, other: Self) -> Res<'a> {
-        match (self, other) {
+    fn mul(self, other: Self) -> Res {
+        match promote(self, other) {
             // — Num × Num
+            (Value::Int(a), Value::Int(b)) => checked(a.checked_mul(b), a, b, |a, b| a * b),
+            (Value::BigInt(a), Value::BigInt(b)) => Res::V(Value::big(&*a * &*b)),
+            (Value::Decimal(a), Value::Decimal(b)) => Res::V(Value::Decimal(Rc::new(&*a * &*b))),
             (Value::Num(a), Value::Num(b)) => Res::V(Value::Num(a * b)),
 
             // — Str × Num or Num × Str: repeat string n times
+            (Value::Str(s), Value::Int(n)) | (Value::Int(n), Value::Str(s)) => {
+                Res::V(Value::Str(s.repeat(n.max(0) as usize)))
+            }
             (Value::Str(s), Value::Num(n)) => {
                 let count = if n >= 0.0 { n as usize } else { 0 };
                 Res::V(Value::Str(s.repeat(count)))
@@ -228,24 +754,39 @@ impl<'a> Mul for Value<'a> {
                 Res::V(Value::Vec(scaled))
             } */
             // — All other cases unsupported
-            (lhs, rhs) => Res::E(format!(
-                "Multiplication not supported: {} × {}",
-                lhs.type_name(),
-                rhs.type_name(),
-            )),
+            (lhs, rhs) => unsupported("Multiplication", "×", lhs, rhs),
         }
     }
 }
 
-impl<'a> Div for Value<'a> {
-    type Output = Res<'a>;
+impl Div for Value {
+    type Output = Res;
 
-    fn div(self, other: Self) -> Res<'a> {
-        match (self, other) {
-            // — Num ÷ Num, with zero‑check
+    fn div(self, other: Self) -> Res {
+        match promote(self, other) {
+            // — Num ÷ Num, with zero‑check. Dividing `Int`s gives a `Num`;
+            //   `~/` is the division that stays whole
+            (Value::Int(a), Value::Int(b)) => {
+                if b == 0 {
+                    return Res::E(Error::new("ZeroDivisionError", "Division by zero"));
+                }
+                Res::V(Value::Num(a as f64 / b as f64))
+            }
+            (Value::BigInt(a), Value::BigInt(b)) => {
+                if b.is_zero() {
+                    return Res::E(Error::new("ZeroDivisionError", "Division by zero"));
+                }
+                Res::V(Value::Num(a.to_f64().unwrap() / b.to_f64().unwrap()))
+            }
+            (Value::Decimal(a), Value::Decimal(b)) => {
+                if b.is_zero() {
+                    return Res::E(Error::new("ZeroDivisionError", "Division by zero"));
+                }
+                Res::V(Value::Decimal(Rc::new(&*a / &*b)))
+            }
             (Value::Num(a), Value::Num(b)) => {
                 if b == 0.0 {
-                    return Res::E("Division by zero".into()); // clear runtime error
+                    return Res::E(Error::new("ZeroDivisionError", "Division by zero"));
                 }
                 Res::V(Value::Num(a / b))
             }
@@ -302,21 +843,66 @@ impl<'a> Div for Value<'a> {
                 Res::V(Value::Vec(inv))
             } */
             // — All other combinations unsupported
-            (lhs, rhs) => Res::E(format!(
-                "Division not supported: {} ÷ {}",
-                lhs.type_name(),
-                rhs.type_name(),
-            )),
+            (lhs, rhs) => unsupported("Division", "÷", lhs, rhs),
         }
     }
 }
 
-impl<'a> Neg for Value<'a> {
-    type Output = Res<'a>;
+impl Rem for Value {
+    type Output = Res;
 
-    fn neg(self) -> Res<'a> {
+    fn rem(self, other: Self) -> Res {
+        match promote(self, other) {
+            // — Num % Num, taking the dividend's sign, with zero‑check
+            (Value::Int(a), Value::Int(b)) => {
+                if b == 0 {
+                    return Res::E(Error::new("ZeroDivisionError", "Modulo by zero"));
+                }
+                Res::V(Value::Int(a.wrapping_rem(b)))
+            }
+            (Value::BigInt(a), Value::BigInt(b)) => {
+                if b.is_zero() {
+                    return Res::E(Error::new("ZeroDivisionError", "Modulo by zero"));
+                }
+                Res::V(Value::big(&*a % &*b))
+            }
+            (Value::Decimal(a), Value::Decimal(b)) => {
+                if b.is_zero() {
+                    return Res::E(Error::new("ZeroDivisionError", "Modulo by zero"));
+                }
+                Res::V(Value::Decimal(Rc::new(&*a % &*b)))
+            }
+            (Value::Num(a), Value::Num(b)) => {
+                if b == 0.0 {
+                    return Res::E(Error::new("ZeroDivisionError", "Modulo by zero"));
+                }
+                Res::V(Value::Num(a % b))
+            }
+
+            // — Undefined as in division
+            (Value::Undefined, Value::Undefined) => Res::V(Value::Undefined),
+            (lhs, Value::Undefined) => Res::V(lhs),
+            (Value::Undefined, _) => Res::V(Value::Undefined),
+
+            // — All other combinations unsupported
+            (lhs, rhs) => unsupported("Modulo", "%", lhs, rhs),
+        }
+    }
+}
+
+impl Neg for Value {
+    type Output = Res;
+
+    fn neg(self) -> Res {
         match self {
-            // — Numeric: just negate the f64
+            // — Numeric: just negate, except that the one i64 without a
+            //   positive counterpart has a `BigInt` one
+            Value::Int(n) => match n.checked_neg() {
+                Some(n) => Res::V(Value::Int(n)),
+                None => Res::V(Value::big(-BigInt::from(n))),
+            },
+            Value::BigInt(n) => Res::V(Value::big(-&*n)),
+            Value::Decimal(n) => Res::V(Value::Decimal(Rc::new(-&*n))),
             Value::Num(n) => Res::V(Value::Num(-n)),
 
             // — Undefined stays undefined (acts like 0)
@@ -334,18 +920,55 @@ impl<'a> Neg for Value<'a> {
                 Res::V(Value::Vec(negated))
             } */
             // — Str and Obj can’t be meaningfully negated
-            other @ _ => Res::E(format!("Negation not supported: {}", other.type_name())),
+            other => {
+                let msg = format!("Negation not supported: {}", other.type_name());
+                Res::E(Error::new("TypeError", msg))
+            }
+        }
+    }
+}
+
+// Numbers of different kinds are equal when they are the same number
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Undefined, Value::Undefined) => true,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Num(a), Value::Num(b)) => a == b,
+            (Value::Int(a), Value::Num(b)) | (Value::Num(b), Value::Int(a)) => {
+                compare(*a, *b) == Some(Ordering::Equal)
+            }
+            (a, b) if is_big(a) || is_big(b) => exact(a, b) == Some(Ordering::Equal),
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Vec(a), Value::Vec(b)) => a == b,
+            (Value::Obj(a), Value::Obj(b)) => a == b,
+            (Value::Fn(a, body, scope), Value::Fn(b, other, with)) => {
+                a == b && body == other && scope == with
+            }
+            (Value::Proto(a, x), Value::Proto(b, y)) => {
+                a == b
+                    && x.len() == y.len()
+                    && x.iter().zip(y.iter()).all(|(x, y)| Rc::ptr_eq(x, y))
+            }
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            (Value::Method(a, name), Value::Method(b, other)) => a == b && name == other,
+            _ => false,
         }
     }
 }
 
-impl<'a> Eq for Value<'a> {}
+impl Eq for Value {}
 
-impl<'a> PartialOrd for Value<'a> {
+impl PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
             // Numeric comparison
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
             (Value::Num(a), Value::Num(b)) => a.partial_cmp(b),
+            (Value::Int(a), Value::Num(b)) => compare(*a, *b),
+            (Value::Num(a), Value::Int(b)) => compare(*b, *a).map(Ordering::reverse),
+            (a, b) if is_big(a) || is_big(b) => exact(a, b),
 
             // String lexicographical
             (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
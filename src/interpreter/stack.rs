@@ -0,0 +1,109 @@
+use super::super::types::Value;
+use super::{Error, Frame};
+use std::cell::RefCell;
+
+/// How deep a program may go before the interpreter raises a
+/// `StackOverflowError`.
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Calls in progress at once.
+    pub calls: usize,
+    /// Expressions being evaluated inside one another, across calls. Each
+    /// level takes native stack, so this is what keeps deep recursion from
+    /// crashing the process; the embedder must run the interpreter on a
+    /// thread whose stack can hold this many.
+    pub depth: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            calls: 1000,
+            depth: 10_000,
+        }
+    }
+}
+
+// The calls in progress, outermost first, how deeply nested the expression
+// being evaluated is, and the tail call the current body ended with
+struct CallStack {
+    frames: Vec<Frame>,
+    depth: usize,
+    tail: Option<(Value, Vec<Value>)>,
+    limits: Limits,
+}
+
+thread_local! {
+    static STACK: RefCell<CallStack> = RefCell::new(CallStack {
+        frames: Vec::new(),
+        depth: 0,
+        tail: None,
+        limits: Limits::default(),
+    });
+}
+
+pub fn set_limits(limits: Limits) {
+    STACK.with_borrow_mut(|stack| stack.limits = limits);
+}
+
+/// Records a call as started, unless that would go past the limit.
+pub fn enter(frame: Frame) -> Result<(), Error> {
+    STACK.with_borrow_mut(|stack| {
+        if stack.frames.len() >= stack.limits.calls {
+            let msg = format!(
+                "Stack overflow: more than {} nested calls.",
+                stack.limits.calls
+            );
+            return Err(Error::new("StackOverflowError", msg));
+        }
+        stack.frames.push(frame);
+        Ok(())
+    })
+}
+
+/// Records the innermost call as finished, however it ended.
+pub fn leave() {
+    STACK.with_borrow_mut(|stack| stack.frames.pop());
+}
+
+/// Records the start of an expression nested in the current one, unless
+/// that would go past the limit.
+pub fn descend() -> Result<(), Error> {
+    STACK.with_borrow_mut(|stack| {
+        if stack.depth >= stack.limits.depth {
+            let msg = format!(
+                "Stack overflow: expressions nested more than {} deep.",
+                stack.limits.depth
+            );
+            return Err(Error::new("StackOverflowError", msg));
+        }
+        stack.depth += 1;
+        Ok(())
+    })
+}
+
+/// Records the innermost expression as evaluated.
+pub fn ascend() {
+    STACK.with_borrow_mut(|stack| stack.depth -= 1);
+}
+
+/// Leaves a call for `apply` to make once the current function's body is
+/// done, in place of the call running it, which `frame` now stands for.
+pub fn defer(callee: Value, args: Vec<Value>, frame: Frame) {
+    STACK.with_borrow_mut(|stack| {
+        if let Some(top) = stack.frames.last_mut() {
+            *top = frame;
+        }
+        stack.tail = Some((callee, args));
+    });
+}
+
+/// The call the current function's body ended with, if it was a tail call.
+pub fn deferred() -> Option<(Value, Vec<Value>)> {
+    STACK.with_borrow_mut(|stack| stack.tail.take())
+}
+
+/// The calls in progress, innermost first.
+pub fn trace() -> Vec<Frame> {
+    STACK.with_borrow(|stack| stack.frames.iter().rev().cloned().collect())
+}
@@ -1,27 +1,151 @@
-use ast::{lex, parse, show_errors};
-use std::{env, fs, process::exit};
+use ast::{position, show_spanned_errors};
+use std::path::{Path, PathBuf};
+use std::{env, fs, process::exit, thread};
+use types::BSE;
 
 mod ast;
+mod checker;
 mod interpreter;
+mod resolver;
+mod symbol;
+mod transpiler;
 pub mod types;
+mod vm;
+
+// Every Pulse call nests several interpreter frames on the native stack,
+// and the main thread's is too small for the default recursion limit
+const STACK_SIZE: usize = 256 * 1024 * 1024;
 
 fn main() {
-    let filename = env::args().nth(1).expect("Expected file argument");
-    let source = fs::read_to_string(&filename).unwrap();
+    let pulse = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(pulse)
+        .expect("Cannot start the interpreter thread");
+    if pulse.join().is_err() {
+        exit(101);
+    }
+}
+
+fn pulse() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    // `pulse check <file>` type checks without running; `--types` also
+    // lists the inferred type of every binding. `pulse build --target
+    // <lang> <file> [-o <dir>]` compiles to another language.
+    // `--recursion-limit <n>` caps how deep calls may nest, on either
+    // engine, and `--max-depth <n>` how deep interpreted expressions may,
+    // calls included.
+    let command = args
+        .first()
+        .filter(|a| *a == "check" || *a == "build")
+        .map(String::as_str);
+    let use_vm = args.iter().any(|a| a == "--vm");
+    let filename = args
+        .iter()
+        .enumerate()
+        .skip(command.is_some() as usize)
+        .find(|(i, a)| !a.starts_with('-') && (*i == 0 || !takes_value(&args[i - 1])))
+        .map(|(_, a)| a)
+        .expect("Expected file argument");
+
+    let (source, mut ast) = load(filename);
 
-    let (tokens, lex_errs) = lex(&source);
-    let tokens = tokens.unwrap_or_else(|| {
-        show_errors(lex_errs, filename.clone(), &source);
+    let errs = resolver::resolve(&mut ast);
+    if !errs.is_empty() {
+        show_spanned_errors(errs, filename.clone(), &source);
         exit(1);
-    });
+    }
+
+    if command == Some("build") {
+        let target = option(&args, "--target").unwrap_or_else(|| {
+            eprintln!("Expected --target <lang>");
+            exit(2);
+        });
+        let target = transpiler::Target::parse(target).unwrap_or_else(|| {
+            eprintln!("Unknown target '{}'", target);
+            exit(2);
+        });
+        let errs = transpiler::unsupported(&ast);
+        if !errs.is_empty() {
+            show_spanned_errors(errs, filename.clone(), &source);
+            exit(1);
+        }
+        let name = Path::new(filename)
+            .file_stem()
+            .map_or("program".into(), |s| s.to_string_lossy());
+        let out = option(&args, "-o")
+            .map_or_else(|| PathBuf::from(format!("{}-build", name)), PathBuf::from);
+        if let Err(e) = transpiler::build(target, &ast, filename, &source, &out) {
+            eprintln!("Cannot write {}: {}", out.display(), e);
+            exit(1);
+        }
+        return;
+    }
+
+    if command == Some("check") {
+        let checked = checker::check(&ast);
+        if args.iter().any(|a| a == "--types") {
+            for (span, name, ty) in checked.types {
+                let (line, col) = position(&source, span.start);
+                println!("{}:{}:{} {}: {}", filename, line, col, name, ty);
+            }
+        }
+        if !checked.errors.is_empty() {
+            show_spanned_errors(checked.errors, filename.clone(), &source);
+            exit(1);
+        }
+        return;
+    }
+
+    //println!("{:#?}", ast);
+
+    let defaults = interpreter::Limits::default();
+    let limits = interpreter::Limits {
+        calls: limit(&args, "--recursion-limit").unwrap_or(defaults.calls),
+        depth: limit(&args, "--max-depth").unwrap_or(defaults.depth),
+    };
+    if use_vm {
+        let program = vm::compile(&ast).unwrap_or_else(|errs| {
+            show_spanned_errors(errs, filename.clone(), &source);
+            exit(1);
+        });
+        vm::run(&program, filename, &source, limits);
+    } else {
+        interpreter::set_limits(limits);
+        interpreter::run(ast, filename, &source);
+    }
+}
 
-    let (ast, parse_errs) = parse(&tokens, &source);
-    let ast = ast.unwrap_or_else(|| {
-        show_errors(parse_errs, filename.clone(), &source);
+/// Reads and parses `filename`. The returned AST owns all of its data; the
+/// source text is only handed back for error reporting.
+fn load(filename: &str) -> (String, Vec<BSE>) {
+    let source = fs::read_to_string(filename).unwrap_or_else(|e| {
+        eprintln!("Cannot read {}: {}", filename, e);
         exit(1);
     });
+    match ast::read(filename, &source) {
+        Some(ast) => (source, ast),
+        None => exit(1),
+    }
+}
 
-    //println!("{:#?}", ast);
+fn takes_value(flag: &str) -> bool {
+    matches!(
+        flag,
+        "--target" | "-o" | "--recursion-limit" | "--max-depth"
+    )
+}
+
+// The argument following `flag`, if it was given
+fn option<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let i = args.iter().position(|a| a == flag)?;
+    args.get(i + 1).map(String::as_str)
+}
 
-    interpreter::run(ast);
+// The count following `flag`, if it was given
+fn limit(args: &[String], flag: &str) -> Option<usize> {
+    let limit = option(args, flag)?;
+    Some(limit.parse().unwrap_or_else(|_| {
+        eprintln!("Expected a number after {}", flag);
+        exit(2);
+    }))
 }
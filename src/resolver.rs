@@ -0,0 +1,272 @@
+use super::types::{BSE, Expr, Imports, Place, PropKey, Signature, Slot, Span, Spanned, Symbol};
+use std::collections::HashMap;
+
+// Static scope resolution, run between `ast::parse` and execution. Scopes
+// here mirror the environments the interpreter creates at runtime: one per
+// function call, one per `if` branch and one per loop iteration. Blocks do
+// not open a scope of their own.
+struct Resolver {
+    // Local scopes, innermost last; a name's position is its slot index.
+    // The flag (here and in `globals`) marks `const` bindings.
+    scopes: Vec<Vec<(Symbol, bool)>>,
+    globals: HashMap<Symbol, bool>,
+    in_fn: usize,
+    // Global reads and writes that must be checked once every top-level
+    // declaration is known. Function bodies may refer to globals declared
+    // after them; top-level code may not.
+    pending: Vec<(Symbol, Span, Access)>,
+    errors: Vec<Spanned<String>>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    EarlyRead,
+    EarlyWrite,
+}
+
+pub fn resolve(ast: &mut [BSE]) -> Vec<Spanned<String>> {
+    let mut resolver = Resolver {
+        scopes: Vec::new(),
+        globals: HashMap::new(),
+        in_fn: 0,
+        pending: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for stmt in ast.iter_mut() {
+        resolver.expr(stmt);
+    }
+
+    for (name, span, access) in std::mem::take(&mut resolver.pending) {
+        let declared = resolver.globals.contains_key(&name);
+        let constant = resolver.globals.get(&name) == Some(&true);
+        let msg = match access {
+            Access::EarlyRead | Access::EarlyWrite if declared => {
+                format!("Variable '{}' is used before its declaration.", name)
+            }
+            Access::Read | Access::EarlyRead if !declared => {
+                format!("Undefined variable '{}'.", name)
+            }
+            Access::Write | Access::EarlyWrite if !declared => {
+                format!("Assignment to undeclared variable '{}'.", name)
+            }
+            Access::Write if constant => format!("Cannot assign to constant '{}'.", name),
+            _ => continue,
+        };
+        resolver.errors.push((msg, span));
+    }
+
+    resolver.errors.sort_by_key(|(_, span)| span.start);
+    resolver.errors
+}
+
+impl Resolver {
+    fn error(&mut self, msg: String, span: Span) {
+        self.errors.push((msg, span));
+    }
+
+    fn declare(&mut self, name: Symbol, constant: bool, span: Span) -> Slot {
+        let duplicate = match self.scopes.last_mut() {
+            Some(scope) => match scope.iter().position(|(n, _)| *n == name) {
+                Some(_) => true,
+                None => {
+                    scope.push((name, constant));
+                    false
+                }
+            },
+            None => self.globals.insert(name, constant).is_some(),
+        };
+
+        if duplicate {
+            self.error(
+                format!("Variable '{}' is already declared in this scope.", name),
+                span,
+            );
+        }
+
+        match self.scopes.last() {
+            Some(scope) => Slot::Local(0, scope.iter().position(|(n, _)| *n == name).unwrap()),
+            None => Slot::Global,
+        }
+    }
+
+    fn lookup(&mut self, name: Symbol, span: Span, write: bool) -> Slot {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(idx) = scope.iter().position(|(n, _)| *n == name) {
+                if write && scope[idx].1 {
+                    let msg = format!("Cannot assign to constant '{}'.", name);
+                    self.errors.push((msg, span));
+                }
+                return Slot::Local(depth, idx);
+            }
+        }
+
+        let access = match (self.in_fn > 0, write) {
+            (true, false) => Access::Read,
+            (true, true) => Access::Write,
+            (false, false) => Access::EarlyRead,
+            (false, true) => Access::EarlyWrite,
+        };
+        match self.globals.get(&name) {
+            _ if self.in_fn > 0 => self.pending.push((name, span, access)),
+            None => self.pending.push((name, span, access)),
+            Some(true) if write => {
+                self.error(format!("Cannot assign to constant '{}'.", name), span)
+            }
+            Some(_) => {}
+        }
+        Slot::Global
+    }
+
+    // Resolves a function's body in a scope of its own, which starts with
+    // its parameters
+    fn function(&mut self, signature: &Signature, body: &mut BSE, span: Span) {
+        let mut scope = Vec::new();
+        for param in signature.params.iter() {
+            if scope.contains(&(param.name, false)) {
+                self.error(format!("Duplicate parameter '{}'.", param.name), span);
+            }
+            scope.push((param.name, false));
+        }
+
+        self.scopes.push(scope);
+        self.in_fn += 1;
+        self.expr(body);
+        self.in_fn -= 1;
+        self.scopes.pop();
+    }
+
+    fn scoped(&mut self, expr: &mut BSE) {
+        self.scopes.push(Vec::new());
+        self.expr(expr);
+        self.scopes.pop();
+    }
+
+    fn expr(&mut self, expr: &mut BSE) {
+        let span = expr.1;
+        match &mut expr.0 {
+            Expr::Var(name, slot) => *slot = self.lookup(*name, span, false),
+
+            Expr::Let(name, slot, _, value) => {
+                // The initializer is resolved first, so `let x = x + 1`
+                // reads an outer `x`.
+                self.expr(value);
+                *slot = self.declare(*name, false, span);
+            }
+
+            Expr::Const(name, slot, _, value) => {
+                self.expr(value);
+                *slot = self.declare(*name, true, span);
+            }
+
+            Expr::Assign(name, slot, value) => {
+                self.expr(value);
+                *slot = self.lookup(*name, span, true);
+            }
+
+            Expr::Fn(name, slot, signature, body) => {
+                *slot = self.declare(*name, false, span);
+                self.function(signature, body, span);
+            }
+
+            Expr::If(cond, then, els) => {
+                self.expr(cond);
+                self.scoped(then);
+                self.scoped(els);
+            }
+
+            Expr::While(cond, body) => {
+                self.expr(cond);
+                self.scoped(body);
+            }
+
+            // Each part runs in a scope of its own; the handler's starts
+            // with the caught error
+            Expr::Try(body, catch, finally) => {
+                self.scoped(body);
+                if let Some((name, slot, handler)) = catch {
+                    self.scopes.push(vec![(*name, false)]);
+                    *slot = Slot::Local(0, 0);
+                    self.expr(handler);
+                    self.scopes.pop();
+                }
+                if let Some(finally) = finally {
+                    self.scoped(finally);
+                }
+            }
+
+            // Imported names are constants in the importing module
+            Expr::Import(_, imports) => {
+                if !self.scopes.is_empty() {
+                    self.error("Imports are only allowed at the top level.".into(), span);
+                }
+                match imports {
+                    Imports::Module(name, slot) => *slot = self.declare(*name, true, span),
+                    Imports::Names(names) => {
+                        for (name, slot) in names.iter_mut() {
+                            *slot = self.declare(*name, true, span);
+                        }
+                    }
+                }
+            }
+
+            Expr::Export(decl) => {
+                if !self.scopes.is_empty() {
+                    self.error("Exports are only allowed at the top level.".into(), span);
+                }
+                self.expr(decl);
+            }
+
+            // Local functions are declared before anything in their scope
+            // is resolved, so they can call each other whatever their order
+            Expr::Block(stmts) if !self.scopes.is_empty() => {
+                for stmt in stmts.iter_mut() {
+                    if let Expr::Fn(name, slot, ..) = &mut stmt.0 {
+                        *slot = self.declare(*name, false, stmt.1);
+                    }
+                }
+                for stmt in stmts.iter_mut() {
+                    match &mut stmt.0 {
+                        Expr::Fn(_, _, signature, body) => self.function(signature, body, stmt.1),
+                        _ => self.expr(stmt),
+                    }
+                }
+            }
+            Expr::Block(stmts) => stmts.iter_mut().for_each(|s| self.expr(s)),
+            Expr::UnOp(_, value)
+            | Expr::Return(value)
+            | Expr::Field(value, _)
+            | Expr::Throw(value) => self.expr(value),
+            Expr::BnOp(left, _, right)
+            | Expr::Index(left, right)
+            | Expr::Set(Place::Field(left, _), _, right) => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Set(Place::Index(object, index), _, value) => {
+                self.expr(object);
+                self.expr(index);
+                self.expr(value);
+            }
+            Expr::Call(callee, args) | Expr::TailCall(callee, args) => {
+                self.expr(callee);
+                args.iter_mut().for_each(|a| self.expr(a));
+            }
+            Expr::ReservedCall(_, args) | Expr::List(args) => {
+                args.iter_mut().for_each(|a| self.expr(a))
+            }
+            Expr::Obj(entries) => {
+                for (key, value) in entries {
+                    if let PropKey::Computed(key) = key {
+                        self.expr(key);
+                    }
+                    self.expr(value);
+                }
+            }
+
+            Expr::Val(_) | Expr::Error | Expr::Comment(_) | Expr::Nl | Expr::ReservedVar(_) => {}
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use core::fmt;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+
+/// An interned identifier. Comparing and hashing a `Symbol` is a plain
+/// integer operation, and it carries no borrow of the source text, so the
+/// AST and runtime values built from it are `'static`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, Symbol>,
+    names: Vec<&'static str>,
+}
+
+static INTERNER: LazyLock<Mutex<Interner>> = LazyLock::new(Default::default);
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let mut interner = INTERNER.lock().unwrap();
+        if let Some(&sym) = interner.ids.get(name) {
+            return sym;
+        }
+
+        // Names live for the whole process, so leaking them lets `as_str`
+        // hand out `&'static str` without holding the lock.
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        let sym = Symbol(interner.names.len() as u32);
+        interner.names.push(name);
+        interner.ids.insert(name, sym);
+        sym
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.lock().unwrap().names[self.0 as usize]
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -0,0 +1,736 @@
+//! Compiles a resolved program to an ES2020 module plus a source map.
+//!
+//! Statements stay statements: `if`, `while` and blocks are emitted as their
+//! JS counterparts, and the value a Pulse block produces is routed to where
+//! it is needed (a `return`, or a temporary for loops) by `Target`. Only a
+//! statement used as an expression, which JS cannot express, becomes an
+//! arrow function called on the spot. Every operator goes through the
+//! runtime, since almost none of them behave like JS's.
+//!
+//! Code is generated with span markers embedded in it; `finish` strips them
+//! out and turns them into the mappings of the source map.
+
+use super::super::types::{
+    AssignOp, BSE, Expr, Key, Op, Place, PropKey, Signature, Slot, Symbol, Type, Value,
+};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+pub const RUNTIME: &str = include_str!("runtime.mjs");
+
+// Surround the byte offset of a span start in generated code
+const MARK: char = '\u{1}';
+const END: char = '\u{2}';
+
+/// Returns the module and its source map.
+pub fn generate(ast: &[BSE], file: &str, source: &str, source_name: &str) -> (String, String) {
+    let mut r#gen = Gen {
+        out: String::new(),
+        ind: 0,
+        scopes: Vec::new(),
+        globals: HashMap::new(),
+        ret: None,
+        temps: 0,
+    };
+    // Exports only matter to importers, and `build` turns down programs
+    // with imports
+    let ast: Vec<_> = ast
+        .iter()
+        .map(|stmt| match &stmt.0 {
+            Expr::Export(decl) => decl,
+            _ => stmt,
+        })
+        .collect();
+    for stmt in &ast {
+        if let Expr::Let(name, Slot::Global, ty, _) | Expr::Const(name, Slot::Global, ty, _) =
+            &stmt.0
+        {
+            r#gen.globals.insert(*name, ty.clone());
+        }
+    }
+
+    writeln!(
+        r#gen.out,
+        "// Generated by `pulse build --target js` from {}.",
+        source_name
+    )
+    .unwrap();
+    r#gen
+        .out
+        .push_str("import * as rt from \"./pulse-runtime.mjs\";\n\n");
+    let globals: Vec<_> = ast
+        .iter()
+        .filter_map(|stmt| match &stmt.0 {
+            Expr::Let(name, ..) | Expr::Const(name, ..) => Some(ident(*name)),
+            _ => None,
+        })
+        .collect();
+    if !globals.is_empty() {
+        writeln!(r#gen.out, "let {};\n", globals.join(", ")).unwrap();
+    }
+
+    // Functions need no guarding; everything else runs through `rt.run` so
+    // that, as in the interpreter, an error only stops its own statement
+    for stmt in &ast {
+        match &stmt.0 {
+            Expr::Fn(name, _, signature, body) => r#gen.function(*name, signature, body, stmt),
+            _ => {
+                let body = r#gen.nested(|g| g.stmt(stmt, &Target::Discard));
+                match body.lines().count() {
+                    0 => {}
+                    1 => writeln!(r#gen.out, "rt.run(() => {{ {} }});", body.trim()).unwrap(),
+                    _ => write!(r#gen.out, "rt.run(() => {{\n{}}});\n", body).unwrap(),
+                }
+            }
+        }
+    }
+
+    let (mut code, map) = finish(&r#gen.out, file, source, source_name);
+    writeln!(code, "//# sourceMappingURL={}.map", file).unwrap();
+    (code, map)
+}
+
+// Where the value of a statement goes
+enum Target {
+    Discard,
+    Return,
+    Assign(String),
+}
+
+struct Gen {
+    out: String,
+    ind: usize,
+    // Declared types of locals, mirroring the resolver's scopes so a slot
+    // finds its binding
+    scopes: Vec<Vec<Option<Type>>>,
+    globals: HashMap<Symbol, Option<Type>>,
+    // Return type of the enclosing function; `None` at the top level
+    ret: Option<Option<Type>>,
+    temps: usize,
+}
+
+impl Gen {
+    fn line(&mut self, text: &str) {
+        writeln!(self.out, "{}{}", "  ".repeat(self.ind), text).unwrap();
+    }
+
+    // Generates statements one level in, returning them instead of
+    // appending them
+    fn nested(&mut self, f: impl FnOnce(&mut Gen)) -> String {
+        let outer = std::mem::take(&mut self.out);
+        self.ind += 1;
+        f(self);
+        self.ind -= 1;
+        std::mem::replace(&mut self.out, outer)
+    }
+
+    fn scoped(&mut self, f: impl FnOnce(&mut Gen)) {
+        self.scopes.push(Vec::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: Symbol, ty: Option<Type>) {
+        match self.scopes.last_mut() {
+            Some(scope) => scope.push(ty),
+            None => {
+                self.globals.insert(name, ty);
+            }
+        }
+    }
+
+    fn declared(&self, name: Symbol, slot: Slot) -> Option<Type> {
+        match slot {
+            Slot::Local(depth, idx) => {
+                let scope = self.scopes.len().checked_sub(depth + 1)?;
+                self.scopes[scope].get(idx).cloned().flatten()
+            }
+            Slot::Global => self.globals.get(&name).cloned().flatten(),
+        }
+    }
+
+    fn stmt(&mut self, expr: &BSE, target: &Target) {
+        let mark = mark(expr);
+        match &expr.0 {
+            Expr::Let(name, slot, ty, init) | Expr::Const(name, slot, ty, init) => {
+                let value = self.expr(init);
+                let value = checked(value, ty, "binding", *name);
+                self.declare(*name, ty.clone());
+                let keyword = match (slot, &expr.0) {
+                    (Slot::Global, _) => "",
+                    (_, Expr::Const(..)) => "const ",
+                    _ => "let ",
+                };
+                self.line(&format!("{}{}{} = {};", mark, keyword, ident(*name), value));
+                self.finish(target, "undefined".into());
+            }
+            Expr::Assign(name, slot, value) => {
+                let value = self.expr(value);
+                let value = checked(value, &self.declared(*name, *slot), "binding", *name);
+                self.line(&format!("{}{} = {};", mark, ident(*name), value));
+                self.finish(target, "undefined".into());
+            }
+            Expr::Fn(name, _, signature, body) => {
+                self.declare(*name, None);
+                self.function(*name, signature, body, expr);
+                self.finish(target, "undefined".into());
+            }
+            Expr::Block(stmts) => {
+                for (i, stmt) in stmts.iter().enumerate() {
+                    match i + 1 == stmts.len() {
+                        true => self.stmt(stmt, target),
+                        false => self.stmt(stmt, &Target::Discard),
+                    }
+                }
+                if stmts.is_empty() {
+                    self.finish(target, "undefined".into());
+                }
+            }
+            Expr::If(..) => {
+                self.r#if(expr, target, "");
+                self.line("}");
+            }
+            Expr::While(cond, body) => {
+                let last = match target {
+                    Target::Discard => None,
+                    _ => {
+                        self.temps += 1;
+                        let last = format!("$last{}", self.temps);
+                        self.line(&format!("let {};", last));
+                        Some(last)
+                    }
+                };
+                let cond = self.expr(cond);
+                self.line(&format!("{}while (rt.truthy({})) {{", mark, cond));
+                let body = self.nested(|g| {
+                    let target = last.clone().map_or(Target::Discard, Target::Assign);
+                    g.scoped(|g| g.stmt(body, &target))
+                });
+                self.out.push_str(&body);
+                self.line("}");
+                if let Some(last) = last {
+                    self.finish(target, last);
+                }
+            }
+            Expr::Export(decl) => self.stmt(decl, target),
+            Expr::Return(value) => match self.ret.clone() {
+                Some(ret) => {
+                    let value = self.expr(value);
+                    self.line(&format!("{}return {};", mark, returned(value, &ret)));
+                }
+                None => self.stmt(value, target),
+            },
+            Expr::Val(_) | Expr::Comment(_) | Expr::Nl | Expr::Error => {
+                let value = self.expr(expr);
+                self.finish(target, value)
+            }
+            _ => {
+                let value = self.expr(expr);
+                self.finish(target, format!("{}{}", mark, value))
+            }
+        }
+    }
+
+    // Emits an `if` and any `else if`s chained to it, leaving the closing
+    // brace to the caller
+    fn r#if(&mut self, expr: &BSE, target: &Target, prefix: &str) {
+        let Expr::If(cond, then, els) = &expr.0 else {
+            unreachable!()
+        };
+        let cond = self.expr(cond);
+        self.line(&format!(
+            "{}{}if (rt.truthy({})) {{",
+            prefix,
+            mark(expr),
+            cond
+        ));
+        let then = self.nested(|g| g.scoped(|g| g.stmt(then, target)));
+        self.out.push_str(&then);
+        match &els.0 {
+            Expr::If(..) => self.scoped(|g| g.r#if(els, target, "} else ")),
+            _ => {
+                let els = self.nested(|g| g.scoped(|g| g.stmt(els, target)));
+                if !els.is_empty() {
+                    self.line("} else {");
+                    self.out.push_str(&els);
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, target: &Target, value: String) {
+        match target {
+            Target::Discard if value == "undefined" => {}
+            Target::Discard => self.line(&format!("{};", value)),
+            Target::Return => match self.ret.clone().flatten() {
+                Some(ty) => self.line(&format!("return {};", returned(value, &Some(ty)))),
+                None if value == "undefined" => {}
+                None => self.line(&format!("return {};", value)),
+            },
+            Target::Assign(temp) => self.line(&format!("{} = {};", temp, value)),
+        }
+    }
+
+    fn function(&mut self, name: Symbol, signature: &Signature, body: &BSE, expr: &BSE) {
+        let params: Vec<_> = signature.params.iter().map(|p| ident(p.name)).collect();
+        self.line(&format!(
+            "{}function {}({}) {{",
+            mark(expr),
+            ident(name),
+            params.join(", ")
+        ));
+        let outer = self.ret.replace(signature.ret.clone());
+        self.scopes
+            .push(signature.params.iter().map(|p| p.ty.clone()).collect());
+        let body = self.nested(|g| {
+            for param in &signature.params {
+                if let Some(ty) = &param.ty {
+                    g.line(&format!(
+                        "rt.check({}, {}, \"param\", \"{}\");",
+                        ident(param.name),
+                        r#type(ty),
+                        param.name
+                    ));
+                }
+            }
+            g.stmt(body, &Target::Return)
+        });
+        self.scopes.pop();
+        self.ret = outer;
+        self.out.push_str(&body);
+        self.line("}");
+
+        let annotated = signature.ret.is_some() || signature.params.iter().any(|p| p.ty.is_some());
+        if annotated {
+            self.line(&format!(
+                "rt.typed({}, {});",
+                ident(name),
+                r#type(&signature.r#type())
+            ));
+        }
+    }
+
+    fn expr(&mut self, expr: &BSE) -> String {
+        let mark = mark(expr);
+        match &expr.0 {
+            Expr::Val(value) => literal(value),
+            Expr::Var(name, _) => ident(*name),
+
+            Expr::List(items) => {
+                let items: Vec<_> = items.iter().map(|i| self.expr(i)).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Expr::Obj(entries) if entries.is_empty() => "new Map()".into(),
+            Expr::Obj(entries) => {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = match key {
+                            PropKey::Fixed(key) => key_literal(key),
+                            PropKey::Computed(key) => format!("rt.key({})", self.expr(key)),
+                        };
+                        format!("[{}, {}]", key, self.expr(value))
+                    })
+                    .collect();
+                format!("new Map([{}])", entries.join(", "))
+            }
+
+            Expr::UnOp(op, value) => {
+                let name = match op {
+                    Op::Neg => "neg",
+                    Op::BitNot => "bitNot",
+                    _ => "not",
+                };
+                format!("{}rt.{}({})", mark, name, self.expr(value))
+            }
+            Expr::BnOp(l, op, r) => {
+                format!(
+                    "{}rt.{}({}, {})",
+                    mark,
+                    binary(op),
+                    self.expr(l),
+                    self.expr(r)
+                )
+            }
+
+            Expr::Assign(name, slot, value) => {
+                let value = self.expr(value);
+                let value = checked(value, &self.declared(*name, *slot), "binding", *name);
+                format!("({}{} = {}, undefined)", mark, ident(*name), value)
+            }
+
+            Expr::Call(callee, args) | Expr::TailCall(callee, args) => {
+                let mut parts = vec![self.expr(callee)];
+                parts.extend(args.iter().map(|a| self.expr(a)));
+                let name = match &expr.0 {
+                    Expr::TailCall(..) => "tail",
+                    _ => "call",
+                };
+                format!("{}rt.{}({})", mark, name, parts.join(", "))
+            }
+            Expr::ReservedCall(name, args) => {
+                let args: Vec<_> = args.iter().map(|a| self.expr(a)).collect();
+                match name.as_str() {
+                    "print" => format!("{}rt.print({})", mark, args.join(", ")),
+                    _ => {
+                        let mut parts = vec![format!("\"{}\"", name)];
+                        parts.extend(args);
+                        format!("{}rt.builtin({})", mark, parts.join(", "))
+                    }
+                }
+            }
+
+            Expr::If(cond, then, els) if simple(then) && simple(els) => {
+                let cond = self.expr(cond);
+                let mut branch = |branch: &BSE| {
+                    self.scopes.push(Vec::new());
+                    let value = match &branch.0 {
+                        Expr::Block(stmts) if stmts.len() == 1 => self.expr(&stmts[0]),
+                        _ => self.expr(branch),
+                    };
+                    self.scopes.pop();
+                    value
+                };
+                let (then, els) = (branch(then), branch(els));
+                format!("(rt.truthy({}) ? {} : {})", cond, then, els)
+            }
+            Expr::Field(object, name) => {
+                format!(
+                    "{}rt.field({}, {})",
+                    mark,
+                    self.expr(object),
+                    string(name.as_str())
+                )
+            }
+            Expr::Index(object, index) => {
+                format!(
+                    "{}rt.index({}, {})",
+                    mark,
+                    self.expr(object),
+                    self.expr(index)
+                )
+            }
+            // The object and the index are passed to an arrow function, so
+            // they are evaluated once. `$` keeps its parameters from
+            // shadowing Pulse names.
+            Expr::Set(place, op, value) => {
+                let (args, get, set) = match place {
+                    Place::Field(object, name) => {
+                        let name = string(name.as_str());
+                        (
+                            self.expr(object),
+                            format!("rt.field($o, {})", name),
+                            format!("rt.setField($o, {}, ", name),
+                        )
+                    }
+                    Place::Index(object, index) => (
+                        format!("{}, {}", self.expr(object), self.expr(index)),
+                        "rt.index($o, $i)".to_string(),
+                        "rt.setIndex($o, $i, ".to_string(),
+                    ),
+                };
+                let params = match place {
+                    Place::Field(..) => "$o",
+                    Place::Index(..) => "$o, $i",
+                };
+                let value = self.expr(value);
+                let body = match op {
+                    AssignOp::Replace => format!("{}{})", set, value),
+                    AssignOp::Apply(op) => {
+                        format!("{}rt.{}({}, {}))", set, binary(op), get, value)
+                    }
+                    AssignOp::IfUndefined => {
+                        format!("{} === undefined ? {}{}) : undefined", get, set, value)
+                    }
+                };
+                format!("{}(({}) => {})({})", mark, params, body, args)
+            }
+            Expr::Return(value) if self.ret.is_none() => self.expr(value),
+            Expr::Error
+            | Expr::Comment(_)
+            | Expr::Nl
+            | Expr::ReservedVar(_)
+            | Expr::Import(..)
+            | Expr::Throw(_)
+            | Expr::Try(..) => "undefined".into(),
+
+            // Statements in expression position
+            _ => {
+                let outer = self.ret.replace(None);
+                let body = self.nested(|g| g.stmt(expr, &Target::Return));
+                self.ret = outer;
+                format!("(() => {{\n{}{}}})()", body, "  ".repeat(self.ind))
+            }
+        }
+    }
+}
+
+fn binary(op: &Op) -> &'static str {
+    match op {
+        Op::Add => "add",
+        Op::Sub => "sub",
+        Op::Mul => "mul",
+        Op::Div => "div",
+        Op::Pow => "pow",
+        Op::IntDiv => "intDiv",
+        Op::BitAnd => "bitAnd",
+        Op::BitOr => "bitOr",
+        Op::BitXor => "bitXor",
+        Op::Shl => "shl",
+        Op::Shr => "shr",
+        Op::And => "and",
+        Op::Or => "or",
+        Op::Eq => "eq",
+        Op::Neq => "ne",
+        Op::Gt => "gt",
+        Op::Gte => "ge",
+        Op::Lt => "lt",
+        Op::Lte => "le",
+        _ => "rem",
+    }
+}
+
+// Whether a branch can be a plain JS expression
+fn simple(expr: &BSE) -> bool {
+    match &expr.0 {
+        Expr::Let(..)
+        | Expr::Const(..)
+        | Expr::Fn(..)
+        | Expr::While(..)
+        | Expr::Return(_)
+        | Expr::Export(_) => false,
+        Expr::Block(stmts) => stmts.len() == 1 && simple(&stmts[0]),
+        Expr::If(_, then, els) => simple(then) && simple(els),
+        _ => true,
+    }
+}
+
+fn mark(expr: &BSE) -> String {
+    format!("{}{}{}", MARK, expr.1.start, END)
+}
+
+fn checked(value: String, ty: &Option<Type>, boundary: &str, name: Symbol) -> String {
+    match ty {
+        Some(ty) => format!(
+            "rt.check({}, {}, \"{}\", \"{}\")",
+            value,
+            r#type(ty),
+            boundary,
+            name
+        ),
+        None => value,
+    }
+}
+
+fn returned(value: String, ty: &Option<Type>) -> String {
+    match ty {
+        Some(ty) => format!("rt.check({}, {}, \"return\")", value, r#type(ty)),
+        None => value,
+    }
+}
+
+// Pulse names that mean something else in JS get a `$` appended
+fn ident(name: Symbol) -> String {
+    const RESERVED: &[&str] = &[
+        "arguments",
+        "await",
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "debugger",
+        "default",
+        "delete",
+        "do",
+        "else",
+        "enum",
+        "eval",
+        "export",
+        "extends",
+        "false",
+        "finally",
+        "for",
+        "function",
+        "if",
+        "implements",
+        "import",
+        "in",
+        "instanceof",
+        "interface",
+        "let",
+        "new",
+        "null",
+        "package",
+        "private",
+        "protected",
+        "public",
+        "return",
+        "rt",
+        "static",
+        "super",
+        "switch",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "typeof",
+        "undefined",
+        "var",
+        "void",
+        "while",
+        "with",
+        "yield",
+        "NaN",
+        "Infinity",
+    ];
+    match RESERVED.contains(&name.as_str()) {
+        true => format!("{}$", name),
+        false => name.to_string(),
+    }
+}
+
+// `Int`s are BigInts, so they keep all 64 bits
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Int(n) => format!("{}n", n),
+        Value::Num(n) => n.to_string(),
+        Value::Str(s) => string(s),
+        Value::Bool(b) => b.to_string(),
+        _ => "undefined".into(),
+    }
+}
+
+// Whole number keys are Numbers where that is exact, as `rt.key` makes them
+fn key_literal(key: &Key) -> String {
+    const SAFE: u64 = (1 << 53) - 1;
+    match key {
+        Key::Str(s) => string(s),
+        Key::Int(n) if n.unsigned_abs() <= SAFE => n.to_string(),
+        Key::Int(n) => format!("{}n", n),
+        Key::Num(n) => n.0.to_string(),
+    }
+}
+
+// A double-quoted literal, valid as both JS and JSON
+fn string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn r#type(ty: &Type) -> String {
+    let list = |types: &[Type]| types.iter().map(r#type).collect::<Vec<_>>().join(", ");
+    match ty {
+        Type::List(item) => format!("{{ list: {} }}", r#type(item)),
+        Type::Fn(params, ret) => format!("{{ fn: [{}], ret: {} }}", list(params), r#type(ret)),
+        Type::Union(types) => format!("{{ union: [{}] }}", list(types)),
+        ty => format!("\"{}\"", ty),
+    }
+}
+
+// Strips the span markers out of `code` and encodes them as a version 3
+// source map. Columns count UTF-16 units, as the format requires.
+fn finish(code: &str, file: &str, source: &str, source_name: &str) -> (String, String) {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let position = |offset: usize| {
+        let line = line_starts.partition_point(|&start| start <= offset) - 1;
+        let start = line_starts[line];
+        let col = source[start..offset.min(source.len())]
+            .chars()
+            .map(char::len_utf16)
+            .sum::<usize>();
+        (line as i64, col as i64)
+    };
+
+    let mut out = String::with_capacity(code.len());
+    let mut mappings = String::new();
+    let mut col = 0i64;
+    // Fields of the previous segment, which each segment is relative to
+    let (mut prev_col, mut prev_line, mut prev_src_col) = (0i64, 0i64, 0i64);
+    let mut line_has_segment = false;
+    let mut last_col = None;
+
+    let mut chars = code.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            MARK => {
+                let offset: String = chars.by_ref().take_while(|&c| c != END).collect();
+                if last_col == Some(col) {
+                    continue;
+                }
+                let (src_line, src_col) = position(offset.parse().unwrap());
+                if line_has_segment {
+                    mappings.push(',');
+                }
+                for field in [
+                    col - prev_col,
+                    0,
+                    src_line - prev_line,
+                    src_col - prev_src_col,
+                ] {
+                    vlq(&mut mappings, field);
+                }
+                (prev_col, prev_line, prev_src_col) = (col, src_line, src_col);
+                line_has_segment = true;
+                last_col = Some(col);
+            }
+            '\n' => {
+                out.push(c);
+                mappings.push(';');
+                col = 0;
+                prev_col = 0;
+                line_has_segment = false;
+                last_col = None;
+            }
+            c => {
+                out.push(c);
+                col += c.len_utf16() as i64;
+            }
+        }
+    }
+
+    let map = format!(
+        "{{\"version\":3,\"file\":{},\"sources\":[{}],\"sourcesContent\":[{}],\"names\":[],\"mappings\":\"{}\"}}\n",
+        string(file),
+        string(source_name),
+        string(source),
+        mappings
+    );
+    (out, map)
+}
+
+// Base64 VLQ, the number encoding of source map segments
+fn vlq(out: &mut String, value: i64) {
+    const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut rest = if value < 0 {
+        (-value << 1) | 1
+    } else {
+        value << 1
+    };
+    loop {
+        let mut digit = rest & 0b11111;
+        rest >>= 5;
+        if rest > 0 {
+            digit |= 0b100000;
+        }
+        out.push(BASE64[digit as usize] as char);
+        if rest == 0 {
+            break;
+        }
+    }
+}
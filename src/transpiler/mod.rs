@@ -0,0 +1,173 @@
+//! `pulse build`: compiles a resolved program to source in another language.
+
+use super::interpreter::builtins;
+use super::types::{BSE, Expr, Place, PropKey, Spanned, Value};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub mod js;
+pub mod rust;
+
+pub enum Target {
+    Rust,
+    Js,
+}
+
+impl Target {
+    pub fn parse(name: &str) -> Option<Target> {
+        match name {
+            "rust" => Some(Target::Rust),
+            "js" => Some(Target::Js),
+            _ => None,
+        }
+    }
+}
+
+/// Errors for what compiled programs cannot do: a build is a single file,
+/// and the runtimes have no standard library, string or list methods,
+/// exceptions or numbers past 64 bits.
+pub fn unsupported(ast: &[BSE]) -> Vec<Spanned<String>> {
+    fn walk(expr: &BSE, errs: &mut Vec<Spanned<String>>) {
+        match &expr.0 {
+            Expr::Import(..) => {
+                errs.push(("Imports are not supported by `pulse build`.".into(), expr.1))
+            }
+            Expr::Throw(_) | Expr::Try(..) => errs.push((
+                "Exceptions are not supported by `pulse build`.".into(),
+                expr.1,
+            )),
+            Expr::ReservedVar(name) => errs.push((
+                format!("The '{}' module is not supported by `pulse build`.", name),
+                expr.1,
+            )),
+            Expr::Val(Value::BigInt(_) | Value::Decimal(_)) => errs.push((
+                "BigInt and Decimal numbers are not supported by `pulse build`.".into(),
+                expr.1,
+            )),
+            Expr::ReservedCall(name, args) if name.as_str() == "range" => {
+                errs.push(("`range` is not supported by `pulse build`.".into(), expr.1));
+                args.iter().for_each(|arg| walk(arg, errs))
+            }
+            // Which value a method is called on is only known at runtime,
+            // so any call of a field named like one is refused
+            Expr::Call(callee, _) => {
+                if let Expr::Field(_, name) = &callee.0
+                    && builtins::is_method(name.as_str())
+                {
+                    errs.push((
+                        format!("The method '{}' is not supported by `pulse build`.", name),
+                        callee.1,
+                    ));
+                }
+                children(expr).for_each(|child| walk(child, errs))
+            }
+            _ => children(expr).for_each(|child| walk(child, errs)),
+        }
+    }
+    let mut errs = Vec::new();
+    ast.iter().for_each(|stmt| walk(stmt, &mut errs));
+    errs
+}
+
+/// Writes the program compiled from `filename` to the directory `out`. For
+/// Rust that is a crate with a copy of `pulse-runtime` next to it, so it
+/// builds offline with nothing but the toolchain; for JS, an ES module with
+/// its source map and the runtime shim it imports.
+pub fn build(
+    target: Target,
+    ast: &[BSE],
+    filename: &str,
+    source: &str,
+    out: &Path,
+) -> io::Result<()> {
+    let path = Path::new(filename);
+    let name = path
+        .file_stem()
+        .map_or("program".into(), |s| s.to_string_lossy());
+    match target {
+        Target::Rust => {
+            fs::create_dir_all(out.join("src"))?;
+            fs::create_dir_all(out.join("runtime/src"))?;
+            fs::write(out.join("Cargo.toml"), rust::manifest(&crate_name(&name)))?;
+            fs::write(out.join("src/main.rs"), rust::generate(ast))?;
+            fs::write(out.join("runtime/Cargo.toml"), rust::RUNTIME_MANIFEST)?;
+            fs::write(out.join("runtime/src/lib.rs"), rust::RUNTIME_LIB)
+        }
+        Target::Js => {
+            let file = format!("{}.mjs", name);
+            let source_name = path
+                .file_name()
+                .map_or(filename.into(), |s| s.to_string_lossy());
+            let (code, map) = js::generate(ast, &file, source, &source_name);
+            fs::create_dir_all(out)?;
+            fs::write(out.join(&file), code)?;
+            fs::write(out.join(format!("{}.map", file)), map)?;
+            fs::write(out.join("pulse-runtime.mjs"), js::RUNTIME)
+        }
+    }
+}
+
+// Cargo package names are alphanumerics, `-` and `_`, and may not start
+// with a digit
+fn crate_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => name,
+        _ => format!("pulse-{}", name),
+    }
+}
+
+// The subexpressions of `expr`, for passes that look at every node
+pub(crate) fn children(expr: &BSE) -> Box<dyn Iterator<Item = &BSE> + '_> {
+    match &expr.0 {
+        Expr::Let(.., value)
+        | Expr::Const(.., value)
+        | Expr::Assign(.., value)
+        | Expr::Fn(.., value)
+        | Expr::UnOp(_, value)
+        | Expr::Return(value)
+        | Expr::Field(value, _)
+        | Expr::Export(value)
+        | Expr::Throw(value) => Box::new(std::iter::once(value)),
+        Expr::Try(body, catch, finally) => Box::new(
+            std::iter::once(body)
+                .chain(catch.iter().map(|(_, _, handler)| handler))
+                .chain(finally),
+        ),
+        Expr::BnOp(l, _, r)
+        | Expr::While(l, r)
+        | Expr::Index(l, r)
+        | Expr::Set(Place::Field(l, _), _, r) => Box::new([l, r].into_iter()),
+        Expr::If(c, t, e) | Expr::Set(Place::Index(c, t), _, e) => Box::new([c, t, e].into_iter()),
+        Expr::Call(callee, args) | Expr::TailCall(callee, args) => {
+            Box::new(std::iter::once(callee).chain(args))
+        }
+        Expr::Block(items) | Expr::List(items) | Expr::ReservedCall(_, items) => {
+            Box::new(items.iter())
+        }
+        Expr::Obj(entries) => Box::new(entries.iter().flat_map(|(key, value)| {
+            let key = match key {
+                PropKey::Computed(key) => Some(key),
+                PropKey::Fixed(_) => None,
+            };
+            key.into_iter().chain(std::iter::once(value))
+        })),
+        Expr::Val(_)
+        | Expr::Var(..)
+        | Expr::ReservedVar(_)
+        | Expr::Error
+        | Expr::Comment(_)
+        | Expr::Nl
+        | Expr::Import(..) => Box::new(std::iter::empty()),
+    }
+}
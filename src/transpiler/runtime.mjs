@@ -0,0 +1,537 @@
+// Runtime for Pulse programs compiled with `pulse build --target js`.
+//
+// Pulse values map onto plain JS ones: `undefined`, BigInts for `Int`s,
+// numbers, strings, booleans, functions, arrays for lists and Maps for
+// objects. Only the
+// operators differ from JS, so generated code calls into here for every one
+// of them; they follow the interpreter's operators.rs case by case.
+
+export class PulseError extends Error {}
+
+// Lists and objects passed to `freeze`
+const frozen = new WeakSet();
+
+function typeName(v) {
+  switch (typeof v) {
+    case "undefined":
+      return "Undefined";
+    case "bigint":
+      return "Int";
+    case "number":
+      return "Num";
+    case "string":
+      return "Str";
+    case "boolean":
+      return "Bool";
+    case "function":
+      return "Function";
+  }
+  return Array.isArray(v) ? "Vec" : "Obj";
+}
+
+// Rust's float formatting: no exponents, `inf`, and a signed zero
+function num(n) {
+  if (n === Infinity) return "inf";
+  if (n === -Infinity) return "-inf";
+  if (Object.is(n, -0)) return "-0";
+  const s = String(n);
+  if (!s.includes("e")) return s;
+  let [mantissa, exp] = s.split("e");
+  const sign = mantissa.startsWith("-") ? "-" : "";
+  const [int, frac = ""] = mantissa.replace("-", "").split(".");
+  const digits = int + frac;
+  const point = int.length + Number(exp);
+  if (point <= 0) return `${sign}0.${"0".repeat(-point)}${digits}`;
+  if (point >= digits.length) return sign + digits + "0".repeat(point - digits.length);
+  return `${sign}${digits.slice(0, point)}.${digits.slice(point)}`;
+}
+
+// Lists and objects print like their literals, strings inside them quoted;
+// `seen` holds the ones `v` is inside of, which print as `[...]` or `{...}`
+// where they come round again.
+export function show(v, seen = []) {
+  switch (typeName(v)) {
+    case "Undefined":
+      return "undefined";
+    case "Int":
+      return String(v);
+    case "Num":
+      return num(v);
+    case "Str":
+      return seen.length > 0 ? `"${v}"` : v;
+    case "Bool":
+      return String(v);
+    case "Function":
+      return "(Function () => {})";
+    case "Vec": {
+      if (seen.includes(v)) return "[...]";
+      const items = v.map((item) => show(item, [...seen, v]));
+      return `[${items.join(", ")}]`;
+    }
+  }
+  if (seen.includes(v)) return "{...}";
+  const entries = [...v].map(([k, item]) => {
+    const key = typeof k === "string" ? `"${k}"` : typeof k === "number" ? num(k) : String(k);
+    return `${key}: ${show(item, [...seen, v])}`;
+  });
+  return `{${entries.join(", ")}}`;
+}
+
+export function truthy(v) {
+  switch (typeName(v)) {
+    case "Undefined":
+      return false;
+    case "Int":
+      return v !== 0n;
+    case "Num":
+      return v !== 0;
+    case "Str":
+      return v.length > 0;
+    case "Bool":
+      return v;
+    case "Function":
+      return true;
+    case "Vec":
+      return v.length > 0;
+  }
+  return v.size > 0;
+}
+
+function unsupported(what, sym, l, r) {
+  throw new PulseError(`${what} not supported: ${typeName(l)} ${sym} ${typeName(r)}`);
+}
+
+// `undefined` next to any of these acts as an empty value
+const blank = ["Int", "Num", "Str", "Vec", "Obj"];
+// Strings concatenate with any of these
+const scalar = ["Int", "Num", "Str", "Bool"];
+const numbers = ["Int", "Num"];
+
+// Mixing an Int with a Num makes both floats
+function promote(l, r) {
+  if (typeof l === "bigint" && typeof r === "number") return [Number(l), r];
+  if (typeof l === "number" && typeof r === "bigint") return [l, Number(r)];
+  return [l, r];
+}
+
+// Int arithmetic is 64-bit, and going past that is an error
+function checked(n, a, sym, b) {
+  if (BigInt.asIntN(64, n) !== n) throw new PulseError(`Integer overflow: ${a} ${sym} ${b}`);
+  return n;
+}
+
+export function add(l, r) {
+  [l, r] = promote(l, r);
+  const a = typeName(l);
+  const b = typeName(r);
+  if (a === "Int" && b === "Int") return checked(l + r, l, "+", r);
+  if (a === "Num" && b === "Num") return l + r;
+  if ((a === "Str" || b === "Str") && scalar.includes(a) && scalar.includes(b)) {
+    return show(l) + show(r);
+  }
+  if (a === "Int" && b === "Bool") return checked(l + BigInt(r), l, "+", Number(r));
+  if (a === "Bool" && b === "Int") return checked(r + BigInt(l), r, "+", Number(l));
+  if (a === "Num" && b === "Bool") return r ? l + 1 : l;
+  if (a === "Bool" && b === "Num") return l ? r + 1 : r;
+  if (a === "Undefined" && b === "Undefined") return undefined;
+  if (a === "Undefined" && blank.includes(b)) return r;
+  if (b === "Undefined" && blank.includes(a)) return l;
+  if (a === "Vec" && b === "Vec") return [...l, ...r];
+  // RHS entries override LHS on key conflict
+  if (a === "Obj" && b === "Obj") return new Map([...l, ...r]);
+  unsupported("Addition", "+", l, r);
+}
+
+export function sub(l, r) {
+  [l, r] = promote(l, r);
+  const a = typeName(l);
+  const b = typeName(r);
+  if (a === "Int" && b === "Int") return checked(l - r, l, "-", r);
+  if (a === "Num" && b === "Num") return l - r;
+  if (a === "Int" && b === "Bool") return checked(l - BigInt(r), l, "-", Number(r));
+  if (a === "Bool" && b === "Int") return checked(r - BigInt(l), r, "-", Number(l));
+  if (a === "Num" && b === "Bool") return r ? l - 1 : l;
+  if (a === "Bool" && b === "Num") return l ? r - 1 : r;
+  if (a === "Undefined" && b === "Undefined") return undefined;
+  if (a === "Undefined" && blank.includes(b)) return r;
+  if (b === "Undefined" && blank.includes(a)) return l;
+  // Drops every key of the RHS from the LHS
+  if (a === "Obj" && b === "Obj") {
+    const obj = new Map(l);
+    r.forEach((_, key) => obj.delete(key));
+    return obj;
+  }
+  unsupported("Subtraction", "-", l, r);
+}
+
+export function mul(l, r) {
+  [l, r] = promote(l, r);
+  const a = typeName(l);
+  const b = typeName(r);
+  if (a === "Int" && b === "Int") return checked(l * r, l, "*", r);
+  if (a === "Num" && b === "Num") return l * r;
+  if ((a === "Str" && numbers.includes(b)) || (numbers.includes(a) && b === "Str")) {
+    const [s, n] = a === "Str" ? [l, r] : [r, l];
+    return s.repeat(n >= 0 ? Math.trunc(Number(n)) : 0);
+  }
+  if (a === "Undefined") return r;
+  if (b === "Undefined") return l;
+  unsupported("Multiplication", "×", l, r);
+}
+
+// Dividing Ints gives a Num; `~/` is the division that stays whole
+export function div(l, r) {
+  [l, r] = promote(l, r);
+  const a = typeName(l);
+  const b = typeName(r);
+  if (a === "Int" && b === "Int") {
+    if (r === 0n) throw new PulseError("Division by zero");
+    return Number(l) / Number(r);
+  }
+  if (a === "Num" && b === "Num") {
+    if (r === 0) throw new PulseError("Division by zero");
+    return l / r;
+  }
+  if (b === "Undefined") return l;
+  if (a === "Undefined") return undefined;
+  unsupported("Division", "÷", l, r);
+}
+
+export function rem(l, r) {
+  [l, r] = promote(l, r);
+  const a = typeName(l);
+  const b = typeName(r);
+  if (a === "Int" && b === "Int") {
+    if (r === 0n) throw new PulseError("Modulo by zero");
+    return l % r;
+  }
+  if (a === "Num" && b === "Num") {
+    if (r === 0) throw new PulseError("Modulo by zero");
+    return l % r;
+  }
+  if (b === "Undefined") return l;
+  if (a === "Undefined") return undefined;
+  unsupported("Modulo", "%", l, r);
+}
+
+// An Int to a negative power is a fraction, so a Num
+export function pow(l, r) {
+  [l, r] = promote(l, r);
+  if (typeof l === "bigint" && typeof r === "bigint") {
+    if (r < 0n) return Number(l) ** Number(r);
+    // Anything but 0, 1 and -1 overflows long before this
+    if (r > 64n && (l < -1n || l > 1n)) throw new PulseError(`Integer overflow: ${l} ** ${r}`);
+    return checked(l ** r, l, "**", r);
+  }
+  if (typeof l === "number" && typeof r === "number") return l ** r;
+  unsupported("Exponentiation", "**", l, r);
+}
+
+// Division rounded down
+export function intDiv(l, r) {
+  [l, r] = promote(l, r);
+  const a = typeName(l);
+  const b = typeName(r);
+  if (a === "Int" && b === "Int") {
+    if (r === 0n) throw new PulseError("Division by zero");
+    // BigInt division truncates
+    const q = l / r;
+    return checked(l % r !== 0n && l < 0n !== r < 0n ? q - 1n : q, l, "~/", r);
+  }
+  if (a === "Num" && b === "Num") {
+    if (r === 0) throw new PulseError("Division by zero");
+    return Math.floor(l / r);
+  }
+  if (b === "Undefined") return l;
+  if (a === "Undefined") return undefined;
+  unsupported("Integer division", "~/", l, r);
+}
+
+// JS's own bitwise operators work on 32 bits on numbers, so these go
+// through BigInt to get the interpreter's 64
+function integer(v) {
+  if (typeof v === "bigint") return v;
+  if (!Number.isInteger(v) || v < -(2 ** 63) || v >= 2 ** 63) {
+    throw new PulseError(`Bitwise operators need whole numbers, found ${num(v)}.`);
+  }
+  return BigInt(v);
+}
+
+function bitwise(what, sym, l, r, op) {
+  const numeric = (v) => typeof v === "number" || typeof v === "bigint";
+  if (!numeric(l) || !numeric(r)) unsupported(what, sym, l, r);
+  const a = integer(l);
+  const b = integer(r);
+  if ((sym === "<<" || sym === ">>") && (b < 0n || b > 63n)) {
+    throw new PulseError(`Shift amount must be between 0 and 63, found ${b}.`);
+  }
+  return BigInt.asIntN(64, op(a, b));
+}
+
+export const bitAnd = (l, r) => bitwise("Bitwise and", "&", l, r, (a, b) => a & b);
+export const bitOr = (l, r) => bitwise("Bitwise or", "|", l, r, (a, b) => a | b);
+export const bitXor = (l, r) => bitwise("Bitwise xor", "^", l, r, (a, b) => a ^ b);
+export const shl = (l, r) => bitwise("Left shift", "<<", l, r, (a, b) => a << b);
+export const shr = (l, r) => bitwise("Right shift", ">>", l, r, (a, b) => a >> b);
+
+export function bitNot(v) {
+  if (typeof v !== "number" && typeof v !== "bigint") {
+    throw new PulseError(`Bitwise not not supported: ${typeName(v)}`);
+  }
+  return ~integer(v);
+}
+
+export function neg(v) {
+  if (typeof v === "bigint") {
+    if (v === -(2n ** 63n)) throw new PulseError(`Integer overflow: -(${v})`);
+    return -v;
+  }
+  if (typeof v === "number") return -v;
+  if (v === undefined) return undefined;
+  throw new PulseError(`Negation not supported: ${typeName(v)}`);
+}
+
+// Logical operators evaluate both sides, like the interpreter's
+export const and = (l, r) => truthy(l) && truthy(r);
+export const or = (l, r) => truthy(l) || truthy(r);
+export const not = (v) => !truthy(v);
+
+// Ints and Nums are equal when they are the same number. Lists and objects
+// compare by contents, functions by identity.
+export function eq(l, r) {
+  const a = typeName(l);
+  if (numbers.includes(a) && numbers.includes(typeName(r))) return l == r;
+  if (a !== typeName(r)) return false;
+  if (a === "Vec") return l.length === r.length && l.every((v, i) => eq(v, r[i]));
+  if (a === "Obj") {
+    if (l.size !== r.size) return false;
+    for (const [key, v] of l) {
+      if (!r.has(key) || !eq(v, r.get(key))) return false;
+    }
+    return true;
+  }
+  return l === r;
+}
+
+export const ne = (l, r) => !eq(l, r);
+
+// Only numbers with numbers, strings with strings and `undefined` with
+// itself are ordered. JS compares BigInts with numbers exactly.
+function ordered(l, r) {
+  const [a, b] = [typeName(l), typeName(r)];
+  if (numbers.includes(a) && numbers.includes(b)) return true;
+  return a === b && ["Str", "Undefined"].includes(a);
+}
+
+export const lt = (l, r) => ordered(l, r) && l < r;
+export const gt = (l, r) => ordered(l, r) && l > r;
+export const le = (l, r) => ordered(l, r) && (l === undefined || l <= r);
+export const ge = (l, r) => ordered(l, r) && (l === undefined || l >= r);
+
+// The key a computed `[expr]` in an object literal stands for. A whole
+// number is the same key as an Int or a Num, so those within 64 bits are
+// numbers where that is exact and BigInts otherwise.
+export function key(v) {
+  const t = typeName(v);
+  if (t === "Int") return Number.isSafeInteger(Number(v)) ? Number(v) : v;
+  if (t === "Num" && Number.isInteger(v) && v >= -(2 ** 63) && v < 2 ** 63) {
+    return Number.isSafeInteger(v) ? v : BigInt(v);
+  }
+  if (t !== "Str" && t !== "Num") {
+    throw new PulseError(`Object keys must be Str or Num, found ${t}.`);
+  }
+  return v;
+}
+
+// Objects are Maps, and a missing field is undefined
+export function field(v, name) {
+  if (typeName(v) !== "Obj") {
+    throw new PulseError(`Cannot read field '${name}' of ${typeName(v)}.`);
+  }
+  return v.get(name);
+}
+
+// Where `i` falls in a sequence of `length`, counting back from the end if
+// negative, or -1 if it falls outside
+function position(i, length) {
+  if (typeof i === "bigint") i = Number(i);
+  if (typeof i !== "number" || !Number.isInteger(i)) {
+    const found = typeof i === "number" ? num(i) : typeName(i);
+    throw new PulseError(`List indexes must be integers, found ${found}.`);
+  }
+  if (i < 0) i += length;
+  return i >= 0 && i < length ? i : -1;
+}
+
+// `v[i]`: an element of a list, a character of a string or a field of an
+// object; anything missing is undefined
+export function index(v, i) {
+  switch (typeName(v)) {
+    case "Vec": {
+      const at = position(i, v.length);
+      return at < 0 ? undefined : v[at];
+    }
+    case "Str": {
+      const chars = [...v];
+      const at = position(i, chars.length);
+      return at < 0 ? undefined : chars[at];
+    }
+    case "Obj":
+      return v.get(key(i));
+  }
+  throw new PulseError(`Cannot index ${typeName(v)}.`);
+}
+
+function store(obj, k, value) {
+  if (frozen.has(obj)) throw new PulseError("Cannot change a frozen object.");
+  obj.set(k, value);
+}
+
+export function setIndex(v, i, value) {
+  switch (typeName(v)) {
+    case "Vec": {
+      if (frozen.has(v)) throw new PulseError("Cannot change a frozen list.");
+      const at = position(i, v.length);
+      if (at < 0) {
+        throw new PulseError(`Index ${show(i)} is out of range for a list of ${v.length}.`);
+      }
+      v[at] = value;
+      return undefined;
+    }
+    case "Obj":
+      store(v, key(i), value);
+      return undefined;
+  }
+  throw new PulseError(`Cannot assign to an element of ${typeName(v)}.`);
+}
+
+export function setField(v, name, value) {
+  if (typeName(v) !== "Obj") {
+    throw new PulseError(`Cannot set field '${name}' of ${typeName(v)}.`);
+  }
+  store(v, name, value);
+  return undefined;
+}
+
+// A call in tail position, left for `call` to make once the function it
+// ends has returned, with that function's return type if it has one
+let pending = null;
+
+// Calls `f`, then any tail calls it ends with, so tail recursion runs in
+// constant stack
+export function call(f, ...args) {
+  let value = invoke(f, args);
+  const returns = [];
+  while (pending !== null) {
+    const { ret } = pending;
+    ({ f, args } = pending);
+    pending = null;
+    if (ret && !returns.some((t) => typeString(t) === typeString(ret))) returns.push(ret);
+    value = invoke(f, args);
+  }
+  for (const t of returns.reverse()) check(value, t, "return");
+  return value;
+}
+
+function invoke(f, args) {
+  if (typeof f !== "function") throw new PulseError("Not a function.");
+  return f(...args);
+}
+
+// A call in tail position; the undefined returned in its place never
+// reaches the `call` that makes it
+export function tail(f, ...args) {
+  pending = { f, args, ret: null };
+  return undefined;
+}
+
+export function print(...args) {
+  console.log(args.map((v) => show(v)).join(" "));
+}
+
+export function builtin(name, ...args) {
+  switch (name) {
+    case "freeze":
+      freeze(args[0]);
+      return args[0];
+  }
+  throw new PulseError(`Unknown builtin '${name}'.`);
+}
+
+function freeze(v) {
+  if ((Array.isArray(v) || v instanceof Map) && !frozen.has(v)) {
+    frozen.add(v);
+    v.forEach((item) => freeze(item));
+  }
+}
+
+// Types are "num", "str" and so on, or { list }, { fn, ret } and { union }
+function typeString(t) {
+  if (typeof t === "string") return t;
+  if (t.list) return `list<${typeString(t.list)}>`;
+  if (t.fn) return `fn(${t.fn.map(typeString).join(", ")}) -> ${typeString(t.ret)}`;
+  return t.union.map(typeString).join(" | ");
+}
+
+function union(types) {
+  const members = [];
+  for (const t of types.flatMap((t) => t.union ?? [t])) {
+    if (t === "any") return "any";
+    if (t !== "never" && !members.some((m) => typeString(m) === typeString(t))) {
+      members.push(t);
+    }
+  }
+  if (members.length === 0) return "any";
+  return members.length === 1 ? members[0] : { union: members };
+}
+
+function typeOf(v) {
+  const name = typeName(v);
+  if (name === "Vec") return { list: union(v.map(typeOf)) };
+  if (name === "Function") return v.pulseType ?? { fn: Array(v.length).fill("any"), ret: "any" };
+  if (name === "Int") return "num";
+  return name === "Undefined" ? "undefined" : name.toLowerCase();
+}
+
+function admits(t, v) {
+  if (t === "any") return true;
+  if (t.union) return t.union.some((u) => admits(u, v));
+  if (t.list) return Array.isArray(v) && v.every((item) => admits(t.list, item));
+  if (t.fn) return typeof v === "function";
+  return typeOf(v) === t;
+}
+
+// Records the declared type of an annotated function.
+export function typed(f, t) {
+  f.pulseType = t;
+  return f;
+}
+
+// Checks an annotated binding, parameter or return value
+export function check(v, t, boundary, name) {
+  // A function ending in a tail call leaves its result to be checked by
+  // `call`
+  if (boundary === "return" && pending !== null) {
+    pending.ret = t;
+    return v;
+  }
+  if (admits(t, v)) return v;
+  const [expected, found] = [typeString(t), typeString(typeOf(v))];
+  switch (boundary) {
+    case "binding":
+      throw new PulseError(`Type mismatch: '${name}' is declared as ${expected}, found ${found}.`);
+    case "param":
+      throw new PulseError(`Type mismatch: parameter '${name}' expects ${expected}, found ${found}.`);
+  }
+  throw new PulseError(`Type mismatch: expected return type ${expected}, found ${found}.`);
+}
+
+// Runs one top-level statement. As in the interpreter, an error is reported
+// and the program carries on with the next statement.
+export function run(stmt) {
+  try {
+    stmt();
+  } catch (e) {
+    console.error(`Error: ${e.message}`);
+  }
+}
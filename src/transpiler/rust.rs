@@ -0,0 +1,482 @@
+//! Compiles a resolved program to a Rust crate depending on `pulse-runtime`.
+//!
+//! Every variable becomes an `rt::Var`, a shared cell, so closures can
+//! capture them by cloning the `Rc`. Globals are fields of one `Globals`
+//! struct, which lets functions call functions declared after them; locals
+//! are Rust `let`s, whose block scoping matches the resolver's. Each
+//! function captures exactly the outer locals the resolver sent its names to.
+
+use super::super::types::{
+    AssignOp, BSE, Expr, Key, Op, Place, PropKey, Signature, Slot, Symbol, Type, Value,
+};
+use super::children;
+use std::fmt::Write;
+
+pub const RUNTIME_MANIFEST: &str = include_str!("../../runtime/Cargo.toml");
+pub const RUNTIME_LIB: &str = include_str!("../../runtime/src/lib.rs");
+
+pub fn manifest(name: &str) -> String {
+    format!(
+        "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n\
+         [dependencies]\npulse-runtime = {{ path = \"runtime\" }}\n\n\
+         # Keeps the output out of any workspace it is written into\n[workspace]\n",
+        name
+    )
+}
+
+pub fn generate(ast: &[BSE]) -> String {
+    let mut globals = Vec::new();
+    ast.iter()
+        .for_each(|stmt| declared_globals(stmt, &mut globals));
+
+    let mut out = String::new();
+    out.push_str("// Generated by `pulse build --target rust`.\n#![allow(warnings)]\n\n");
+    out.push_str("use pulse_runtime as rt;\nuse std::rc::Rc;\n\n");
+    out.push_str("struct Globals {\n");
+    for name in &globals {
+        writeln!(out, "    v_{}: rt::Var,", name).unwrap();
+    }
+    out.push_str("}\n\nfn main() {\n    let g = &Rc::new(Globals {\n");
+    for name in &globals {
+        writeln!(out, "        v_{0}: rt::Var::new(\"{0}\"),", name).unwrap();
+    }
+    out.push_str("    });\n");
+
+    // Like the interpreter, an error only stops the statement it came from
+    let r#gen = Gen { ret: None };
+    for stmt in ast {
+        writeln!(out, "    rt::report((|| Ok({}))());", r#gen.expr(stmt, 1)).unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn declared_globals(expr: &BSE, out: &mut Vec<Symbol>) {
+    match &expr.0 {
+        Expr::Let(name, Slot::Global, ..)
+        | Expr::Const(name, Slot::Global, ..)
+        | Expr::Fn(name, Slot::Global, ..)
+            if !out.contains(name) =>
+        {
+            out.push(*name)
+        }
+        _ => {}
+    }
+    children(expr).for_each(|child| declared_globals(child, out));
+}
+
+// Names of the locals `expr` uses from outside the function it is in.
+// `inner` counts the resolver scopes between `expr` and that function's
+// boundary, its own scope included.
+fn captures(expr: &BSE, inner: usize, out: &mut Vec<Symbol>) {
+    match &expr.0 {
+        Expr::Var(name, Slot::Local(depth, _)) | Expr::Assign(name, Slot::Local(depth, _), _)
+            if *depth >= inner && !out.contains(name) =>
+        {
+            out.push(*name)
+        }
+        _ => {}
+    }
+    match &expr.0 {
+        Expr::Fn(.., body) => captures(body, inner + 1, out),
+        Expr::If(cond, then, els) => {
+            captures(cond, inner, out);
+            captures(then, inner + 1, out);
+            captures(els, inner + 1, out);
+        }
+        Expr::While(cond, body) => {
+            captures(cond, inner, out);
+            captures(body, inner + 1, out);
+        }
+        _ => children(expr).for_each(|child| captures(child, inner, out)),
+    }
+}
+
+struct Gen {
+    // Return type of the enclosing function; `None` at the top level,
+    // where `return` only yields its value.
+    ret: Option<Option<Type>>,
+}
+
+fn pad(ind: usize) -> String {
+    "    ".repeat(ind)
+}
+
+impl Gen {
+    fn var(&self, name: Symbol, slot: Slot) -> String {
+        match slot {
+            Slot::Global => format!("g.v_{}", name),
+            Slot::Local(..) => format!("v_{}", name),
+        }
+    }
+
+    fn expr(&self, expr: &BSE, ind: usize) -> String {
+        match &expr.0 {
+            Expr::Val(value) => literal(value),
+            Expr::Var(name, slot) => format!("{}.get()?", self.var(*name, *slot)),
+
+            Expr::List(items) => {
+                let items: Vec<_> = items.iter().map(|i| self.expr(i, ind)).collect();
+                format!("rt::list(vec![{}])", items.join(", "))
+            }
+            Expr::Obj(entries) => {
+                let entries: Vec<_> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = match key {
+                            PropKey::Fixed(key) => key_literal(key),
+                            PropKey::Computed(key) => format!("rt::key({})?", self.expr(key, ind)),
+                        };
+                        format!("({}, {})", key, self.expr(value, ind))
+                    })
+                    .collect();
+                format!("rt::obj(vec![{}])", entries.join(", "))
+            }
+
+            Expr::UnOp(Op::Neg, value) => format!("rt::neg({})?", self.expr(value, ind)),
+            Expr::UnOp(Op::BitNot, value) => format!("rt::bit_not({})?", self.expr(value, ind)),
+            Expr::UnOp(_, value) => format!("rt::not({})", self.expr(value, ind)),
+            Expr::BnOp(l, op, r) => binary(op, &self.expr(l, ind), &self.expr(r, ind)),
+
+            Expr::Let(name, Slot::Global, ty, value)
+            | Expr::Const(name, Slot::Global, ty, value) => {
+                format!(
+                    "g.v_{}.define({}, {})?",
+                    name,
+                    self.expr(value, ind),
+                    option(ty)
+                )
+            }
+            Expr::Fn(name, Slot::Global, signature, body) => {
+                format!(
+                    "g.v_{}.define({}, None)?",
+                    name,
+                    self.function(signature, body, ind)
+                )
+            }
+            // A local declared outside a block, e.g. as an `if` branch, is
+            // dropped again straight away
+            Expr::Let(..) | Expr::Const(..) | Expr::Fn(..) => self.block(&[expr], ind),
+            Expr::Assign(name, slot, value) => {
+                format!("{}.set({})?", self.var(*name, *slot), self.expr(value, ind))
+            }
+
+            Expr::Block(stmts) => self.block(&stmts.iter().collect::<Vec<_>>(), ind),
+
+            Expr::Return(value) => match &self.ret {
+                Some(ret) => format!("return rt::ret({}, {})", self.expr(value, ind), option(ret)),
+                None => self.expr(value, ind),
+            },
+            Expr::Call(callee, args) => {
+                let args: Vec<_> = args.iter().map(|a| self.expr(a, ind)).collect();
+                format!(
+                    "rt::call(&{}, &[{}])?",
+                    self.expr(callee, ind),
+                    args.join(", ")
+                )
+            }
+            Expr::TailCall(callee, args) => {
+                let args: Vec<_> = args.iter().map(|a| self.expr(a, ind)).collect();
+                format!(
+                    "rt::tail({}, vec![{}])?",
+                    self.expr(callee, ind),
+                    args.join(", ")
+                )
+            }
+
+            Expr::ReservedCall(name, args) if name.as_str() == "print" => {
+                let mut out = String::from("{\n");
+                for (i, arg) in args.iter().enumerate() {
+                    writeln!(
+                        out,
+                        "{}rt::write(&{}, {});",
+                        pad(ind + 1),
+                        self.expr(arg, ind + 1),
+                        i + 1 == args.len()
+                    )
+                    .unwrap();
+                }
+                write!(
+                    out,
+                    "{0}rt::newline();\n{0}rt::Value::Undefined\n{1}}}",
+                    pad(ind + 1),
+                    pad(ind)
+                )
+                .unwrap();
+                out
+            }
+            Expr::ReservedCall(name, args) => {
+                let args: Vec<_> = args.iter().map(|a| self.expr(a, ind)).collect();
+                format!("rt::builtin(\"{}\", vec![{}])?", name, args.join(", "))
+            }
+
+            Expr::If(cond, then, els) => format!(
+                "if ({}).truthy() {} else {}",
+                self.expr(cond, ind),
+                self.body(then, ind),
+                self.body(els, ind)
+            ),
+            Expr::While(cond, body) => format!(
+                "{{\n{1}let mut last = rt::Value::Undefined;\n\
+                 {1}while ({2}).truthy() {{\n{3}last = {4};\n{1}}}\n{1}last\n{0}}}",
+                pad(ind),
+                pad(ind + 1),
+                self.expr(cond, ind + 1),
+                pad(ind + 2),
+                self.body(body, ind + 2)
+            ),
+
+            Expr::Field(object, name) => {
+                format!(
+                    "rt::field({}, {:?})?",
+                    self.expr(object, ind),
+                    name.as_str()
+                )
+            }
+            Expr::Index(object, index) => format!(
+                "rt::index({}, {})?",
+                self.expr(object, ind),
+                self.expr(index, ind)
+            ),
+            // The object and the index go in locals, so they are evaluated
+            // once
+            Expr::Set(place, op, value) => {
+                let inner = pad(ind + 1);
+                let mut out = String::from("{\n");
+                let (get, set) = match place {
+                    Place::Field(object, name) => {
+                        let object = self.expr(object, ind + 1);
+                        writeln!(out, "{}let object = {};", inner, object).unwrap();
+                        (
+                            format!("rt::field(object.clone(), {:?})?", name.as_str()),
+                            format!("rt::set_field(object, {:?}, ", name.as_str()),
+                        )
+                    }
+                    Place::Index(object, index) => {
+                        let object = self.expr(object, ind + 1);
+                        let index = self.expr(index, ind + 1);
+                        writeln!(out, "{}let object = {};", inner, object).unwrap();
+                        writeln!(out, "{}let index = {};", inner, index).unwrap();
+                        (
+                            "rt::index(object.clone(), index.clone())?".to_string(),
+                            "rt::set_index(object, index, ".to_string(),
+                        )
+                    }
+                };
+                match op {
+                    AssignOp::Replace => {
+                        let value = self.expr(value, ind + 1);
+                        writeln!(out, "{}{}{})?;", inner, set, value).unwrap();
+                    }
+                    AssignOp::Apply(op) => {
+                        writeln!(out, "{}let current = {};", inner, get).unwrap();
+                        let value = binary(op, "current", &self.expr(value, ind + 1));
+                        writeln!(out, "{}{}{})?;", inner, set, value).unwrap();
+                    }
+                    AssignOp::IfUndefined => {
+                        let value = self.expr(value, ind + 2);
+                        writeln!(
+                            out,
+                            "{0}if let rt::Value::Undefined = {1} {{\n{2}{3}{4})?;\n{0}}}",
+                            inner,
+                            get,
+                            pad(ind + 2),
+                            set,
+                            value
+                        )
+                        .unwrap();
+                    }
+                }
+                write!(out, "{}rt::Value::Undefined\n{}}}", inner, pad(ind)).unwrap();
+                out
+            }
+
+            // Exports only matter to importers, and `build` turns down
+            // programs with imports
+            Expr::Export(decl) => self.expr(decl, ind),
+
+            // Turned down by `build` as well
+            Expr::Error
+            | Expr::Comment(_)
+            | Expr::Nl
+            | Expr::ReservedVar(_)
+            | Expr::Import(..)
+            | Expr::Throw(_)
+            | Expr::Try(..) => "rt::Value::Undefined".into(),
+        }
+    }
+
+    // A branch or loop body, which always gets its own Rust block
+    fn body(&self, expr: &BSE, ind: usize) -> String {
+        match &expr.0 {
+            Expr::Block(stmts) => self.block(&stmts.iter().collect::<Vec<_>>(), ind),
+            _ => self.block(&[expr], ind),
+        }
+    }
+
+    // Locals are declared as Rust `let`s so later statements see them; the
+    // block's value is that of its last statement.
+    fn block(&self, stmts: &[&BSE], ind: usize) -> String {
+        let mut out = String::from("{\n");
+        let inner = pad(ind + 1);
+        let mut value = "rt::Value::Undefined".to_string();
+        // Local functions exist from the start of their block, as in the
+        // resolver, so they can call each other whatever their order
+        for stmt in stmts {
+            if let Expr::Fn(name, Slot::Local(..), ..) = &stmt.0 {
+                writeln!(out, "{}let v_{1} = rt::Var::new(\"{1}\");", inner, name).unwrap();
+            }
+        }
+        for (i, stmt) in stmts.iter().enumerate() {
+            let last = i + 1 == stmts.len();
+            match &stmt.0 {
+                Expr::Let(name, Slot::Local(..), ty, init)
+                | Expr::Const(name, Slot::Local(..), ty, init) => {
+                    writeln!(
+                        out,
+                        "{}let v_{1} = rt::Var::declare(\"{1}\", {2}, {3})?;",
+                        inner,
+                        name,
+                        self.expr(init, ind + 1),
+                        option(ty)
+                    )
+                    .unwrap();
+                }
+                Expr::Fn(name, Slot::Local(..), signature, body) => {
+                    writeln!(
+                        out,
+                        "{}v_{}.define({}, None)?;",
+                        inner,
+                        name,
+                        self.function(signature, body, ind + 1)
+                    )
+                    .unwrap();
+                }
+                _ if last => value = self.expr(stmt, ind + 1),
+                _ => writeln!(out, "{}{};", inner, self.expr(stmt, ind + 1)).unwrap(),
+            }
+        }
+        write!(out, "{}{}\n{}}}", inner, value, pad(ind)).unwrap();
+        out
+    }
+
+    fn function(&self, signature: &Signature, body: &BSE, ind: usize) -> String {
+        let mut captured = Vec::new();
+        captures(body, 1, &mut captured);
+
+        let inner = pad(ind + 1);
+        let mut out = format!("{{\n{}let g = g.clone();\n", inner);
+        for name in captured {
+            writeln!(out, "{}let v_{1} = v_{1}.clone();", inner, name).unwrap();
+        }
+        writeln!(
+            out,
+            "{}rt::func({}, move |args: &[rt::Value]| {{",
+            inner,
+            r#type(&signature.r#type())
+        )
+        .unwrap();
+        for (i, param) in signature.params.iter().enumerate() {
+            writeln!(
+                out,
+                "{}let v_{1} = rt::Var::param(\"{1}\", args, {2}, {3})?;",
+                pad(ind + 2),
+                param.name,
+                i,
+                option(&param.ty)
+            )
+            .unwrap();
+        }
+        let r#gen = Gen {
+            ret: Some(signature.ret.clone()),
+        };
+        writeln!(
+            out,
+            "{0}let value = {1};\n{0}rt::ret(value, {2})",
+            pad(ind + 2),
+            r#gen.body(body, ind + 2),
+            option(&signature.ret)
+        )
+        .unwrap();
+        write!(out, "{}}})\n{}}}", inner, pad(ind)).unwrap();
+        out
+    }
+}
+
+fn binary(op: &Op, l: &str, r: &str) -> String {
+    let (name, fallible) = match op {
+        Op::Add => ("add", true),
+        Op::Sub => ("sub", true),
+        Op::Mul => ("mul", true),
+        Op::Div => ("div", true),
+        Op::Pow => ("pow", true),
+        Op::IntDiv => ("int_div", true),
+        Op::BitAnd => ("bit_and", true),
+        Op::BitOr => ("bit_or", true),
+        Op::BitXor => ("bit_xor", true),
+        Op::Shl => ("shl", true),
+        Op::Shr => ("shr", true),
+        Op::And => ("and", false),
+        Op::Or => ("or", false),
+        Op::Eq => ("eq", false),
+        Op::Neq => ("ne", false),
+        Op::Gt => ("gt", false),
+        Op::Gte => ("ge", false),
+        Op::Lt => ("lt", false),
+        Op::Lte => ("le", false),
+        _ => ("rem", true),
+    };
+    format!(
+        "rt::{}({}, {}){}",
+        name,
+        l,
+        r,
+        if fallible { "?" } else { "" }
+    )
+}
+
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Int(n) => format!("rt::Value::Int({})", n),
+        Value::Num(n) => format!("rt::Value::Num({:?})", n),
+        Value::Str(s) => format!("rt::Value::Str({:?}.to_string())", s),
+        Value::Bool(b) => format!("rt::Value::Bool({})", b),
+        // The parser only produces the scalar literals above
+        _ => "rt::Value::Undefined".into(),
+    }
+}
+
+fn key_literal(key: &Key) -> String {
+    match key {
+        Key::Str(s) => format!("rt::Key::Str({:?}.to_string())", s),
+        Key::Int(n) => format!("rt::Key::Int({})", n),
+        Key::Num(n) => format!("rt::Key::num({:?})", n.0),
+    }
+}
+
+fn option(ty: &Option<Type>) -> String {
+    match ty {
+        Some(ty) => format!("Some({})", r#type(ty)),
+        None => "None".into(),
+    }
+}
+
+fn r#type(ty: &Type) -> String {
+    let list = |types: &[Type]| types.iter().map(r#type).collect::<Vec<_>>().join(", ");
+    match ty {
+        Type::Any => "rt::Type::Any".into(),
+        Type::Never => "rt::Type::Never".into(),
+        Type::Undefined => "rt::Type::Undefined".into(),
+        Type::Num => "rt::Type::Num".into(),
+        Type::Str => "rt::Type::Str".into(),
+        Type::Bool => "rt::Type::Bool".into(),
+        Type::Obj => "rt::Type::Obj".into(),
+        Type::List(item) => format!("rt::Type::List(Box::new({}))", r#type(item)),
+        Type::Fn(params, ret) => format!(
+            "rt::Type::Fn(vec![{}], Box::new({}))",
+            list(params),
+            r#type(ret)
+        ),
+        Type::Union(types) => format!("rt::Type::Union(vec![{}])", list(types)),
+    }
+}
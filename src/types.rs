@@ -1,28 +1,148 @@
 #![allow(warnings)]
+use bigdecimal::BigDecimal;
 use chumsky::span::SimpleSpan;
 use core::fmt;
+use indexmap::IndexMap;
+use num_bigint::BigInt;
 use ordered_float::OrderedFloat;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::interpreter::Scope;
+pub use crate::symbol::Symbol;
+use crate::vm::Captures;
 
 pub type Span = SimpleSpan;
 pub type Spanned<T> = (T, Span);
-pub type BSE<'a> = Box<Spanned<Expr<'a>>>;
+pub type BSE = Box<Spanned<Expr>>;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Key {
     Str(String),
+    Int(i64),
     Num(OrderedFloat<f64>),
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub enum Value<'a> {
+impl Key {
+    /// The key for a number. Whole numbers are `Int` keys, whichever kind
+    /// of number they came as, since `1` and `1.0` are equal.
+    pub fn num(n: f64) -> Self {
+        const LIMIT: f64 = 9_223_372_036_854_775_808.0; // 2^63
+        if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(&n) {
+            Key::Int(n as i64)
+        } else {
+            Key::Num(OrderedFloat(n))
+        }
+    }
+}
+
+// Where a variable lives at runtime. The parser leaves every name as
+// `Global`; the resolver fills in locals as (scopes up, index in scope).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Slot {
+    Global,
+    Local(usize, usize),
+}
+
+// Lists and objects are shared by reference. `frozen` is set by the
+// `freeze` builtin and checked before any mutation.
+#[derive(Debug, Default)]
+pub struct Shared<T> {
+    pub data: RefCell<T>,
+    pub frozen: Cell<bool>,
+}
+
+impl<T> Shared<T> {
+    pub fn new(data: T) -> Rc<Self> {
+        Rc::new(Shared {
+            data: RefCell::new(data),
+            frozen: Cell::new(false),
+        })
+    }
+}
+
+thread_local! {
+    // The pairs of containers being compared further up the stack
+    static COMPARING: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+// A list or object can hold itself, so comparing two of them can come back
+// round to a pair already being compared. That pair is taken to be equal:
+// if anything differs, the comparison further up finds it.
+impl<T: PartialEq> PartialEq for Shared<T> {
+    fn eq(&self, other: &Self) -> bool {
+        if std::ptr::eq(self, other) {
+            return true;
+        }
+        let pair = (self as *const Self as usize, other as *const Self as usize);
+        if COMPARING.with_borrow(|pairs| pairs.contains(&pair)) {
+            return true;
+        }
+        COMPARING.with_borrow_mut(|pairs| pairs.push(pair));
+        let equal = self.data == other.data;
+        COMPARING.with_borrow_mut(|pairs| pairs.pop());
+        equal
+    }
+}
+
+pub type List = Rc<Shared<Vec<Value>>>;
+// Objects keep their keys in insertion order
+pub type Object = Rc<Shared<IndexMap<Key, Value>>>;
+
+#[derive(Clone, Debug)]
+pub enum Value {
     Undefined,
+    // Whole numbers written without a fraction, and what arithmetic on them
+    // gives; mixing in a `Num` gives a `Num`
+    Int(i64),
+    // Whole numbers past the `i64`s, which `Int` arithmetic overflows into.
+    // Anything that fits in an `Int` is one instead; see `Value::big`.
+    BigInt(Rc<BigInt>),
+    // Exact decimals, written with a `d` suffix: `1.10d`
+    Decimal(Rc<BigDecimal>),
     Num(f64),
     Str(String),
     Bool(bool),
-    Vec(Vec<BSE<'a>>),
-    Obj(HashMap<Key, BSE<'a>>),
-    Fn(Vec<&'a str>, BSE<'a>),
+    Vec(List),
+    Obj(Object),
+    Fn(Rc<Signature>, BSE, Scope),
+    // Function compiled by the bytecode VM, indexed into its function table,
+    // with the variables it captured from the functions it is nested in
+    Proto(usize, Captures),
+    // Function implemented in Rust, named like `math.sqrt`; see `builtins`
+    Builtin(Symbol),
+    // Builtin method together with the value it was read from, which it
+    // runs on: `"abc".upper`
+    Method(Box<Value>, Symbol),
+}
+
+// Optional type annotations. Unannotated bindings and parameters are never
+// checked at runtime; the checker infers what it can about them. `Never` is
+// the type of no value at all and is only produced by inference.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Any,
+    Never,
+    Undefined,
+    Num,
+    Str,
+    Bool,
+    Obj,
+    List(Box<Type>),
+    Fn(Vec<Type>, Box<Type>),
+    Union(Vec<Type>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Param {
+    pub name: Symbol,
+    pub ty: Option<Type>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub params: Vec<Param>,
+    pub ret: Option<Type>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -31,12 +151,25 @@ pub enum Op {
 
     Not,
     Neg,
+    BitNot,
 
     Add,
     Sub,
     Mul,
     Div,
     Mod,
+    // `**`
+    Pow,
+    // `~/`, division rounded down
+    IntDiv,
+
+    // Bitwise operators work on the 64-bit integers that whole numbers
+    // stand for
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
 
     And,
     Or,
@@ -50,41 +183,103 @@ pub enum Op {
 }
 
 #[derive(Clone, Debug, PartialEq)]
-pub enum Expr<'a> {
+pub enum Expr {
     Error,
     Comment(String),
     Nl,
-    Var(&'a str),
-    ReservedVar(&'a str),
+    Var(Symbol, Slot),
+    ReservedVar(Symbol),
+
+    Val(Value),
+    List(Vec<BSE>),
+    Obj(Vec<(PropKey, BSE)>),
 
-    Val(Value<'a>),
+    UnOp(Op, BSE),
+    BnOp(BSE, Op, BSE),
 
-    UnOp(Op, BSE<'a>),
-    BnOp(BSE<'a>, Op, BSE<'a>),
+    Let(Symbol, Slot, Option<Type>, BSE),
+    Const(Symbol, Slot, Option<Type>, BSE),
+    Assign(Symbol, Slot, BSE),
 
-    Let(&'a str, BSE<'a>),
-    Assign(&'a str, BSE<'a>),
+    Block(Vec<BSE>),
 
-    Block(Vec<BSE<'a>>),
+    Fn(Symbol, Slot, Rc<Signature>, BSE),
+    Return(BSE),
+    Call(BSE, Vec<BSE>),
+    // A call whose value is the enclosing function's result, marked by the
+    // parser. The interpreter runs it in place of that function's call
+    // rather than nested in it.
+    TailCall(BSE, Vec<BSE>),
 
-    Fn(&'a str, Vec<&'a str>, BSE<'a>),
-    Return(BSE<'a>),
-    Call(BSE<'a>, Vec<BSE<'a>>),
+    ReservedCall(Symbol, Vec<BSE>),
 
-    ReservedCall(&'a str, Vec<BSE<'a>>),
+    If(BSE, BSE, BSE),
 
-    If(BSE<'a>, BSE<'a>, BSE<'a>),
+    While(BSE, BSE),
+
+    // Reads a field of an object, like a module namespace's `math.square`
+    Field(BSE, Symbol),
+    // `target[index]`: an element of a list or string, or an object's field
+    Index(BSE, BSE),
+    // Assigns to a field or an element, evaluating the object and the index
+    // once however the new value is worked out. Assignments to variables
+    // are all rewritten into `Assign` by the parser.
+    Set(Place, AssignOp, BSE),
+
+    // Raises a value as an error
+    Throw(BSE),
+    // `try body catch (name) handler finally cleanup`, where either the
+    // catch or the finally part may be left out
+    Try(BSE, Option<(Symbol, Slot, BSE)>, Option<BSE>),
+
+    Import(String, Imports),
+    // A top-level `let`, `const` or `fn` that other modules may import
+    Export(BSE),
+}
 
-    While(BSE<'a>, BSE<'a>),
+// A key in an object literal: written out, or `[expr]`, which is computed
+// each time the literal is evaluated.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PropKey {
+    Fixed(Key),
+    Computed(BSE),
+}
+
+// What `Set` assigns to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Place {
+    Field(BSE, Symbol),
+    Index(BSE, BSE),
+}
+
+// How an assignment's value is worked out: `=` takes it as it is, `+=` and
+// the like combine it with the current value, and `??=` only assigns it,
+// and only evaluates it, if the current value is undefined.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AssignOp {
+    Replace,
+    Apply(Op),
+    IfUndefined,
+}
+
+// What an `import` binds in the importing module: the whole module as one
+// namespace object, or some of its exports under their own names.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Imports {
+    Module(Symbol, Slot),
+    Names(Vec<(Symbol, Slot)>),
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Tkn<'a> {
+    Int(i64),
+    BigInt(BigInt),
+    Decimal(BigDecimal),
     Number(f64),
     Str(String),
     Bool(bool),
 
-    Identifier(&'a str),
+    Identifier(Symbol),
     Delimiter(char),
     Keyword(&'a str),
     Symbol(&'a str),
@@ -94,6 +289,9 @@ pub enum Tkn<'a> {
 impl fmt::Display for Tkn<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Tkn::Int(n) => write!(f, "{}", n),
+            Tkn::BigInt(n) => write!(f, "{}", n),
+            Tkn::Decimal(n) => write!(f, "{}d", n),
             Tkn::Number(n) => write!(f, "{}", n),
             Tkn::Bool(n) => write!(f, "{}", n),
             Tkn::Str(n) => write!(f, "{}", n),
@@ -106,16 +304,93 @@ impl fmt::Display for Tkn<'_> {
     }
 }
 
-impl fmt::Display for Value<'_> {
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "any"),
+            Type::Never => write!(f, "never"),
+            Type::Undefined => write!(f, "undefined"),
+            Type::Num => write!(f, "num"),
+            Type::Str => write!(f, "str"),
+            Type::Bool => write!(f, "bool"),
+            Type::Obj => write!(f, "obj"),
+            Type::List(item) => write!(f, "list<{}>", item),
+            Type::Fn(params, ret) => {
+                let params: Vec<_> = params.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", params.join(", "), ret)
+            }
+            Type::Union(types) => {
+                let types: Vec<_> = types.iter().map(|t| t.to_string()).collect();
+                write!(f, "{}", types.join(" | "))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            Value::Int(x) => write!(f, "{}", x),
+            Value::BigInt(x) => write!(f, "{}", x),
+            // As written, trailing zeros included, and never in exponent form
+            Value::Decimal(x) => write!(f, "{}", x.to_plain_string()),
             Value::Num(x) => write!(f, "{}", x),
             Value::Str(x) => write!(f, "{}", x),
             Value::Bool(x) => write!(f, "{}", x),
-            Value::Vec(_) => write!(f, ""),
-            Value::Obj(_) => write!(f, ""),
+            Value::Vec(_) | Value::Obj(_) => show(self, f, &mut Vec::new()),
             Value::Undefined => write!(f, "undefined"),
-            Value::Fn(_, _) => write!(f, "(Function () => {{}})"),
+            Value::Fn(..) | Value::Proto(..) | Value::Builtin(_) | Value::Method(..) => {
+                write!(f, "(Function () => {{}})")
+            }
+        }
+    }
+}
+
+// Lists and objects print like their literals, with the strings inside them
+// quoted, and objects in insertion order. `seen` holds the lists and objects
+// `value` is inside of; one holding itself prints as `[...]` or `{...}` where
+// it comes round again.
+fn show(value: &Value, f: &mut fmt::Formatter, seen: &mut Vec<usize>) -> fmt::Result {
+    match value {
+        Value::Str(s) if !seen.is_empty() => write!(f, "\"{}\"", s),
+        Value::Vec(list) => {
+            let at = Rc::as_ptr(list) as usize;
+            if seen.contains(&at) {
+                return write!(f, "[...]");
+            }
+            seen.push(at);
+            write!(f, "[")?;
+            for (i, item) in list.data.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                show(item, f, seen)?;
+            }
+            seen.pop();
+            write!(f, "]")
+        }
+        Value::Obj(obj) => {
+            let at = Rc::as_ptr(obj) as usize;
+            if seen.contains(&at) {
+                return write!(f, "{{...}}");
+            }
+            seen.push(at);
+            write!(f, "{{")?;
+            for (i, (key, value)) in obj.data.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                match key {
+                    Key::Str(s) => write!(f, "\"{}\"", s)?,
+                    Key::Int(n) => write!(f, "{}", n)?,
+                    Key::Num(n) => write!(f, "{}", n)?,
+                }
+                write!(f, ": ")?;
+                show(value, f, seen)?;
+            }
+            seen.pop();
+            write!(f, "}}")
         }
+        value => write!(f, "{}", value),
     }
 }
@@ -0,0 +1,112 @@
+use super::super::checker::Boundary;
+use super::super::types::{Key, Op, Span, Symbol, Type, Value};
+
+#[derive(Clone, Debug)]
+pub enum Instr {
+    Const(usize),
+    Undefined,
+    Pop,
+    // Push copies of the top `n` values, in order
+    Dup(usize),
+    // Drop `n` locals sitting beneath the value on top of the stack
+    Close(usize),
+
+    GetLocal(usize),
+    SetLocal(usize),
+    // Variables the running function captured, by their index among its
+    // captures
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    GetGlobal(Symbol),
+    SetGlobal(Symbol),
+    // The flag is false for `const` bindings; the type is the annotation
+    // later assignments are checked against
+    DefineGlobal(Symbol, bool, Option<Type>),
+    // Checks the value on top of the stack against an annotation
+    Check(Type, Boundary),
+
+    BuildList(usize),
+    // A computed key (`None`) is on the stack just before its value
+    BuildObj(Vec<Option<Key>>),
+
+    Unary(Op),
+    Field(Symbol),
+    Index,
+    Binary(Op),
+    // Store the value on top into the object beneath it, and its key
+    // beneath that for `SetIndex`, popping them all
+    SetField(Symbol),
+    SetIndex,
+
+    Jump(usize),
+    JumpIfFalse(usize),
+
+    // Creates a function, capturing the variables its prototype lists
+    Function(usize),
+    Call(usize),
+    // A call whose result the running function returns: the callee takes over
+    // its frame. The type is the running function's return annotation, which
+    // the callee's result is checked against when it returns
+    TailCall(usize, Option<Type>),
+    Return,
+    // `print` writes each argument as soon as it is evaluated, like the
+    // tree-walker; the flag says whether a separating space follows
+    Write(bool),
+    Newline,
+    Builtin(Symbol, usize),
+}
+
+/// A sequence of instructions together with its constant pool. `spans` runs
+/// parallel to `code` so runtime errors can point back at the source.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<Instr>,
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+}
+
+impl Chunk {
+    pub fn emit(&mut self, instr: Instr, span: Span) -> usize {
+        self.code.push(instr);
+        self.spans.push(span);
+        self.code.len() - 1
+    }
+
+    pub fn constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction to be emitted.
+    pub fn patch(&mut self, at: usize) {
+        let target = self.code.len();
+        match &mut self.code[at] {
+            Instr::Jump(to) | Instr::JumpIfFalse(to) => *to = target,
+            _ => unreachable!("patching a non-jump instruction"),
+        }
+    }
+}
+
+/// Where a function gets a variable it captures when it is created: from
+/// the locals of the function creating it, or from what that function
+/// captured itself.
+#[derive(Clone, Copy, Debug)]
+pub enum Capture {
+    Local(usize),
+    Upvalue(usize),
+}
+
+#[derive(Debug)]
+pub struct Proto {
+    pub arity: usize,
+    pub chunk: Chunk,
+    pub captures: Vec<Capture>,
+}
+
+/// A compiled program: the function table plus one entry point per top-level
+/// statement, run in order the same way `interpreter::run` walks the AST.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub protos: Vec<Proto>,
+    pub main: Vec<usize>,
+}
@@ -0,0 +1,516 @@
+use super::super::checker::Boundary;
+use super::super::interpreter::builtins;
+use super::super::types::{
+    AssignOp, BSE, Expr, Op, Place, PropKey, Signature, Span, Spanned, Symbol, Type,
+};
+use super::chunk::{Capture, Chunk, Instr, Program, Proto};
+
+struct Local {
+    name: Symbol,
+    depth: usize,
+    constant: bool,
+    ty: Option<Type>,
+}
+
+// A variable captured from an enclosing function, with what its declaration
+// says about assigning to it
+struct Upvalue {
+    name: Symbol,
+    from: Capture,
+    constant: bool,
+    ty: Option<Type>,
+}
+
+// Where a name the current function uses lives
+#[derive(Clone, Copy)]
+enum Var {
+    Local(usize),
+    Upvalue(usize),
+    Global,
+}
+
+// Per-function compilation state. Locals are resolved to stack slots here,
+// so the VM never looks a local up by name.
+struct FnState {
+    chunk: Chunk,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    depth: usize,
+    is_fn: bool,
+    ret: Option<Type>,
+}
+
+impl FnState {
+    fn new(is_fn: bool) -> Self {
+        FnState {
+            chunk: Chunk::default(),
+            locals: Vec::new(),
+            upvalues: Vec::new(),
+            depth: if is_fn { 1 } else { 0 },
+            is_fn,
+            ret: None,
+        }
+    }
+}
+
+struct Compiler {
+    program: Program,
+    states: Vec<FnState>,
+    errors: Vec<Spanned<String>>,
+}
+
+pub fn compile(ast: &[BSE]) -> Result<Program, Vec<Spanned<String>>> {
+    let mut compiler = Compiler {
+        program: Program::default(),
+        states: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    for stmt in ast {
+        compiler.states.push(FnState::new(false));
+        compiler.expr(stmt);
+        compiler.emit(Instr::Return, stmt.1);
+
+        let state = compiler.states.pop().unwrap();
+        let idx = compiler.proto(0, state);
+        compiler.program.main.push(idx);
+    }
+
+    if compiler.errors.is_empty() {
+        Ok(compiler.program)
+    } else {
+        Err(compiler.errors)
+    }
+}
+
+impl Compiler {
+    fn state(&mut self) -> &mut FnState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, instr: Instr, span: Span) -> usize {
+        self.state().chunk.emit(instr, span)
+    }
+
+    fn patch(&mut self, at: usize) {
+        self.state().chunk.patch(at)
+    }
+
+    fn proto(&mut self, arity: usize, state: FnState) -> usize {
+        let captures = state.upvalues.iter().map(|u| u.from).collect();
+        self.program.protos.push(Proto {
+            arity,
+            chunk: state.chunk,
+            captures,
+        });
+        self.program.protos.len() - 1
+    }
+
+    fn error(&mut self, msg: String, span: Span) {
+        self.errors.push((msg, span));
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        let state = self.state();
+        state.depth -= 1;
+
+        let depth = state.depth;
+        let keep = state.locals.iter().take_while(|l| l.depth <= depth).count();
+        let dropped = state.locals.len() - keep;
+        state.locals.truncate(keep);
+
+        if dropped > 0 {
+            self.emit(Instr::Close(dropped), span);
+        }
+    }
+
+    fn scoped(&mut self, expr: &BSE) {
+        self.begin_scope();
+        self.expr(expr);
+        self.end_scope(expr.1);
+    }
+
+    fn declare(&mut self, name: Symbol, constant: bool, ty: Option<Type>) {
+        let state = self.state();
+        let depth = state.depth;
+        state.locals.push(Local {
+            name,
+            depth,
+            constant,
+            ty,
+        });
+    }
+
+    fn define(&mut self, name: Symbol, constant: bool, ty: Option<Type>, span: Span) {
+        if let Some(ty) = &ty {
+            self.emit(Instr::Check(ty.clone(), Boundary::Binding(name)), span);
+        }
+        if self.state().depth == 0 {
+            self.emit(Instr::DefineGlobal(name, !constant, ty), span);
+        } else {
+            self.declare(name, constant, ty);
+        }
+        self.emit(Instr::Undefined, span);
+    }
+
+    // Emits a return from the current function, checking its annotation
+    fn r#return(&mut self, span: Span) {
+        if let Some(ret) = self.state().ret.clone() {
+            self.emit(Instr::Check(ret, Boundary::Return), span);
+        }
+        self.emit(Instr::Return, span);
+    }
+
+    fn resolve(&mut self, name: Symbol) -> Var {
+        let current = self.states.len() - 1;
+        if let Some(slot) = self.states[current]
+            .locals
+            .iter()
+            .rposition(|l| l.name == name)
+        {
+            return Var::Local(slot);
+        }
+        match self.upvalue(current, name) {
+            Some(idx) => Var::Upvalue(idx),
+            None => Var::Global,
+        }
+    }
+
+    // The index among the captures of the function compiled by `states[at]`
+    // of `name`, a local of a function it is nested in, capturing it through
+    // every function in between if need be. The top level captures nothing.
+    fn upvalue(&mut self, at: usize, name: Symbol) -> Option<usize> {
+        if at == 0 {
+            return None;
+        }
+        if let Some(idx) = self.states[at].upvalues.iter().position(|u| u.name == name) {
+            return Some(idx);
+        }
+        let enclosing = &self.states[at - 1];
+        let upvalue = match enclosing.locals.iter().rposition(|l| l.name == name) {
+            Some(slot) => Upvalue {
+                name,
+                from: Capture::Local(slot),
+                constant: enclosing.locals[slot].constant,
+                ty: enclosing.locals[slot].ty.clone(),
+            },
+            None => {
+                let idx = self.upvalue(at - 1, name)?;
+                let outer = &self.states[at - 1].upvalues[idx];
+                Upvalue {
+                    name,
+                    from: Capture::Upvalue(idx),
+                    constant: outer.constant,
+                    ty: outer.ty.clone(),
+                }
+            }
+        };
+        self.states[at].upvalues.push(upvalue);
+        Some(self.states[at].upvalues.len() - 1)
+    }
+
+    // Compiles a function and leaves it on the stack
+    fn function(&mut self, signature: &Signature, body: &BSE, span: Span) {
+        let mut state = FnState::new(true);
+        state.ret = signature.ret.clone();
+        for param in &signature.params {
+            state.locals.push(Local {
+                name: param.name,
+                depth: 1,
+                constant: false,
+                ty: param.ty.clone(),
+            });
+        }
+        self.states.push(state);
+
+        for (slot, param) in signature.params.iter().enumerate() {
+            if let Some(ty) = &param.ty {
+                self.emit(Instr::GetLocal(slot), span);
+                self.emit(Instr::Check(ty.clone(), Boundary::Param(param.name)), span);
+                self.emit(Instr::Pop, span);
+            }
+        }
+        self.expr(body);
+        self.r#return(body.1);
+
+        let state = self.states.pop().unwrap();
+        let idx = self.proto(signature.params.len(), state);
+        self.emit(Instr::Function(idx), span);
+    }
+
+    // Every expression leaves exactly one value on the stack, except that a
+    // local `let` additionally leaves its slot beneath that value.
+    fn expr(&mut self, expr: &BSE) {
+        let span = expr.1;
+        match &expr.0 {
+            Expr::Val(v) => {
+                let idx = self.state().chunk.constant(v.clone());
+                self.emit(Instr::Const(idx), span);
+            }
+
+            Expr::Var(name, _) => {
+                let get = match self.resolve(*name) {
+                    Var::Local(slot) => Instr::GetLocal(slot),
+                    Var::Upvalue(idx) => Instr::GetUpvalue(idx),
+                    Var::Global => Instr::GetGlobal(*name),
+                };
+                self.emit(get, span);
+            }
+
+            Expr::Let(name, _, ty, value) => {
+                self.expr(value);
+                self.define(*name, false, ty.clone(), span);
+            }
+
+            Expr::Const(name, _, ty, value) => {
+                self.expr(value);
+                self.define(*name, true, ty.clone(), span);
+            }
+
+            Expr::Assign(name, _, value) => {
+                self.expr(value);
+                let var = self.resolve(*name);
+                let (constant, ty, set) = match var {
+                    Var::Local(slot) => {
+                        let local = &self.state().locals[slot];
+                        (local.constant, local.ty.clone(), Instr::SetLocal(slot))
+                    }
+                    Var::Upvalue(idx) => {
+                        let upvalue = &self.state().upvalues[idx];
+                        (upvalue.constant, upvalue.ty.clone(), Instr::SetUpvalue(idx))
+                    }
+                    Var::Global => (false, None, Instr::SetGlobal(*name)),
+                };
+                if constant {
+                    self.error(format!("Cannot assign to constant '{}'.", name), span);
+                }
+                if let Some(ty) = ty {
+                    self.emit(Instr::Check(ty, Boundary::Binding(*name)), span);
+                }
+                self.emit(set, span);
+                self.emit(Instr::Undefined, span);
+            }
+
+            Expr::List(items) => {
+                for item in items {
+                    self.expr(item);
+                }
+                self.emit(Instr::BuildList(items.len()), span);
+            }
+
+            Expr::Obj(entries) => {
+                let mut keys = Vec::with_capacity(entries.len());
+                for (key, value) in entries {
+                    match key {
+                        PropKey::Fixed(key) => keys.push(Some(key.clone())),
+                        PropKey::Computed(key) => {
+                            self.expr(key);
+                            keys.push(None);
+                        }
+                    }
+                    self.expr(value);
+                }
+                self.emit(Instr::BuildObj(keys), span);
+            }
+
+            Expr::BnOp(left, op, right) => {
+                self.expr(left);
+                self.expr(right);
+                self.emit(Instr::Binary(op.clone()), span);
+            }
+
+            Expr::UnOp(op, value) => {
+                self.expr(value);
+                self.emit(Instr::Unary(op.clone()), span);
+            }
+
+            Expr::Block(stmts) => {
+                self.begin_scope();
+                if stmts.is_empty() {
+                    self.emit(Instr::Undefined, span);
+                }
+                // Local functions get their slots before anything in the
+                // block runs, so they can call each other whatever their
+                // order
+                for stmt in stmts {
+                    if let Expr::Fn(name, ..) = &stmt.0 {
+                        self.emit(Instr::Undefined, stmt.1);
+                        self.declare(*name, false, None);
+                    }
+                }
+                for (i, stmt) in stmts.iter().enumerate() {
+                    match &stmt.0 {
+                        Expr::Fn(name, _, signature, body) => {
+                            self.function(signature, body, stmt.1);
+                            if let Var::Local(slot) = self.resolve(*name) {
+                                self.emit(Instr::SetLocal(slot), stmt.1);
+                            }
+                            self.emit(Instr::Undefined, stmt.1);
+                        }
+                        _ => self.expr(stmt),
+                    }
+                    if i + 1 < stmts.len() {
+                        self.emit(Instr::Pop, stmt.1);
+                    }
+                }
+                self.end_scope(span);
+            }
+
+            Expr::Fn(name, _, signature, body) => {
+                self.function(signature, body, span);
+                self.define(*name, false, None, span);
+            }
+
+            Expr::Return(value) => {
+                self.expr(value);
+                if self.state().is_fn {
+                    self.r#return(span);
+                }
+            }
+
+            Expr::Call(callee, args) => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+                self.emit(Instr::Call(args.len()), span);
+            }
+            Expr::TailCall(callee, args) => {
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+                let ret = self.state().ret.clone();
+                self.emit(Instr::TailCall(args.len(), ret), span);
+            }
+
+            Expr::ReservedCall(name, args) => match name.as_str() {
+                "print" => {
+                    for (i, arg) in args.iter().enumerate() {
+                        self.expr(arg);
+                        self.emit(Instr::Write(i + 1 < args.len()), arg.1);
+                    }
+                    self.emit(Instr::Newline, span);
+                }
+                _ => {
+                    for arg in args {
+                        self.expr(arg);
+                    }
+                    self.emit(Instr::Builtin(*name, args.len()), span);
+                }
+            },
+
+            Expr::If(cond, then, els) => {
+                self.expr(cond);
+                let to_else = self.emit(Instr::JumpIfFalse(0), span);
+                self.scoped(then);
+                let to_end = self.emit(Instr::Jump(0), span);
+                self.patch(to_else);
+                self.scoped(els);
+                self.patch(to_end);
+            }
+
+            Expr::While(cond, body) => {
+                // The loop's value is its last iteration's, as in the
+                // tree-walker, so a result slot rides on top of the stack.
+                self.emit(Instr::Undefined, span);
+                let start = self.state().chunk.code.len();
+                self.expr(cond);
+                let to_end = self.emit(Instr::JumpIfFalse(0), span);
+                self.emit(Instr::Pop, span);
+                self.scoped(body);
+                self.emit(Instr::Jump(start), span);
+                self.patch(to_end);
+            }
+
+            // Standard library modules are frozen, so one value serves
+            // every use
+            Expr::ReservedVar(name) => match builtins::namespace(*name) {
+                Some(module) => {
+                    let idx = self.state().chunk.constant(module);
+                    self.emit(Instr::Const(idx), span);
+                }
+                None => {
+                    self.error(format!("Unknown builtin '{}'.", name), span);
+                    self.emit(Instr::Undefined, span);
+                }
+            },
+
+            Expr::Field(object, name) => {
+                self.expr(object);
+                self.emit(Instr::Field(*name), span);
+            }
+
+            Expr::Index(object, index) => {
+                self.expr(object);
+                self.expr(index);
+                self.emit(Instr::Index, span);
+            }
+
+            // The object and the index stay on the stack, and are copied
+            // when the current value has to be read
+            Expr::Set(place, op, value) => {
+                let (n, get, set) = match place {
+                    Place::Field(object, name) => {
+                        self.expr(object);
+                        (1, Instr::Field(*name), Instr::SetField(*name))
+                    }
+                    Place::Index(object, index) => {
+                        self.expr(object);
+                        self.expr(index);
+                        (2, Instr::Index, Instr::SetIndex)
+                    }
+                };
+                match op {
+                    AssignOp::Replace => {
+                        self.expr(value);
+                        self.emit(set, span);
+                    }
+                    AssignOp::Apply(op) => {
+                        self.emit(Instr::Dup(n), span);
+                        self.emit(get, span);
+                        self.expr(value);
+                        self.emit(Instr::Binary(op.clone()), span);
+                        self.emit(set, span);
+                    }
+                    AssignOp::IfUndefined => {
+                        self.emit(Instr::Dup(n), span);
+                        self.emit(get, span);
+                        self.emit(Instr::Undefined, span);
+                        self.emit(Instr::Binary(Op::Eq), span);
+                        let to_skip = self.emit(Instr::JumpIfFalse(0), span);
+                        self.expr(value);
+                        self.emit(set, span);
+                        let to_end = self.emit(Instr::Jump(0), span);
+                        self.patch(to_skip);
+                        for _ in 0..n {
+                            self.emit(Instr::Pop, span);
+                        }
+                        self.patch(to_end);
+                    }
+                }
+                self.emit(Instr::Undefined, span);
+            }
+
+            // A program run by the VM is a single file
+            Expr::Import(..) => {
+                self.error("Imports are not supported by the VM.".into(), span);
+                self.emit(Instr::Undefined, span);
+            }
+            Expr::Export(decl) => self.expr(decl),
+            Expr::Throw(_) | Expr::Try(..) => {
+                self.error("Exceptions are not supported by the VM.".into(), span);
+                self.emit(Instr::Undefined, span);
+            }
+
+            _ => {
+                self.error("Not implemented".into(), span);
+                self.emit(Instr::Undefined, span);
+            }
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use super::ast::show_spanned_errors;
+use super::checker::{Boundary, check_value};
+use super::interpreter::{Error, Limits, Res, builtins, operators};
+use super::types::{Shared, Span, Spanned, Symbol, Type, Value};
+use chunk::{Capture, Instr, Program};
+use indexmap::IndexMap;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::rc::Rc;
+
+mod chunk;
+mod compiler;
+
+pub use compiler::compile;
+
+/// A variable a function has captured. It stays in its stack slot, shared
+/// with the function that declared it, until that slot goes away; then it
+/// is closed over, and the value moves in here.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// The variables a function captured, in the order its code refers to them.
+pub type Captures = Rc<[Rc<RefCell<Upvalue>>]>;
+
+struct Frame {
+    proto: usize,
+    ip: usize,
+    // Stack index of the frame's first parameter; the callee sits just below
+    base: usize,
+    captures: Captures,
+    // Annotations of the functions that tail-called into this frame, which
+    // its result is returned from as well
+    returns: Vec<(Type, Span)>,
+}
+
+struct Global {
+    value: Value,
+    mutable: bool,
+    ty: Option<Type>,
+}
+
+struct Vm {
+    globals: HashMap<Symbol, Global>,
+    stack: Vec<Value>,
+    // Captured variables still in their stack slots
+    open: Vec<Rc<RefCell<Upvalue>>>,
+    // Callbacks running inside one another, each in a nested `run`
+    callbacks: usize,
+    // Frames of the runs a callback is running inside of
+    suspended: usize,
+    // Only the call limit applies: calls within a run take no native stack
+    limits: Limits,
+}
+
+// Calls within a run need no native stack, but a callback does, so builtins
+// calling functions that call builtins can only nest this deep
+const MAX_CALLBACKS: usize = 1000;
+
+pub fn run(program: &Program, filename: &str, source: &str, limits: Limits) {
+    let mut vm = Vm {
+        globals: HashMap::new(),
+        stack: Vec::new(),
+        open: Vec::new(),
+        callbacks: 0,
+        suspended: 0,
+        limits,
+    };
+
+    for &entry in &program.main {
+        if let Err(err) = vm.execute(program, entry) {
+            show_spanned_errors(vec![err], filename.to_string(), source);
+            vm.truncate(0);
+        }
+    }
+}
+
+impl Vm {
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("VM stack underflow")
+    }
+
+    // The captured variable in stack slot `slot`, shared with every other
+    // function that captured it while it is still there
+    fn capture(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let open = self
+            .open
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(s) if s == slot));
+        match open {
+            Some(upvalue) => upvalue.clone(),
+            None => {
+                let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+                self.open.push(upvalue.clone());
+                upvalue
+            }
+        }
+    }
+
+    // Shrinks the stack to `len` values, first closing over the captured
+    // variables in the slots that go away
+    fn truncate(&mut self, len: usize) {
+        let stack = &self.stack;
+        self.open.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= len => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+        self.stack.truncate(len);
+    }
+
+    fn execute(&mut self, program: &Program, entry: usize) -> Result<Value, Spanned<String>> {
+        let frame = Frame {
+            proto: entry,
+            ip: 0,
+            base: 0,
+            captures: Captures::default(),
+            returns: Vec::new(),
+        };
+        self.run(program, frame)
+    }
+
+    // Calls a function value from native code, for builtins that take
+    // callbacks. A Pulse function runs in a nested loop of its own.
+    fn call(&mut self, program: &Program, callee: Value, mut args: Vec<Value>) -> Res {
+        match callee {
+            Value::Proto(..) if self.callbacks >= MAX_CALLBACKS => {
+                let msg = format!(
+                    "Stack overflow: callbacks nested more than {} deep.",
+                    MAX_CALLBACKS
+                );
+                Res::E(Error::new("StackOverflowError", msg))
+            }
+            // Every suspended frame is a call but the top level's
+            Value::Proto(..) if self.suspended > self.limits.calls => {
+                let msg = format!(
+                    "Stack overflow: more than {} nested calls.",
+                    self.limits.calls
+                );
+                Res::E(Error::new("StackOverflowError", msg))
+            }
+            Value::Proto(idx, ref captures) => {
+                let base = self.stack.len() + 1;
+                args.resize(program.protos[idx].arity, Value::Undefined);
+                let frame = Frame {
+                    proto: idx,
+                    ip: 0,
+                    base,
+                    captures: captures.clone(),
+                    returns: Vec::new(),
+                };
+                self.stack.push(callee);
+                self.stack.extend(args);
+                self.callbacks += 1;
+                let result = self.run(program, frame);
+                self.callbacks -= 1;
+                self.truncate(base - 1);
+                match result {
+                    Ok(value) => Res::V(value),
+                    Err((msg, span)) => Res::E(Error {
+                        span: Some(span),
+                        ..Error::from(msg)
+                    }),
+                }
+            }
+            native @ (Value::Builtin(_) | Value::Method(..)) => {
+                builtins::native(native, args, &mut |f, args| self.call(program, f, args))
+            }
+            _ => Res::E("Not a function.".into()),
+        }
+    }
+
+    // Runs until `frame` returns
+    fn run(&mut self, program: &Program, frame: Frame) -> Result<Value, Spanned<String>> {
+        let mut frames = vec![frame];
+
+        loop {
+            let frame = frames.last_mut().unwrap();
+            let chunk = &program.protos[frame.proto].chunk;
+            let instr = &chunk.code[frame.ip];
+            let span = chunk.spans[frame.ip];
+            let base = frame.base;
+            frame.ip += 1;
+
+            match instr {
+                Instr::Const(idx) => self.stack.push(chunk.constants[*idx].clone()),
+                Instr::Undefined => self.stack.push(Value::Undefined),
+                Instr::Pop => {
+                    self.pop();
+                }
+                Instr::Dup(n) => {
+                    let from = self.stack.len() - n;
+                    self.stack.extend_from_within(from..);
+                }
+                Instr::Close(n) => {
+                    let top = self.pop();
+                    self.truncate(self.stack.len() - n);
+                    self.stack.push(top);
+                }
+
+                Instr::GetLocal(slot) => self.stack.push(self.stack[base + slot].clone()),
+                Instr::SetLocal(slot) => {
+                    let value = self.pop();
+                    self.stack[base + slot] = value;
+                }
+                Instr::GetUpvalue(idx) => {
+                    let value = match &*frame.captures[*idx].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                Instr::SetUpvalue(idx) => {
+                    let value = self.pop();
+                    match &mut *frame.captures[*idx].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                Instr::GetGlobal(name) => match self.globals.get(name) {
+                    Some(global) => self.stack.push(global.value.clone()),
+                    None => return Err((format!("Undefined variable '{}'.", name), span)),
+                },
+                Instr::SetGlobal(name) => {
+                    let value = self.pop();
+                    match self.globals.get_mut(name) {
+                        Some(global) if !global.mutable => {
+                            let msg = format!("Cannot assign to constant '{}'.", name);
+                            return Err((msg, span));
+                        }
+                        Some(global) => {
+                            if let Some(ty) = &global.ty
+                                && let Some(msg) = check_value(ty, &value, Boundary::Binding(*name))
+                            {
+                                return Err((msg, span));
+                            }
+                            global.value = value
+                        }
+                        None => return Err((format!("Undefined variable '{}'.", name), span)),
+                    }
+                }
+                Instr::DefineGlobal(name, mutable, ty) => {
+                    let value = self.pop();
+                    match self.globals.entry(*name) {
+                        Entry::Occupied(_) => {
+                            let msg = format!("Variable '{}' is already declared.", name);
+                            return Err((msg, span));
+                        }
+                        Entry::Vacant(slot) => {
+                            slot.insert(Global {
+                                value,
+                                mutable: *mutable,
+                                ty: ty.clone(),
+                            });
+                        }
+                    }
+                }
+
+                Instr::Check(ty, boundary) => {
+                    let value = self.stack.last().unwrap();
+                    if let Some(msg) = check_value(ty, value, *boundary) {
+                        return Err((msg, span));
+                    }
+                }
+
+                Instr::BuildList(n) => {
+                    let list = self.stack.split_off(self.stack.len() - n);
+                    self.stack.push(Value::Vec(Shared::new(list)));
+                }
+                Instr::BuildObj(keys) => {
+                    let computed = keys.iter().filter(|key| key.is_none()).count();
+                    let len = self.stack.len() - keys.len() - computed;
+                    let mut values = self.stack.split_off(len).into_iter();
+                    let mut obj = IndexMap::with_capacity(keys.len());
+                    for key in keys {
+                        let key = match key {
+                            Some(key) => key.clone(),
+                            None => match operators::key(values.next().unwrap()) {
+                                Ok(key) => key,
+                                Err(e) => return Err((e.msg, span)),
+                            },
+                        };
+                        obj.insert(key, values.next().unwrap());
+                    }
+                    self.stack.push(Value::Obj(Shared::new(obj)));
+                }
+
+                Instr::Unary(op) => {
+                    let value = self.pop();
+                    match operators::unary(op, value).into_result() {
+                        Ok(v) => self.stack.push(v),
+                        Err(e) => return Err((e.msg, span)),
+                    }
+                }
+                Instr::Field(name) => {
+                    let value = self.pop();
+                    match operators::field(value, *name).into_result() {
+                        Ok(v) => self.stack.push(v),
+                        Err(e) => return Err((e.msg, span)),
+                    }
+                }
+                Instr::Index => {
+                    let index = self.pop();
+                    let value = self.pop();
+                    match operators::index(value, index).into_result() {
+                        Ok(v) => self.stack.push(v),
+                        Err(e) => return Err((e.msg, span)),
+                    }
+                }
+                Instr::SetField(name) => {
+                    let value = self.pop();
+                    let object = self.pop();
+                    if let Err(e) = operators::set_field(object, *name, value) {
+                        return Err((e.msg, span));
+                    }
+                }
+                Instr::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    if let Err(e) = operators::set_index(object, index, value) {
+                        return Err((e.msg, span));
+                    }
+                }
+                Instr::Binary(op) => {
+                    let r = self.pop();
+                    let l = self.pop();
+                    match operators::binary(op, l, r).into_result() {
+                        Ok(v) => self.stack.push(v),
+                        Err(e) => return Err((e.msg, span)),
+                    }
+                }
+
+                Instr::Jump(to) => frame.ip = *to,
+                Instr::JumpIfFalse(to) => {
+                    if !self.pop().is_truthy() {
+                        frame.ip = *to;
+                    }
+                }
+
+                Instr::Function(idx) => {
+                    let captures = program.protos[*idx]
+                        .captures
+                        .iter()
+                        .map(|capture| match capture {
+                            Capture::Local(slot) => self.capture(base + slot),
+                            Capture::Upvalue(idx) => frame.captures[*idx].clone(),
+                        })
+                        .collect();
+                    self.stack.push(Value::Proto(*idx, captures));
+                }
+                Instr::Call(argc) | Instr::TailCall(argc, _) => {
+                    let callee = self.stack.len() - argc - 1;
+                    let tail = matches!(instr, Instr::TailCall(..));
+                    match &self.stack[callee] {
+                        // Every frame is a call but the top level's
+                        Value::Proto(..)
+                            if !tail && self.suspended + frames.len() > self.limits.calls =>
+                        {
+                            let msg = format!(
+                                "Stack overflow: more than {} nested calls.",
+                                self.limits.calls
+                            );
+                            return Err((msg, span));
+                        }
+                        Value::Proto(idx, captures) => {
+                            let (idx, captures) = (*idx, captures.clone());
+                            // Missing arguments are undefined, extra ones dropped
+                            let arity = program.protos[idx].arity;
+                            if let Instr::TailCall(_, ret) = instr {
+                                // The callee and its arguments move down over
+                                // the running function, which is done with
+                                let moved = self.stack[callee..].to_vec();
+                                self.truncate(base - 1);
+                                self.stack.extend(moved);
+                                self.stack.resize(base + arity, Value::Undefined);
+                                let frame = frames.last_mut().unwrap();
+                                let mut returns = std::mem::take(&mut frame.returns);
+                                if let Some(ty) = ret
+                                    && !returns.iter().any(|(seen, _)| seen == ty)
+                                {
+                                    returns.push((ty.clone(), span));
+                                }
+                                *frame = Frame {
+                                    proto: idx,
+                                    ip: 0,
+                                    base,
+                                    captures,
+                                    returns,
+                                };
+                            } else {
+                                self.stack.resize(callee + 1 + arity, Value::Undefined);
+                                frames.push(Frame {
+                                    proto: idx,
+                                    ip: 0,
+                                    base: callee + 1,
+                                    captures,
+                                    returns: Vec::new(),
+                                });
+                            }
+                        }
+                        Value::Builtin(_) | Value::Method(..) => {
+                            let args = self.stack.split_off(callee + 1);
+                            let native = self.pop();
+                            self.suspended += frames.len();
+                            let mut call = |f, args| self.call(program, f, args);
+                            let result = builtins::native(native, args, &mut call);
+                            self.suspended -= frames.len();
+                            match result.into_result() {
+                                Ok(v) => self.stack.push(v),
+                                // Errors raised in a callback keep their span
+                                Err(e) => return Err((e.msg, e.span.unwrap_or(span))),
+                            }
+                        }
+                        _ => return Err(("Not a function.".into(), span)),
+                    }
+                }
+                Instr::Return => {
+                    let result = self.pop();
+                    let frame = frames.pop().unwrap();
+                    for (ty, span) in frame.returns.iter().rev() {
+                        if let Some(msg) = check_value(ty, &result, Boundary::Return) {
+                            return Err((msg, *span));
+                        }
+                    }
+                    if frames.is_empty() {
+                        self.truncate(frame.base);
+                        return Ok(result);
+                    }
+                    self.truncate(frame.base - 1);
+                    self.stack.push(result);
+                }
+
+                Instr::Write(space) => {
+                    let value = self.pop();
+                    if *space {
+                        print!("{} ", value);
+                    } else {
+                        print!("{}", value);
+                    }
+                }
+                Instr::Newline => {
+                    println!();
+                    self.stack.push(Value::Undefined);
+                }
+                Instr::Builtin(name, argc) => {
+                    let args = self.stack.split_off(self.stack.len() - argc);
+                    match builtins::call(*name, args).into_result() {
+                        Ok(v) => self.stack.push(v),
+                        Err(e) => return Err((e.msg, span)),
+                    }
+                }
+            }
+        }
+    }
+}
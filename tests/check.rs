@@ -0,0 +1,125 @@
+// `pulse check`, and the runtime checks annotated code gets when a value
+// reaches it.
+
+mod common;
+
+use common::{interpret, plain, program, pulse};
+use std::path::Path;
+
+// What `pulse check` prints for `program`, and whether it passed
+fn check(program: &Path, flags: &[&str]) -> (String, bool) {
+    let mut args = vec!["check", program.to_str().unwrap()];
+    args.extend(flags);
+    let output = pulse(&args);
+    let printed = plain(&String::from_utf8(output.stdout).unwrap());
+    (printed, output.status.success())
+}
+
+#[test]
+fn mismatches_are_reported() {
+    let program = program(
+        "check/mismatches",
+        r#"fn add(a: num, b: num) -> num = a + b;
+let total: num = add(1, 2)
+let name: str = 5
+add("x", 1)
+fn bad() -> str = 1;
+"#,
+    );
+    let (out, passed) = check(&program, &[]);
+    assert!(!passed, "{}", out);
+    for msg in [
+        "Type mismatch: 'name' is declared as str, found num.",
+        "Type mismatch: argument 1 expects num, found str.",
+        "Type mismatch: expected return type str, found num.",
+    ] {
+        assert!(out.contains(msg), "missing {:?} in\n{}", msg, out);
+    }
+    assert!(!out.contains("total"), "{}", out);
+}
+
+#[test]
+fn well_typed_programs_pass() {
+    let program = program(
+        "check/clean",
+        "fn add(a: num, b: num) -> num = a + b;
+let total: num | str = add(1, 2)
+total = \"three\"
+let items: list<num> = [1, 2]
+",
+    );
+    assert_eq!(check(&program, &[]), (String::new(), true));
+}
+
+#[test]
+fn annotations_are_checked_at_runtime() {
+    // `id` hides the types from `pulse check`, so these only fail once the
+    // values arrive
+    let program = program(
+        "check/runtime",
+        r#"fn add(a: num, b: num) -> num = a + b;
+fn id(x) = x;
+fn wrong(x) -> str = id(x);
+print(add(1, 2))
+print(add(id("x"), 1))
+print(wrong(3))
+let n: num = id("s")
+print("end")
+"#,
+    );
+    for flags in [&[][..], &["--vm"]] {
+        let out = plain(&interpret(&program, flags));
+        assert!(out.starts_with("3\n"), "{}", out);
+        assert!(out.ends_with("end\n"), "{}", out);
+        for msg in [
+            "Type mismatch: parameter 'a' expects num, found str.",
+            "Type mismatch: expected return type str, found num.",
+            "Type mismatch: 'n' is declared as num, found str.",
+        ] {
+            assert!(out.contains(msg), "missing {:?} in\n{}", msg, out);
+        }
+    }
+}
+
+#[test]
+fn types_lists_what_was_inferred() {
+    let program = program(
+        "check/types",
+        "fn twice(x) = x * 2;
+let r = twice(3)
+let items = [1, 2]
+fn same(v) = v;
+same(1)
+same(\"one\")
+",
+    );
+    let (out, passed) = check(&program, &["--types"]);
+    assert!(passed, "{}", out);
+    let name = program.display();
+    assert_eq!(
+        out,
+        format!(
+            "{name}:1:1 twice: fn(num) -> num
+{name}:2:1 r: num
+{name}:3:1 items: list<num>
+{name}:4:1 same: fn(num | str) -> num | str
+"
+        )
+    );
+}
+
+#[test]
+fn operations_that_can_only_fail_are_flagged() {
+    let program = program(
+        "check/flagged",
+        "let oops = \"a\" - 1\nlet fine = \"a\" + 1\n",
+    );
+    let (out, passed) = check(&program, &[]);
+    assert!(!passed, "{}", out);
+    assert!(
+        out.contains("Subtraction not supported: Str - Num"),
+        "{}",
+        out
+    );
+    assert_eq!(out.matches("Error:").count(), 1, "{}", out);
+}
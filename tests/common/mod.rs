@@ -0,0 +1,85 @@
+// Helpers shared by the integration tests, which all drive the `pulse`
+// binary. Not every test file uses every helper.
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Writes `source` to `<name>.pu` in the test scratch directory, creating
+/// the directories `name` goes through.
+pub fn program(name: &str, source: &str) -> PathBuf {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.pu", name));
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, source).unwrap();
+    path
+}
+
+/// `sample.pu` and every program in `tests/programs`.
+pub fn programs() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut programs = vec![root.join("sample.pu")];
+    for entry in fs::read_dir(root.join("tests/programs")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "pu") {
+            programs.push(path);
+        }
+    }
+    programs.sort();
+    programs
+}
+
+/// Runs `pulse` with `args`, however it ends.
+pub fn pulse<S: AsRef<OsStr>>(args: &[S]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pulse"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// What a successful command printed; `what` says what failed otherwise.
+pub fn stdout(output: Output, what: &str) -> String {
+    assert!(
+        output.status.success(),
+        "{} failed:\n{}",
+        what,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// What `program` prints when it is run with `flags`.
+pub fn interpret(program: &Path, flags: &[&str]) -> String {
+    let name = program.file_stem().unwrap().to_string_lossy();
+    let mut args = vec![program.as_os_str()];
+    args.extend(flags.iter().map(OsStr::new));
+    stdout(pulse(&args), &format!("interpreting {}", name))
+}
+
+/// What `program` prints, after checking the VM prints the same.
+pub fn both(program: &Path) -> String {
+    let expected = interpret(program, &[]);
+    let name = program.file_stem().unwrap().to_string_lossy();
+    assert_eq!(
+        interpret(program, &["--vm"]),
+        expected,
+        "the VM disagrees on {}",
+        name
+    );
+    expected
+}
+
+/// `text` without the colour codes error reports are printed with.
+pub fn plain(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
@@ -0,0 +1,168 @@
+// Exceptions and error reports, which only the interpreter has.
+
+mod common;
+
+use common::{interpret, plain, program};
+
+#[test]
+fn thrown_values_are_wrapped() {
+    let program = program(
+        "errors/thrown",
+        r#"fn attempt(v) {
+    try {
+        throw v
+    } catch (e) {
+        print(e.kind, e.message, e.value, e.stack.len())
+    }
+}
+attempt("plain")
+attempt(42)
+attempt({message: "custom", kind: "ValueError", code: 7})
+attempt({code: 7})
+"#,
+    );
+    // An object's own `message` and `kind` are used, anything else is
+    // shown as the message
+    assert_eq!(
+        interpret(&program, &[]),
+        r#"Error plain plain 2
+Error 42 42 2
+ValueError custom {"message": "custom", "kind": "ValueError", "code": 7} 2
+Error {"code": 7} {"code": 7} 2
+"#
+    );
+}
+
+#[test]
+fn runtime_errors_carry_their_stack() {
+    let program = program(
+        "errors/stack",
+        "fn inner() = 1 / 0;
+fn outer() = inner() + 1;
+try {
+    outer()
+} catch (e) {
+    print(e.kind, e.message, e.value)
+    print(e.stack)
+}
+",
+    );
+    let name = program.display();
+    assert_eq!(
+        interpret(&program, &[]),
+        format!(
+            r#"ZeroDivisionError Division by zero undefined
+["{name}:1:14", "{name}:2:14 in call to inner", "{name}:4:5 in call to outer"]
+"#
+        )
+    );
+}
+
+#[test]
+fn finally_runs_last_and_its_errors_win() {
+    let program = program(
+        "errors/finally",
+        r#"let order = []
+fn steps() {
+    try {
+        order.push("try")
+        throw "oops"
+    } catch (e) {
+        order.push("catch " + e.message)
+        return "returned"
+    } finally {
+        order.push("finally")
+    }
+}
+print(steps(), order)
+
+try {
+    try {
+        throw "first"
+    } finally {
+        throw "second"
+    }
+} catch (e) {
+    print(e.message)
+}
+
+fn overruled() {
+    try {
+        return "body"
+    } finally {
+        return "finally"
+    }
+}
+print(overruled())
+
+try {
+    try {
+        throw "inner"
+    } catch (e) {
+        throw e.message + " again"
+    }
+} catch (e) {
+    print(e.message)
+}
+"#,
+    );
+    assert_eq!(
+        interpret(&program, &[]),
+        r#"returned ["try", "catch oops", "finally"]
+second
+finally
+inner again
+"#
+    );
+}
+
+#[test]
+fn uncaught_errors_show_their_kind() {
+    let program = program(
+        "errors/uncaught",
+        "throw {message: \"empty age\", kind: \"ValueError\"}\nprint(\"after\")\n",
+    );
+    let out = plain(&interpret(&program, &[]));
+    assert!(out.contains("Uncaught ValueError: empty age"), "{}", out);
+    assert!(out.ends_with("after\n"), "{}", out);
+}
+
+#[test]
+fn reports_label_each_call_folding_recursion() {
+    let program = program(
+        "errors/trace",
+        "fn boom(x) = x / 0;
+fn down(n) {
+    if n == 0 {
+        return boom(1) + 1
+    }
+    return down(n - 1) + 1
+}
+fn start() = down(3) + 1;
+print(start() + 1)
+",
+    );
+    let out = plain(&interpret(&program, &[]));
+    assert!(
+        out.contains(&format!("[ {}:1:14 ]", program.display())),
+        "{}",
+        out
+    );
+    // Innermost first, with the three recursive calls from line 6 as one
+    // label and the first call into `down` on its own
+    let labels = [
+        "Division by zero",
+        "in call to boom",
+        "in call to down (3 times)",
+        "in call to down\n",
+        "in call to start",
+    ];
+    let mut rest = out.as_str();
+    for label in labels {
+        let at = rest
+            .find(label)
+            .unwrap_or_else(|| panic!("missing {:?} in order in\n{}", label, out));
+        rest = &rest[at + label.len()..];
+    }
+    assert_eq!(out.matches("in call to").count(), 4, "{}", out);
+}
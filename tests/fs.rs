@@ -0,0 +1,113 @@
+// Runs programs using `fs` in a scratch directory of their own, on both
+// engines, except where they catch errors, which only the interpreter can.
+
+mod common;
+
+use common::{both, interpret, program};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// An empty directory called `name`, as a path to put in a program
+fn scratch(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("fs").join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn files_round_trip() {
+    let dir = scratch("round_trip");
+    let program = program(
+        "fs/round_trip",
+        &format!(
+            r#"let dir = "{}"
+fs.write(dir + "/notes.txt", "one
+")
+fs.append(dir + "/notes.txt", "two
+")
+fs.append(dir + "/new.txt", "created")
+print(fs.read(dir + "/notes.txt") == "one
+two
+", fs.read(dir + "/new.txt"))
+fs.write(dir + "/notes.txt", "replaced")
+print(fs.read(dir + "/notes.txt"), fs.exists(dir + "/new.txt"))
+fs.remove(dir + "/new.txt")
+print(fs.exists(dir + "/new.txt"))
+"#,
+            dir.display()
+        ),
+    );
+    assert_eq!(both(&program), "true created\nreplaced true\nfalse\n");
+}
+
+#[test]
+fn directories_list_sorted() {
+    let dir = scratch("list_dir");
+    let program = program(
+        "fs/list_dir",
+        &format!(
+            r#"let dir = "{}"
+fs.write(dir + "/b.txt", "")
+fs.mkdir(dir + "/c/deeper")
+fs.write(dir + "/a.txt", "")
+print(fs.list_dir(dir), fs.list_dir(dir + "/c"))
+fs.remove(dir + "/c/deeper")
+print(fs.list_dir(dir + "/c"))
+"#,
+            dir.display()
+        ),
+    );
+    assert_eq!(
+        both(&program),
+        "[\"a.txt\", \"b.txt\", \"c\"] [\"deeper\"]\n[]\n"
+    );
+}
+
+#[test]
+fn each_line_stops_when_told() {
+    let dir = scratch("each_line");
+    fs::write(dir.join("lines.txt"), "alpha\nbeta\ngamma\ndelta\n").unwrap();
+    let program = program(
+        "fs/each_line",
+        &format!(
+            r#"let path = "{}/lines.txt"
+let seen = []
+fn upto(line, i) {{
+    seen.push(i + ":" + line)
+    return line != "beta"
+}}
+fn every(line, i) = seen.push(line);
+print(fs.each_line(path, upto), seen)
+print(fs.each_line(path, every), seen.len())
+"#,
+            dir.display()
+        ),
+    );
+    // The line the function stops on is counted
+    assert_eq!(both(&program), "2 [\"0:alpha\", \"1:beta\"]\n4 6\n");
+}
+
+#[test]
+fn missing_files_raise_io_errors() {
+    let dir = scratch("missing");
+    let program = program(
+        "fs/missing",
+        &format!(
+            r#"try {{
+    fs.read("{}/missing.txt")
+}} catch (e) {{
+    print(e.kind, e.message)
+}}
+"#,
+            dir.display()
+        ),
+    );
+    // The rest of the message is the operating system's
+    let out = interpret(&program, &[]);
+    let expected = format!(
+        "IOError fs.read: cannot read '{}/missing.txt': ",
+        dir.display()
+    );
+    assert!(out.starts_with(&expected), "{}", out);
+}
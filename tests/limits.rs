@@ -0,0 +1,147 @@
+// Runs programs that recurse deeply. Tail calls have to run in constant
+// stack, and other recursion that goes too deep has to end in a
+// `StackOverflowError` the interpreter carries on from, rather than the
+// process dying on a native stack overflow.
+
+mod common;
+
+use common::{both, interpret, program};
+
+const FACTORIAL: &str = "fn factorial(n) {
+  if n < 2 {
+    1
+  } else {
+    n * factorial(n - 1)
+  }
+}
+";
+
+#[test]
+fn call_limit_stops_deep_recursion() {
+    let program = program(
+        "calls",
+        &format!(
+            "{}print(factorial(100000))\nprint(\"still running\")\n",
+            FACTORIAL
+        ),
+    );
+    let out = interpret(&program, &[]);
+    assert!(
+        out.contains("Stack overflow: more than 1000 nested calls."),
+        "{}",
+        out
+    );
+    assert!(out.contains("still running"), "{}", out);
+}
+
+#[test]
+fn depth_limit_outlasts_a_raised_call_limit() {
+    let program = program(
+        "depth",
+        &format!(
+            "{}print(factorial(100000))\nprint(\"still running\")\n",
+            FACTORIAL
+        ),
+    );
+    let out = interpret(&program, &["--recursion-limit", "1000000"]);
+    assert!(
+        out.contains("Stack overflow: expressions nested"),
+        "{}",
+        out
+    );
+    assert!(out.contains("still running"), "{}", out);
+}
+
+#[test]
+fn stack_overflow_can_be_caught() {
+    let program = program(
+        "caught",
+        &format!(
+            "{}try {{
+  factorial(100000)
+}} catch (e) {{
+  print(e.kind)
+}}
+print(factorial(5))
+",
+            FACTORIAL
+        ),
+    );
+    let out = interpret(&program, &["--recursion-limit", "1000000"]);
+    assert_eq!(out, "StackOverflowError\n120\n");
+}
+
+#[test]
+fn tail_recursion_runs_in_constant_stack() {
+    let program = program(
+        "countdown",
+        "fn countdown(n) {
+  if n == 0 {
+    \"liftoff\"
+  } else {
+    return countdown(n - 1)
+  }
+}
+print(countdown(1000000))
+",
+    );
+    assert_eq!(both(&program), "liftoff\n");
+}
+
+#[test]
+fn mutual_tail_recursion_runs_in_constant_stack() {
+    let program = program(
+        "parity",
+        "fn is_even(n) {
+  if n == 0 {
+    true
+  } else {
+    return is_odd(n - 1)
+  }
+}
+fn is_odd(n) {
+  if n == 0 {
+    false
+  } else {
+    return is_even(n - 1)
+  }
+}
+print(is_even(100001), is_odd(100001))
+",
+    );
+    assert_eq!(both(&program), "false true\n");
+}
+
+#[test]
+fn call_limit_applies_to_the_vm() {
+    let program = program(
+        "vm-calls",
+        "fn forever(n) = 1 + forever(n + 1);
+forever(0);
+print(\"still running\");
+fn nested(n) {
+  fn step(x) = nested(x - 1);
+  if n == 0 {
+    0
+  } else {
+    [n].map(step)[0]
+  }
+}
+nested(100000);
+",
+    );
+    let out = interpret(&program, &["--vm"]);
+    assert!(
+        out.contains("Stack overflow: more than 1000 nested calls."),
+        "{}",
+        out
+    );
+    assert!(out.contains("still running"), "{}", out);
+
+    let out = interpret(&program, &["--vm", "--recursion-limit", "50"]);
+    assert!(
+        out.contains("Stack overflow: more than 50 nested calls."),
+        "{}",
+        out
+    );
+}
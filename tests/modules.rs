@@ -0,0 +1,57 @@
+// Runs the programs in `tests/modules`, which import each other. Paths in
+// error reports are the ones the programs were run with, relative to the
+// crate root that `cargo test` runs in.
+
+mod common;
+
+use common::{interpret, plain};
+use std::path::Path;
+
+fn run(name: &str) -> String {
+    let program = Path::new("tests/modules").join(format!("{}.pu", name));
+    plain(&interpret(&program, &[]))
+}
+
+#[test]
+fn modules_run_once_relative_to_their_importer() {
+    // `main` imports `counter` twice and `nested/uses_counter` once more,
+    // as `../counter.pu`
+    assert_eq!(run("main"), "counter runs\n1 2 2\n");
+}
+
+#[test]
+fn cycles_are_reported_with_their_chain() {
+    let out = run("cycle_a");
+    assert!(
+        out.contains(
+            "Import cycle: tests/modules/cycle_a.pu -> tests/modules/cycle_b.pu -> tests/modules/cycle_a.pu."
+        ),
+        "{}",
+        out
+    );
+    assert!(out.contains("[ tests/modules/cycle_b.pu:1:1 ]"), "{}", out);
+}
+
+#[test]
+fn missing_exports_are_reported() {
+    let out = run("missing_export");
+    assert!(
+        out.contains("Module './counter.pu' has no export 'nope'."),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("[ tests/modules/missing_export.pu:1:1 ]"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn errors_name_the_module_they_are_raised_in() {
+    let out = run("imports_broken");
+    assert!(out.contains("Division not supported: Str ÷ Int"), "{}", out);
+    assert!(out.contains("[ tests/modules/broken.pu:2:14 ]"), "{}", out);
+    // The failed import stops nothing but itself
+    assert!(out.ends_with("after\n"), "{}", out);
+}
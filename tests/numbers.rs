@@ -0,0 +1,89 @@
+// Runs programs using `BigInt`s and `Decimal`s, which `pulse build` does not
+// support, so these check the interpreter and the VM against each other
+// rather than against the compiled targets.
+
+mod common;
+
+use common::{both, interpret, plain, program, pulse};
+
+#[test]
+fn int_overflow_becomes_bigint() {
+    let program = program(
+        "bigint",
+        "let max = 9223372036854775807
+print(max + 1, -max - 2, max * max)
+print(max + 1 - 1 == max, 2 ** 100, (2 ** 64) ~/ -3, (2 ** 64) % 7)
+print(123456789012345678901234567890, 0xffffffffffffffffff)
+print(math.abs(-9223372036854775808), math.sqrt(2 ** 64))
+",
+    );
+    assert_eq!(
+        both(&program),
+        "9223372036854775808 -9223372036854775809 85070591730234615847396907784232501249
+true 1267650600228229401496703205376 -6148914691236517206 2
+123456789012345678901234567890 4722366482869645213695
+9223372036854775808 4294967296
+"
+    );
+}
+
+#[test]
+fn decimals_are_exact() {
+    let program = program(
+        "decimal",
+        "print(1.10d, 0.1d + 0.2d, 0.1d + 0.2d == 0.3d, 0.1 + 0.2 == 0.3)
+print(1.10d * 3, 1d / 4d, -7d ~/ 2d, 7.5d % 2d, 1.5d ** 2, 2d ** -1)
+print(2 ** 64 + 1.5d, 1.5d + 0.25, 1.5d == 1.5, 2 ** 64 + 1 > 18446744073709551616.0)
+",
+    );
+    assert_eq!(
+        both(&program),
+        "1.10 0.3 true false
+3.30 0.25 -4 1.5 2.25 0.5
+18446744073709551617.5 1.75 true true
+"
+    );
+}
+
+#[test]
+fn huge_powers_are_refused() {
+    let program = program(
+        "powers",
+        "print(1 ** 4000000000, (-1) ** 4000000001, 0 ** 0, 2 ** 63)
+print(3 ** 4000000000)
+print(1.5d ** 4000000)
+",
+    );
+    let out = interpret(&program, &[]);
+    assert_eq!(
+        out.lines().next(),
+        Some("1 -1 1 9223372036854775808"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("Exponent too large: 3 ** 4000000000"),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("Exponent too large: 1.5 ** 4000000"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn huge_exponent_literals_are_refused() {
+    let program = program("literal", "print(1e300000 > 1)\nprint(1e200000000 > 1)\n");
+    let output = pulse(&[&program]);
+    let out = plain(&String::from_utf8(output.stdout).unwrap());
+    // Reported before anything runs, at the literal
+    assert!(!output.status.success(), "{}", out);
+    assert!(out.starts_with("Error: Exponent too large."), "{}", out);
+    assert!(
+        out.contains(&format!("[ {}:2:7 ]", program.display())),
+        "{}",
+        out
+    );
+}
@@ -0,0 +1,72 @@
+// Lists and objects: how they print, and, since they are shared references
+// that can end up holding themselves, that comparing, typing and printing
+// such a value comes back out.
+
+mod common;
+
+use common::{both, interpret, program};
+use std::path::Path;
+
+#[test]
+fn objects_print_in_insertion_order() {
+    let objects = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs/objects.pu");
+    let out = both(&objects);
+    let last: Vec<_> = out.lines().rev().take(2).collect();
+    assert_eq!(
+        last,
+        [
+            r#"{"x": 1, "y": 2, "lang": "pulse", "named": true, 2: "two"} [{"inner": {"deep": "yes"}}, [1, "two"], [], "back\slash"]"#,
+            r#"{"a": 1, "b": 20, "c": 3, "d": 4} {"b": 20, "d": 4} {"b": 20, "d": 4, "a": 5}"#,
+        ]
+    );
+}
+
+#[test]
+fn self_containing_values_compare() {
+    let program = program(
+        "cycles",
+        "let xs = []
+xs.push(xs)
+let ys = []
+ys.push(ys)
+let o = {}
+o.me = o
+print(xs == xs, xs == ys, o == o, [xs] == [ys], xs == [1])
+",
+    );
+    assert_eq!(both(&program), "true true true true false\n");
+}
+
+#[test]
+fn self_containing_values_are_typed() {
+    let program = program(
+        "cycle_types",
+        "let xs = []
+xs.push(xs)
+let y: num = xs
+",
+    );
+    let out = interpret(&program, &[]);
+    assert!(
+        out.contains("'y' is declared as num, found list<list<any>>."),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn self_containing_values_print() {
+    let program = program(
+        "cycle_print",
+        "let o = {z: 1, a: 2}
+o.self = o
+let ys = [o, 0]
+ys[1] = ys
+print(o, ys)
+",
+    );
+    assert_eq!(
+        both(&program),
+        "{\"z\": 1, \"a\": 2, \"self\": {...}} [{\"z\": 1, \"a\": 2, \"self\": {...}}, [...]]\n"
+    );
+}
@@ -0,0 +1,216 @@
+// Runs programs using the standard library and the built-in methods, which
+// `pulse build` does not support, so these check the interpreter and the VM
+// against each other rather than against the compiled targets.
+
+mod common;
+
+use common::{both, interpret, plain, program};
+
+#[test]
+fn readme_example() {
+    let program = program(
+        "readme",
+        r#"let name = "wörld".upper()
+print("hello, {}!".format(name), name.len())
+print("a,b,c".split(",").join(" | "))
+
+fn square(x) = x * x;
+fn add(a, b) = a + b;
+print(range(1, 5).map(square).reduce(add))
+print(range(1, 5).len(), range(3)[2])
+"#,
+    );
+    assert_eq!(
+        both(&program),
+        "hello, WÖRLD! 5
+a | b | c
+30
+4 2
+"
+    );
+}
+
+#[test]
+fn math_functions() {
+    let program = program(
+        "math",
+        "print(math.floor(2.7), math.ceil(2.1), math.round(2.5), math.round(-2.5), math.trunc(-2.7))
+print(math.abs(-3), math.abs(-3.5), math.sign(-4), math.sign(0.5), math.min(3, 1.5, 2), math.max(3, 7, 5))
+print(math.clamp(12, 0, 10), math.clamp(-1, 0, 10), math.gcd(12, 18), math.is_int(4.0), math.is_int(4.5))
+print(math.sqrt(16), math.cbrt(27), math.pow(2, 10), math.log(1000), math.log(8, 2), math.log2(8), math.ln(1))
+print(math.hypot(3, 4), math.atan2(0, -1) == math.pi, math.sin(0), math.cos(0), math.exp(0))
+print(math.pi, math.e, math.inf, -math.inf, math.nan == math.nan)
+",
+    );
+    // Rounding goes away from zero, and integer arguments stay integers
+    assert_eq!(
+        both(&program),
+        "2 3 3 -3 -2
+3 3.5 -1 1 1.5 7
+10 0 6 true false
+4 3 1024 3 3 3 0
+5 true 0 1 1
+3.141592653589793 2.718281828459045 inf -inf false
+"
+    );
+}
+
+#[test]
+fn math_argument_errors_name_the_type_found() {
+    let program = program(
+        "math_errors",
+        "print(math.sqrt(\"16\"))
+print(math.max())
+print(math.clamp(1, 2))
+print(math.gcd(1.5, 2))
+try {
+    math.abs([1])
+} catch (e) {
+    print(e.kind, e.message)
+}
+",
+    );
+    let out = plain(&interpret(&program, &[]));
+    for msg in [
+        "math.sqrt expects a Num as argument 1, found Str.",
+        "math.max expects at least one argument.",
+        "math.clamp expects a Num as argument 3, found Undefined.",
+        "math.gcd expects integers, found 1.5.",
+    ] {
+        assert!(out.contains(msg), "missing {:?} in\n{}", msg, out);
+    }
+    assert!(
+        out.ends_with("TypeError math.abs expects a Num as argument 1, found Vec.\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn range_refuses_bounds_that_are_not_finite() {
+    let program = program(
+        "range_errors",
+        "print(range(math.inf))
+print(range(0, math.inf, 1))
+print(range(-math.inf, 0))
+print(range(0, 1, math.nan))
+try {
+    range(0, 1, -math.inf)
+} catch (e) {
+    print(e.kind, e.message)
+}
+print(range(0, 1, 0.5))
+",
+    );
+    let out = plain(&interpret(&program, &[]));
+    for msg in [
+        "range expects finite numbers, found inf.",
+        "range expects finite numbers, found -inf.",
+        "range expects finite numbers, found NaN.",
+    ] {
+        assert!(out.contains(msg), "missing {:?} in\n{}", msg, out);
+    }
+    assert!(
+        out.ends_with("TypeError range expects finite numbers, found -inf.\n[0, 0.5]\n"),
+        "{}",
+        out
+    );
+}
+
+#[test]
+fn string_methods_count_characters() {
+    let program = program(
+        "strings",
+        r#"let s = "héllo wörld"
+print(s.len(), s.slice(1, 4), s.slice(-5), s.find("ö"), s.find("zz"), s[1], s.chars().len())
+print(" x ".trim(), "a-b-c".replace("-", "+"), "abc".contains("b"), "abc".starts_with("ab"), "abc".ends_with("bc"))
+print("Straße".upper(), "ÄB".lower(), "a,b,,c".split(","), "a  b".split())
+print("7".pad_left(3, "0"), "ab".pad_right(4, "."), "wörld".pad_left(7), "|")
+print("{} + {} = {}".format(1, 2, 3), "{1} {0} {1}".format("a", "b"), "{{}} {}".format("x"))
+"#,
+    );
+    assert_eq!(
+        both(&program),
+        r#"11 éll wörld 7 undefined é 11
+x a+b+c true true true
+STRASSE äb ["a", "b", "", "c"] ["a", "b"]
+007 ab..   wörld |
+1 + 2 = 3 b a b {} x
+"#
+    );
+}
+
+#[test]
+fn string_method_errors() {
+    let program = program(
+        "string_errors",
+        r#"print("{} {}".format(1))
+print("{".format())
+print("{x}".format(1))
+print("ab".pad_left(4, "xy"))
+print("ab".slice("1"))
+"#,
+    );
+    let out = plain(&interpret(&program, &[]));
+    for msg in [
+        "str.format: no argument 1 to format.",
+        "str.format: unclosed '{' in template.",
+        "str.format: invalid placeholder '{x}'.",
+        "str.pad_left pads with a single character, found 'xy'.",
+        "str.slice expects a Num as argument 1, found Str.",
+    ] {
+        assert!(out.contains(msg), "missing {:?} in\n{}", msg, out);
+    }
+}
+
+#[test]
+fn object_functions_keep_insertion_order() {
+    let program = program(
+        "objects",
+        r#"let o = {b: 1, a: 2}
+o.c = 3
+o.b = 10
+print(object.keys(o), object.values(o), object.entries(o))
+print(object.delete(o, "b"), object.keys(o), object.delete(o, "zz"))
+o.b = 4
+print(object.keys(o), object.has(o, "a"), object.has(o, "zz"), object.get(o, "zz", 0), object.get(o, "a", 0))
+object.set(o, "keys", "shadow")
+print(object.keys(o).len(), o.keys)
+print(object.from_entries([["y", 1], ["x", 2], [3, "three"], ["y", 5]]))
+fn label(v, k) = k + "=" + v;
+print(object.map_values({p: 1, q: 2}, label))
+"#,
+    );
+    // Changing a key's value keeps its place; deleting and adding it again
+    // moves it to the end
+    assert_eq!(
+        both(&program),
+        r#"["b", "a", "c"] [10, 2, 3] [["b", 10], ["a", 2], ["c", 3]]
+10 ["a", "c"] undefined
+["a", "c", "b"] true false 0 2
+4 shadow
+{"y": 5, "x": 2, 3: "three"}
+{"p": "p=1", "q": "q=2"}
+"#
+    );
+}
+
+#[test]
+fn object_function_errors() {
+    let program = program(
+        "object_errors",
+        r#"let frozen = freeze({a: 1})
+object.set(frozen, "b", 2)
+print(object.from_entries([1]))
+print(object.keys(1))
+"#,
+    );
+    let out = plain(&interpret(&program, &[]));
+    for msg in [
+        "object.set: cannot change a frozen object.",
+        "object.from_entries expects [key, value] pairs, found Int at 0.",
+        "object.keys expects an Obj as argument 1, found Int.",
+    ] {
+        assert!(out.contains(msg), "missing {:?} in\n{}", msg, out);
+    }
+}
@@ -0,0 +1,104 @@
+// Runs every sample program through the interpreter and through each
+// `pulse build` target, and checks both print the same thing. The generated
+// crates are built offline with the toolchain running the tests; the JS
+// output is run with `node`, and skipped where there is none.
+
+mod common;
+
+use common::{interpret, program, programs, pulse, stdout};
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn build(target: &str, program: &Path, out: &Path) {
+    let name = program.file_stem().unwrap().to_string_lossy();
+    stdout(
+        Command::new(env!("CARGO_BIN_EXE_pulse"))
+            .args(["build", "--target", target])
+            .arg(program)
+            .arg("-o")
+            .arg(out)
+            .output()
+            .unwrap(),
+        &format!("transpiling {} to {}", name, target),
+    );
+}
+
+#[test]
+fn rust_target_matches_interpreter() {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let work = Path::new(env!("CARGO_TARGET_TMPDIR")).join("rust-target");
+    // One target directory for all programs so the runtime builds once
+    let target_dir = work.join("target");
+
+    for program in programs() {
+        let name = program.file_stem().unwrap().to_string_lossy().to_string();
+        let expected = interpret(&program, &[]);
+        let out = work.join(&name);
+        build("rust", &program, &out);
+        let actual = stdout(
+            Command::new(&cargo)
+                .args(["run", "--offline", "--quiet", "--manifest-path"])
+                .arg(out.join("Cargo.toml"))
+                .env("CARGO_TARGET_DIR", &target_dir)
+                .output()
+                .unwrap(),
+            &format!("building and running {}", name),
+        );
+
+        assert_eq!(actual, expected, "output of {} differs", name);
+    }
+}
+
+#[test]
+fn js_target_matches_interpreter() {
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!("node not found, skipping");
+        return;
+    }
+    let work = Path::new(env!("CARGO_TARGET_TMPDIR")).join("js-target");
+
+    for program in programs() {
+        let name = program.file_stem().unwrap().to_string_lossy().to_string();
+        let expected = interpret(&program, &[]);
+        build("js", &program, &work);
+        let actual = stdout(
+            Command::new("node")
+                .arg(work.join(format!("{}.mjs", name)))
+                .output()
+                .unwrap(),
+            &format!("running {}", name),
+        );
+
+        assert_eq!(actual, expected, "output of {} differs", name);
+    }
+}
+
+#[test]
+fn methods_are_refused() {
+    let program = program(
+        "methods",
+        "let s = \"abc\"\nprint(s.upper(), [1, 2].push(3))\n",
+    );
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("methods-build");
+    let output = pulse(&[
+        "build".as_ref(),
+        "--target".as_ref(),
+        "js".as_ref(),
+        program.as_os_str(),
+        "-o".as_ref(),
+        out.as_os_str(),
+    ]);
+    let printed = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{}", printed);
+    assert!(
+        printed.contains("The method 'upper' is not supported by `pulse build`."),
+        "{}",
+        printed
+    );
+    assert!(
+        printed.contains("The method 'push' is not supported by `pulse build`."),
+        "{}",
+        printed
+    );
+}
@@ -0,0 +1,13 @@
+// Runs every sample program through the tree-walker and through the
+// bytecode VM, and checks both print the same thing.
+
+mod common;
+
+use common::{both, programs};
+
+#[test]
+fn vm_matches_interpreter() {
+    for program in programs() {
+        both(&program);
+    }
+}